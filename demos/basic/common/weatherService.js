@@ -1,3 +1,5 @@
+requires(randomService.js);
+
 const weatherService = {
     WeatherForecast: class {
         constructor(data={}) {
@@ -1,4 +1,72 @@
+// Orders the runtime files by their requires() statements and writes them into a Rust file that is included by runtime.rs,
+// so the runtime doesn't need to be assembled on every compile.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+#[path = "src/dependency_order.rs"]
+mod dependency_order;
+
+const RUNTIME_DIR: &str = "runtime";
+
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
-    println!("cargo:rerun-if-changed=runtime");
+    println!("cargo:rerun-if-changed=src/dependency_order.rs");
+    println!("cargo:rerun-if-changed={RUNTIME_DIR}");
+
+    // Read every js file in the runtime dir
+    let mut runtime_files = vec![];
+    for entry in fs::read_dir(RUNTIME_DIR).expect("Could not read runtime directory") {
+        let path = entry.expect("Could not read runtime directory").path();
+        if path.extension().is_some_and(|e| e == "js") {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let content = fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Could not read {}: {e}", path.display()));
+            runtime_files.push((name, content));
+        }
+    }
+
+    let dependencies: Vec<(String, Vec<String>)> = runtime_files
+        .iter()
+        .map(|(name, content)| {
+            (
+                name.clone(),
+                dependency_order::parse_requires_statement(content),
+            )
+        })
+        .collect();
+    let order = dependency_order::order_by_dependencies(&dependencies)
+        .unwrap_or_else(|e| panic!("Could not bundle the runtime. {e}"));
+
+    // Write them out in order as a list of RuntimeFiles, along with the classes that they declare so that they can be exported from a module
+    let mut generated = String::from("// Generated by build.rs from the files in runtime/\n");
+    generated += "const RUNTIME_FILES: &[RuntimeFile] = &[\n";
+    for index in order {
+        let (name, requires) = &dependencies[index];
+        let content = &runtime_files[index].1;
+        generated += &format!(
+            "    RuntimeFile {{ name: {name:?}, requires: &{requires:?}, classes: &{:?}, content: {:?} }},\n",
+            find_class_names(content),
+            dependency_order::remove_requires_statement(content)
+        );
+    }
+    generated += "];\n";
+
+    let out_path = Path::new(&env::var("OUT_DIR").unwrap()).join("runtime_files.rs");
+    fs::write(out_path, generated).expect("Could not write runtime files");
+}
+
+fn find_class_names(file_content: &str) -> Vec<String> {
+    // Names of the top level classes in a runtime file (they're never indented)
+
+    file_content
+        .lines()
+        .filter_map(|line| line.strip_prefix("class "))
+        .map(|rest| {
+            rest.chars()
+                .take_while(|c| c.is_alphanumeric() || *c == '_')
+                .collect()
+        })
+        .collect()
 }
@@ -1,4 +1,4 @@
-requires(SpallRenderer.js, SpallRouter.js);
+requires(SpallRenderer.js);
 
 // Root-most class of the Spall runtime
 
@@ -10,6 +10,8 @@ class SpallApp {
                 appContainer: <html element>, (ignored if renderer is provided)
                 renderer: <SpallRenderer>,
                 router: <SpallRouter>,
+                rootElement: <compiled root element class>, (defaults to the global __SpallCompiledRoot)
+                routes: <list of [route sections, page class]>, (defaults to SpallRouter.routeList)
                 disableAutoRun: bool, (if is true, will not render immediately after construction)
             } 
         */
@@ -21,6 +23,13 @@ class SpallApp {
             this.appContainer = document.body;
         }
 
+        if (Object.hasOwn(options, "rootElement")) {
+            this.rootElement = options.rootElement;
+        }
+        else {
+            this.rootElement = __SpallCompiledRoot;
+        }
+
         if (Object.hasOwn(options, "renderer")) {
             this.renderer = renderer;
             this.renderer.attachSpallApp(this);
@@ -33,8 +42,12 @@ class SpallApp {
             this.router = router;
             this.router.attachSpallApp(this);
         }
+        else if (typeof SpallRouter != "undefined") {
+            this.router = new SpallRouter(this, options.routes);
+        }
         else {
-            this.router = new SpallRouter(this);
+            // The router is left out of the runtime when the app has no pages
+            this.router = null;
         }
 
         this.running = false;
@@ -46,7 +59,7 @@ class SpallApp {
 
     run() {
         this.running = true;
-        this.router.autoDetectRoute();
+        if (this.router != null) this.router.autoDetectRoute();
         this.renderer.renderPage();
     }
 }
\ No newline at end of file
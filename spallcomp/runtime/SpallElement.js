@@ -1,3 +1,5 @@
+requires(SpallRenderable.js, SpallUtils.js);
+
 class SpallElement {
     // Represents an element that's actually on the page and has a state and such. Is extended by compiled files.
     constructor(elementName, id, parentId, spallApp, path) {
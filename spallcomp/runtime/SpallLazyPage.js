@@ -0,0 +1,63 @@
+requires(SpallUtils.js);
+
+class SpallLazyPage {
+    // Stands in for a page class in the route list when the pages are split into chunks.
+    // The chunks are loaded the first time the page is visited, then pageClass is set to the real class.
+    // Chunks that are scripts add their page to SpallLazyPage.loadedPages, while modules export it.
+
+    constructor(className, chunkUrls, isModule=false) {
+        this.className = className;
+        this.chunkUrls = chunkUrls;
+        this.isModule = isModule;
+        this.pageClass = null;
+        this.failed = false;
+        this._loading = null;
+    }
+
+    // Returns a promise that resolves when the page has loaded (or failed to). The chunks are only fetched once
+    load() {
+        if (this._loading == null) {
+            this._loading = this._loadChunks()
+                .then(() => {
+                    this.pageClass = SpallLazyPage.loadedPages[this.className];
+                    if (this.pageClass == undefined) throw new Error(`${this.className} was not in its chunk`);
+                })
+                .catch(e => {
+                    this.failed = true;
+                    SpallUtils.fatalError(`Could not load page chunk:\n${SpallUtils.indentText(e.toString(), SpallUtils.errorIndent)}`);
+                });
+        }
+        return this._loading;
+    }
+
+    async _loadChunks() {
+        // Chunks are loaded one after another since the page chunk uses what the shared chunks before it define
+        for (var url of this.chunkUrls) {
+            if (this.isModule) {
+                var module = await import(url);
+                if (this.className in module) SpallLazyPage.loadedPages[this.className] = module[this.className];
+            }
+            else {
+                await SpallLazyPage._loadScript(url);
+            }
+        }
+    }
+
+    static _loadScript(url) {
+        // Shared chunks are used by several pages, but running one twice would declare its classes twice
+        if (!(url in SpallLazyPage._loadedScripts)) {
+            SpallLazyPage._loadedScripts[url] = new Promise((resolve, reject) => {
+                var script = document.createElement("script");
+                script.src = url;
+                script.onload = resolve;
+                script.onerror = () => reject(new Error(`Could not fetch ${url}`));
+                document.head.appendChild(script);
+            });
+        }
+        return SpallLazyPage._loadedScripts[url];
+    }
+}
+
+// Page classes from the chunks that have been loaded, by class name
+SpallLazyPage.loadedPages = {};
+SpallLazyPage._loadedScripts = {};
@@ -1,4 +1,4 @@
-requires(SpallElement.js);
+requires(SpallElement.js, SpallRouter.js);
 
 class SpallPage extends SpallElement {
     constructor(elementName, id, parentId, spallApp, path) {
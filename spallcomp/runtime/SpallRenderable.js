@@ -17,4 +17,27 @@ class SpallElementRenderable extends SpallRenderable {
         this.relativePath = relativePath;
         this.parameters = parameters; // (dictionary of var name to function that can produce the value)
     }
-}
\ No newline at end of file
+}
+
+class SpallMarkup {
+    // Values interpolated into markup are passed through these by the compiled elements, unless they're wrapped in raw()
+
+    static escapeHtml(value) {
+        return String(value)
+            .replace(/&/g, '&amp;')
+            .replace(/</g, '&lt;')
+            .replace(/>/g, '&gt;');
+    }
+
+    static escapeAttribute(value) {
+        return SpallMarkup.escapeHtml(value)
+            .replace(/"/g, '&quot;')
+            .replace(/'/g, '&#39;');
+    }
+
+    // event handler attributes are run as javascript, so values go in as js literals rather than bare text
+    static escapeHandlerValue(value) {
+        const literal = JSON.stringify(value);
+        return SpallMarkup.escapeAttribute(literal === undefined ? 'undefined' : literal);
+    }
+}
@@ -1,4 +1,4 @@
-requires(SpallUtils.js);
+requires(SpallRenderLogger.js, SpallRenderable.js, SpallUtils.js);
 
 class SpallRenderer {
     constructor(spallApp=null, logger=new SpallMockRenderLogger()) {
@@ -21,7 +21,7 @@ class SpallRenderer {
     renderPage() {
         this._throwIfRendering();
         if (this.spallApp == null) this._fatalRendererError("SpallRenderer.spallApp was not provided");
-        var root = new __SpallCompiledRoot(this._lastUsedId, -1, this);
+        var root = new this.spallApp.rootElement(this._lastUsedId, -1, this);
         this._idToHtml[root.id] = this.spallApp.appContainer;
 
         this._registerElement(root, '');
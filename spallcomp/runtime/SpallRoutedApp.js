@@ -1,4 +1,4 @@
-requires(SpallElement.js);
+requires(SpallElement.js, SpallRenderable.js, SpallRouter.js);
 
 class __SpallCompiledRoutedApp extends SpallElement {
     // Defines the section of the app that is rendered by routing
@@ -28,6 +28,19 @@ class __SpallCompiledRoutedApp extends SpallElement {
         else {
             var [routeSections, elementClass] = matchingRouteData;
 
+            // Pages that are split into chunks have to be loaded the first time they're visited.
+            // SpallLazyPage is only in the runtime when pages are split
+            if (typeof SpallLazyPage != "undefined" && elementClass instanceof SpallLazyPage) {
+                if (elementClass.failed) {
+                    return [new SpallMarkupRenderable(__SpallCompiledRoutedApp.loadFailedMarkup)];
+                }
+                if (elementClass.pageClass == null) {
+                    elementClass.load().then(() => this.needsRender());
+                    return [new SpallMarkupRenderable(__SpallCompiledRoutedApp.loadingMarkup)];
+                }
+                elementClass = elementClass.pageClass;
+            }
+
             // Find what the values of the element properties should be based on the placeholders in the URL
             var properties = {};
             stringRouteSections.forEach((crntStringSection, i) => {
@@ -42,4 +55,8 @@ class __SpallCompiledRoutedApp extends SpallElement {
         }
 
     }
-}
\ No newline at end of file
+}
+
+// Shown in place of a page while its chunk is loading, or if it couldn't be loaded. Can be changed by the app
+__SpallCompiledRoutedApp.loadingMarkup = "<p>Loading...</p>";
+__SpallCompiledRoutedApp.loadFailedMarkup = "<p>This page could not be loaded</p>";
@@ -1,3 +1,5 @@
+requires(SpallUtils.js);
+
 class SpallRouter {
     // Handles switching between "pages" I guess.
     // Most of the work is done in the element, this just links everything together and adds a nice interface
@@ -7,10 +9,10 @@ class SpallRouter {
     // - Template routes (also called routeSections, distinct from stringRouteSections) are a list of SpallStringRouteSections or SpallPropertyRouteSections.
     //      They are linked to a page and can be determined whether it matches the route that the user wants to navigate to.
 
-    constructor(spallApp=null) {
+    constructor(spallApp=null, routeList=SpallRouter.routeList) {
         this.spallApp = spallApp;
 
-        this.routeList = SpallRouter.routeList;
+        this.routeList = routeList;
         this.crntRoute = ""; // empty route == homepage
         this.defaultTitle = ""; // title shown if page doesn't define a title
     }
@@ -9,6 +9,11 @@ pub struct Options {
     pub do_not_minify: bool,
     pub project_path: String,
     pub preserve_html_comments: bool,
+    pub release: bool,
+    pub es_modules: bool,
+    pub split_pages: bool,
+    pub warn_unmatched_selectors: bool,
+    pub global_stylesheet: String,
 }
 
 pub fn parse_args(args: &Vec<String>) -> Options {
@@ -21,6 +26,11 @@ pub fn parse_args(args: &Vec<String>) -> Options {
         do_not_minify: false,
         project_path: "".to_string(),
         preserve_html_comments: false,
+        release: false,
+        es_modules: false,
+        split_pages: false,
+        warn_unmatched_selectors: false,
+        global_stylesheet: "".to_string(),
     };
 
     // Set up argparser and use it
@@ -39,7 +49,7 @@ pub fn parse_args(args: &Vec<String>) -> Options {
         parser.refer(&mut options.do_not_minify).add_option(
             &["-l", "--large"],
             argparse::StoreTrue,
-            "Whether to disable minifying of the final bundle for debugging purposes",
+            "Whether to disable minifying of the final bundle and CSS for debugging purposes",
         );
         parser.refer(&mut options.project_path).add_argument(
             "project",
@@ -53,6 +63,33 @@ pub fn parse_args(args: &Vec<String>) -> Options {
                 argparse::StoreTrue,
                 "Preserve HTML comments in final markup",
             );
+        parser.refer(&mut options.release).add_option(
+            &["-r", "--release"],
+            argparse::StoreTrue,
+            "Build for release - output file names contain a hash of their content",
+        );
+        parser.refer(&mut options.es_modules).add_option(
+            &["-m", "--modules"],
+            argparse::StoreTrue,
+            "Output ES modules instead of scripts that define global classes",
+        );
+        parser.refer(&mut options.split_pages).add_option(
+            &["-s", "--split-pages"],
+            argparse::StoreTrue,
+            "Load the code for each page when it is first visited instead of all at once",
+        );
+        parser
+            .refer(&mut options.warn_unmatched_selectors)
+            .add_option(
+                &["-u", "--unmatched-selectors"],
+                argparse::StoreTrue,
+                "Warn about scoped CSS selectors that match no tag or class in their element",
+            );
+        parser.refer(&mut options.global_stylesheet).add_option(
+            &["-g", "--global-css"],
+            argparse::Store,
+            "Stylesheet to bundle as global CSS, relative to the project (default styles/global/index.css)",
+        );
         let result = parser.parse(args.clone(), &mut std::io::stdout(), &mut std::io::stderr());
         if let Err(err_code) = result {
             println!("");
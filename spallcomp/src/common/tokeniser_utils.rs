@@ -52,33 +52,154 @@ pub fn read_whitespace(data: &str) -> String {
     result
 }
 
-pub fn read_until_char(data: &str, char: char) -> (String, bool) {
-    // Read a string until a certain character is found.
-    // Second return value indicates if the character was found before EOF
-    // currently uses a comparitively slow implementation wrapping read_until_any_of
+/// Length in bytes of the CSS escape sequence that starts with the \\ at the start of `text`.
+/// Hex escapes like `\\31 ` are up to 6 hex digits and can end with a space that is part of the escape
+pub fn escape_length(text: &str) -> usize {
+    let mut chars = text.char_indices().skip(1);
+    match chars.next() {
+        None => 1,
+        Some((_, c)) if !c.is_ascii_hexdigit() => 1 + c.len_utf8(),
+        Some(_) => {
+            let hex_end = text[1..]
+                .char_indices()
+                .take(6)
+                .take_while(|(_, c)| c.is_ascii_hexdigit())
+                .last()
+                .map(|(idx, _)| 2 + idx)
+                .unwrap_or(1);
+            match text[hex_end..].chars().next() {
+                Some(c) if c.is_whitespace() => hex_end + c.len_utf8(),
+                _ => hex_end,
+            }
+        }
+    }
+}
 
-    let (string, found_char) = read_until_any_of(data, &vec![char]);
-    (string, found_char.is_some())
+// What a part of some CSS-like text is, as found by a BracketScanner
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScannedKind {
+    Char(char),
+    OpenBracket(char),
+    CloseBracket(char),
+    String, // including its quotes, if it has a closing one
+    Escape,
+    Comment,
+    Url, // the brackets of an unquoted url() and everything in them, which can be anything apart from a )
 }
 
-pub fn read_until_any_of(data: &str, chars: &Vec<char>) -> (String, Option<char>) {
-    // Read a string until (but not including) one of chars.
-    // If the char is not found then returns the whole string.
-    // Second return value is the char that is stopped at. None if went until end
+pub struct ScannedPart {
+    pub idx: usize, // in bytes
+    pub len: usize,
+    pub kind: ScannedKind,
+    pub depth: usize, // how many brackets it is inside. For a bracket itself, this is how many are outside it
+}
 
-    let mut idx: usize = 0;
-    let mut result = "".to_string();
-    let mut found_char = None;
-    while idx < data.len() {
-        let crnt_char = get_char_unwrap(data, idx);
-        if chars.contains(&crnt_char) {
-            found_char = Some(crnt_char);
-            break;
+/// Goes through CSS-like text (CSS, selectors and the like) keeping track of (), [] and {}.
+/// Strings, escapes, comments and unquoted url()s are skipped over as a whole, since brackets in them don't count.
+/// A closing bracket that doesn't close anything is just a Char
+pub struct BracketScanner<'a> {
+    text: &'a str,
+    idx: usize,
+    expected_closes: Vec<char>, // innermost last
+    pub has_unclosed_string: bool,
+    pub has_mismatched_bracket: bool,
+}
+
+impl<'a> BracketScanner<'a> {
+    pub fn new(text: &'a str) -> BracketScanner<'a> {
+        BracketScanner {
+            text,
+            idx: 0,
+            expected_closes: vec![],
+            has_unclosed_string: false,
+            has_mismatched_bracket: false,
         }
-        result.push(crnt_char);
-        idx += 1;
     }
-    (result, found_char)
+
+    /// Whether a bracket is still open, to be called once the scanner has been used up
+    pub fn has_unclosed_bracket(&self) -> bool {
+        !self.expected_closes.is_empty()
+    }
+
+    fn string_length(&mut self, rest: &str, quote_char: char) -> usize {
+        let mut idx = quote_char.len_utf8();
+        while let Some(c) = rest[idx..].chars().next() {
+            if c == quote_char {
+                return idx + c.len_utf8();
+            }
+            idx += if c == '\\' {
+                escape_length(&rest[idx..])
+            } else {
+                c.len_utf8()
+            };
+        }
+        self.has_unclosed_string = true;
+        rest.len()
+    }
+}
+
+impl Iterator for BracketScanner<'_> {
+    type Item = ScannedPart;
+
+    fn next(&mut self) -> Option<ScannedPart> {
+        let rest = &self.text[self.idx..];
+        let c = rest.chars().next()?;
+        let mut depth = self.expected_closes.len();
+        let (kind, len) = match c {
+            '\\' => (ScannedKind::Escape, escape_length(rest)),
+            '"' | '\'' => (ScannedKind::String, self.string_length(rest, c)),
+            '/' if rest.starts_with("/*") => (
+                ScannedKind::Comment,
+                rest[2..].find("*/").map_or(rest.len(), |end| end + 4),
+            ),
+            '(' if self.text[..self.idx].to_lowercase().ends_with("url")
+                && !rest[1..].trim_start().starts_with(['"', '\'']) =>
+            {
+                (
+                    ScannedKind::Url,
+                    rest.find(')').map_or(rest.len(), |end| end + 1),
+                )
+            }
+            '(' | '[' | '{' => {
+                self.expected_closes.push(match c {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                });
+                (ScannedKind::OpenBracket(c), 1)
+            }
+            ')' | ']' | '}' if !self.expected_closes.is_empty() => {
+                if self.expected_closes.pop() != Some(c) {
+                    self.has_mismatched_bracket = true;
+                }
+                depth -= 1;
+                (ScannedKind::CloseBracket(c), 1)
+            }
+            ')' | ']' | '}' => {
+                self.has_mismatched_bracket = true;
+                (ScannedKind::Char(c), 1)
+            }
+            c => (ScannedKind::Char(c), c.len_utf8()),
+        };
+        let part = ScannedPart {
+            idx: self.idx,
+            len,
+            kind,
+            depth,
+        };
+        self.idx += len;
+        Some(part)
+    }
+}
+
+/// Byte indices and values of the characters in `text` that aren't inside brackets, strings, escapes or comments
+pub fn top_level_chars(text: &str) -> Vec<(usize, char)> {
+    BracketScanner::new(text)
+        .filter_map(|part| match part.kind {
+            ScannedKind::Char(c) if part.depth == 0 => Some((part.idx, c)),
+            _ => None,
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -103,7 +224,59 @@ mod tests {
     }
 
     #[test]
-    fn test_read_until_character() {
-        // todo: write this!!!
+    fn test_escape_length() {
+        assert_eq!(escape_length("\\:hidden"), 2);
+        assert_eq!(escape_length("\\31 0"), 4);
+        assert_eq!(escape_length("\\1F600x"), 6);
+        assert_eq!(escape_length("\\"), 1);
+    }
+
+    #[test]
+    fn test_bracket_scanner() {
+        let kinds: Vec<(ScannedKind, usize)> =
+            BracketScanner::new("a(\"b)\"[c]) /* ) */ url(x;y)\\)")
+                .filter(|part| part.kind != ScannedKind::Char(' '))
+                .map(|part| (part.kind, part.depth))
+                .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (ScannedKind::Char('a'), 0),
+                (ScannedKind::OpenBracket('('), 0),
+                (ScannedKind::String, 1),
+                (ScannedKind::OpenBracket('['), 1),
+                (ScannedKind::Char('c'), 2),
+                (ScannedKind::CloseBracket(']'), 1),
+                (ScannedKind::CloseBracket(')'), 0),
+                (ScannedKind::Comment, 0),
+                (ScannedKind::Char('u'), 0),
+                (ScannedKind::Char('r'), 0),
+                (ScannedKind::Char('l'), 0),
+                (ScannedKind::Url, 0),
+                (ScannedKind::Escape, 0),
+            ]
+        );
+
+        let mut scanner = BracketScanner::new("a[b) 'c");
+        scanner.by_ref().for_each(drop);
+        assert!(scanner.has_mismatched_bracket);
+        assert!(scanner.has_unclosed_string);
+        assert!(!scanner.has_unclosed_bracket());
+    }
+
+    #[test]
+    fn test_top_level_chars() {
+        assert_eq!(
+            top_level_chars("a:is(b, c), d"),
+            vec![
+                (0, 'a'),
+                (1, ':'),
+                (2, 'i'),
+                (3, 's'),
+                (10, ','),
+                (11, ' '),
+                (12, 'd')
+            ]
+        );
     }
 }
@@ -0,0 +1,495 @@
+// Handles the Javascript files in a project's common/ dir.
+// A common file can say which other common files it uses, either with a requires() first line like the runtime files
+// or with ES import statements. The imports/exports are stripped out since everything is bundled into one script,
+// and the files are ordered so that each one comes after the files it uses.
+
+use std::path::PathBuf;
+
+use crate::dependency_order::{self, DependencyError};
+use crate::errs;
+
+pub struct CommonFile {
+    pub source_file: PathBuf,
+    pub file_name: String,                      // eg weatherService.js
+    pub requires: Vec<String>,                  // file names of the common files this one uses
+    pub declared_names: Vec<String>, // top level consts, classes etc that elements can use
+    pub default_export: Option<String>, // name of the class/function that is exported as default
+    pub default_imports: Vec<(String, String)>, // (local name, file name) of each default import
+    pub content: String,             // with requires() and import/export statements removed
+}
+
+// Keywords that start a top level declaration
+const DECLARATION_KEYWORDS: [&str; 5] = ["const", "let", "var", "class", "function"];
+
+pub fn read_common_file(
+    source_file: PathBuf,
+    file_content: &str,
+) -> Result<CommonFile, errs::CommonFileError> {
+    let file_name = source_file
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let mut requires = dependency_order::parse_requires_statement(file_content);
+    let mut declared_names = vec![];
+    let mut default_export = None;
+    let mut default_imports = vec![];
+    let mut content_lines = vec![];
+
+    let mut lines = dependency_order::remove_requires_statement(file_content)
+        .lines()
+        .map(|l| l.to_string())
+        .collect::<Vec<String>>()
+        .into_iter();
+    while let Some(line) = lines.next() {
+        if line.starts_with("import ") || line.starts_with("import{") {
+            // Imports can be split over multiple lines, they end at the quoted file name
+            let mut statement = line;
+            while find_quoted_string(&statement).is_none() {
+                match lines.next() {
+                    Some(next_line) => statement += &next_line,
+                    None => break,
+                }
+            }
+            if let Some(specifier) = find_quoted_string(&statement) {
+                // There is no module object to give a namespace import, since the files all share one scope
+                if let Some(namespace) = find_namespace_import(&statement) {
+                    return Err(errs::CommonFileError::NamespaceImport {
+                        namespace,
+                        specifier,
+                    });
+                }
+                let required_file = file_name_from_import_specifier(&specifier);
+                if let Some(local_name) = find_default_import(&statement) {
+                    default_imports.push((local_name, required_file.clone()));
+                }
+                if !requires.contains(&required_file) {
+                    requires.push(required_file);
+                }
+            }
+            // Renamed imports become a const with the new name
+            for (imported, local) in find_renamed_bindings(&statement) {
+                content_lines.push(format!("const {local} = {imported};"));
+            }
+        } else if line.starts_with("export {") || line.starts_with("export{") {
+            // Exports of things that are declared elsewhere in the file - everything is global anyway,
+            // so only renamed ones need anything. Export lists can be split over multiple lines, they end at the }
+            let mut statement = line;
+            while !statement.contains('}') {
+                match lines.next() {
+                    Some(next_line) => statement += &format!(" {next_line}"),
+                    None => break,
+                }
+            }
+            for (name, exported) in find_renamed_bindings(&statement) {
+                if exported == "default" {
+                    default_export = Some(name);
+                } else {
+                    content_lines.push(format!("const {exported} = {name};"));
+                    declared_names.push(exported);
+                }
+            }
+        } else {
+            let is_default_export = line.starts_with("export default ");
+            let line = line
+                .strip_prefix("export default ")
+                .or(line.strip_prefix("export "))
+                .map(|l| l.to_string())
+                .unwrap_or(line);
+            let declared_name = find_declared_name(&line);
+            // A default export that isn't a named declaration would be left as a value that nothing can refer to
+            if is_default_export && declared_name.is_none() {
+                return Err(errs::CommonFileError::AnonymousDefaultExport);
+            }
+            if let Some(name) = declared_name {
+                if is_default_export {
+                    default_export = Some(name.clone());
+                }
+                declared_names.push(name);
+            }
+            content_lines.push(line);
+        }
+    }
+
+    Ok(CommonFile {
+        source_file,
+        file_name,
+        requires,
+        declared_names,
+        default_export,
+        default_imports,
+        content: content_lines.join("\n"),
+    })
+}
+
+/// Point default imports at the class/function that their file exports as default.
+/// Imports using a different name than it get a const with their name, eg `import W from "./widget.js"` gives
+/// `const W = Widget;`. Files that don't exist are left to order_common_files to report
+pub fn link_default_imports(files: &mut [CommonFile]) -> Result<(), errs::CompilationError> {
+    for index in 0..files.len() {
+        let mut aliases = vec![];
+        for (local_name, file_name) in &files[index].default_imports {
+            let Some(imported_file) = files.iter().find(|f| f.file_name == *file_name) else {
+                continue;
+            };
+            match &imported_file.default_export {
+                Some(exported_name) if exported_name == local_name => (),
+                Some(exported_name) => {
+                    aliases.push(format!("const {local_name} = {exported_name};"))
+                }
+                None => {
+                    return Err(errs::CompilationError::File {
+                        file_name: files[index].source_file.to_string_lossy().to_string(),
+                        inner_error: errs::FileCompilationError::CommonFileError(
+                            errs::CommonFileError::MissingDefaultExport {
+                                local_name: local_name.clone(),
+                                file_name: file_name.clone(),
+                            },
+                        ),
+                    })
+                }
+            }
+        }
+        if !aliases.is_empty() {
+            let file = &mut files[index];
+            file.content = format!("{}\n{}", aliases.join("\n"), file.content);
+        }
+    }
+    Ok(())
+}
+
+fn find_default_import(statement: &str) -> Option<String> {
+    // Give the local name in an `import name from "..."` or `import name, { a } from "..."` statement
+
+    let clause = statement
+        .strip_prefix("import")?
+        .split(['"', '\''])
+        .next()?;
+    let clause = clause.trim_end().strip_suffix("from")?;
+    let name = clause.split([',', '{']).next()?.trim();
+    (!name.is_empty() && name.chars().all(is_identifier_char)).then(|| name.to_string())
+}
+
+fn find_namespace_import(statement: &str) -> Option<String> {
+    // Give the name in an `import * as name from "..."` statement
+
+    let after_star = &statement[statement.find('*')? + 1..];
+    let name = after_star.trim_start().strip_prefix("as")?.trim_start();
+    Some(
+        name.chars()
+            .take_while(|c| is_identifier_char(*c))
+            .collect(),
+    )
+}
+
+fn find_renamed_bindings(statement: &str) -> Vec<(String, String)> {
+    // Find the `a as b` pairs in the braces of an import/export statement, eg `{ a as b, c }` gives [(a, b)]
+
+    let Some(start) = statement.find('{') else {
+        return vec![];
+    };
+    let Some(end) = statement[start..].find('}') else {
+        return vec![];
+    };
+    statement[start + 1..start + end]
+        .split(',')
+        .filter_map(|binding| {
+            let mut words = binding.split_whitespace();
+            let name = words.next()?;
+            if words.next()? != "as" {
+                return None;
+            }
+            let new_name = words.next()?;
+            (name != new_name).then(|| (name.to_string(), new_name.to_string()))
+        })
+        .collect()
+}
+
+fn find_quoted_string(text: &str) -> Option<String> {
+    // Find the last string literal in some text, eg the "./file.js" in an import statement
+
+    let end = text.rfind(['"', '\''])?;
+    let quote_char = text[end..].chars().next()?;
+    let start = text[..end].rfind(quote_char)?;
+    Some(text[start + 1..end].to_string())
+}
+
+fn file_name_from_import_specifier(specifier: &str) -> String {
+    // "./weatherService.js", "weatherService.js" and "./weatherService" all refer to common/weatherService.js
+
+    let file_name = specifier.trim_start_matches("./");
+    if file_name.ends_with(".js") {
+        file_name.to_string()
+    } else {
+        format!("{file_name}.js")
+    }
+}
+
+fn find_declared_name(line: &str) -> Option<String> {
+    // If the line is a top level declaration (not indented), give the name that it declares
+
+    if line.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let mut words = line.split_whitespace();
+    let mut keyword = words.next()?;
+    if keyword == "async" {
+        keyword = words.next()?;
+    }
+    if !DECLARATION_KEYWORDS.contains(&keyword) {
+        return None;
+    }
+    let name: String = words
+        .next()?
+        .trim_start_matches('*')
+        .chars()
+        .take_while(|c| is_identifier_char(*c))
+        .collect();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+pub fn contains_identifier(text: &str, identifier: &str) -> bool {
+    // Whether identifier appears in text as a whole word (so "randomService" doesn't match "myRandomService")
+
+    text.match_indices(identifier).any(|(index, _)| {
+        let before = text[..index].chars().next_back();
+        let after = text[index + identifier.len()..].chars().next();
+        !before.is_some_and(is_identifier_char) && !after.is_some_and(is_identifier_char)
+    })
+}
+
+/// Order common files so that each one comes after the files it requires
+pub fn order_common_files(files: Vec<CommonFile>) -> Result<Vec<CommonFile>, DependencyError> {
+    let dependencies = files
+        .iter()
+        .map(|f| (f.file_name.clone(), f.requires.clone()))
+        .collect::<Vec<(String, Vec<String>)>>();
+    let order = dependency_order::order_by_dependencies(&dependencies)?;
+
+    let mut files: Vec<Option<CommonFile>> = files.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .filter_map(|index| files[index].take())
+        .collect())
+}
+
+/// Find the common files that aren't used by any of `element_contents`, directly or through other common files.
+///
+/// A file is used if one of the names it declares appears in an element. Files that don't declare anything
+/// we can find (eg ones that only add to `window`) are always counted as used.
+pub fn find_unused_common_files<'a>(
+    files: &'a [CommonFile],
+    element_contents: &[&str],
+) -> Vec<&'a CommonFile> {
+    let mut used: Vec<bool> = files
+        .iter()
+        .map(|file| {
+            file.declared_names.is_empty()
+                || file.declared_names.iter().any(|name| {
+                    element_contents
+                        .iter()
+                        .any(|content| contains_identifier(content, name))
+                })
+        })
+        .collect();
+
+    // Anything required by a used file is used too. Keep going until nothing changes
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for index in 0..files.len() {
+            if !used[index] {
+                continue;
+            }
+            for requirement in &files[index].requires {
+                if let Some(required_index) = files.iter().position(|f| f.file_name == *requirement)
+                {
+                    if !used[required_index] {
+                        used[required_index] = true;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    files
+        .iter()
+        .zip(used)
+        .filter(|(_, used)| !used)
+        .map(|(file, _)| file)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn common_file(file_name: &str, content: &str) -> CommonFile {
+        read_common_file(PathBuf::from("/project/common").join(file_name), content).unwrap()
+    }
+
+    #[test]
+    fn test_requires_statement() {
+        let file = common_file(
+            "weatherService.js",
+            "requires(randomService.js);\nconst weatherService = {};",
+        );
+        assert_eq!(file.requires, vec!["randomService.js"]);
+        assert_eq!(file.declared_names, vec!["weatherService"]);
+        assert_eq!(file.content, "const weatherService = {};");
+    }
+
+    #[test]
+    fn test_es_imports_and_exports() {
+        let file = common_file(
+            "weatherService.js",
+            "import { randomService } from './randomService.js';\nimport {\n    a,\n    b\n} from \"./letters\";\n\nexport const weatherService = {};\nexport function helper() {}\nexport { helper };",
+        );
+        assert_eq!(file.requires, vec!["randomService.js", "letters.js"]);
+        assert_eq!(file.declared_names, vec!["weatherService", "helper"]);
+        assert_eq!(
+            file.content,
+            "\nconst weatherService = {};\nfunction helper() {}"
+        );
+    }
+
+    #[test]
+    fn test_renamed_imports_and_exports() {
+        let file = common_file(
+            "weatherService.js",
+            "import { randomService as random, other } from './randomService.js';\nfunction helper() {}\nexport { helper as otherName, helper as default };",
+        );
+        assert_eq!(file.requires, vec!["randomService.js"]);
+        assert_eq!(file.declared_names, vec!["helper", "otherName"]);
+        assert_eq!(
+            file.content,
+            "const random = randomService;\nfunction helper() {}\nconst otherName = helper;"
+        );
+    }
+
+    #[test]
+    fn test_multi_line_export_list() {
+        let file = common_file(
+            "weatherService.js",
+            "function helper() {}\nexport {\n    helper as otherName,\n    helper\n};\nconst x = 1;",
+        );
+        assert_eq!(file.declared_names, vec!["helper", "otherName", "x"]);
+        assert_eq!(
+            file.content,
+            "function helper() {}\nconst otherName = helper;\nconst x = 1;"
+        );
+    }
+
+    #[test]
+    fn test_default_exports() {
+        let file = common_file("weather.js", "export default class Weather {}");
+        assert_eq!(file.declared_names, vec!["Weather"]);
+        assert_eq!(file.default_export, Some("Weather".to_string()));
+        assert_eq!(file.content, "class Weather {}");
+
+        let result = read_common_file(PathBuf::from("weather.js"), "export default { a: 1 };");
+        assert!(matches!(
+            result,
+            Err(errs::CommonFileError::AnonymousDefaultExport)
+        ));
+    }
+
+    #[test]
+    fn test_link_default_imports() {
+        let mut files = vec![
+            common_file(
+                "app.js",
+                "import W, { a } from './widget.js';\nimport Helper from \"./helper\";\nconst app = new W();",
+            ),
+            common_file("widget.js", "export default class Widget {}\nconst a = 1;"),
+            common_file("helper.js", "function Helper() {}\nexport { Helper as default };"),
+        ];
+        assert_eq!(
+            files[0].default_imports,
+            vec![
+                ("W".to_string(), "widget.js".to_string()),
+                ("Helper".to_string(), "helper.js".to_string())
+            ]
+        );
+        link_default_imports(&mut files).unwrap();
+        assert_eq!(files[0].content, "const W = Widget;\nconst app = new W();");
+
+        let mut files = vec![
+            common_file("app.js", "import W from './widget.js';"),
+            common_file("widget.js", "const Widget = 1;"),
+        ];
+        assert_eq!(
+            link_default_imports(&mut files).err().unwrap().to_string(),
+            "Error compiling /project/common/app.js:\n    \"W\" is imported as the default export of widget.js, but it doesn't export a class or function as default"
+        );
+    }
+
+    #[test]
+    fn test_namespace_import() {
+        let result = read_common_file(
+            PathBuf::from("weather.js"),
+            "import * as random from './randomService.js';",
+        );
+        assert!(matches!(
+            result,
+            Err(errs::CommonFileError::NamespaceImport { namespace, specifier })
+                if namespace == "random" && specifier == "./randomService.js"
+        ));
+    }
+
+    #[test]
+    fn test_order_common_files() {
+        let files = vec![
+            common_file("a.js", "import { b } from './b.js';\nconst a = b;"),
+            common_file("b.js", "const b = 1;"),
+        ];
+        let names = order_common_files(files)
+            .unwrap()
+            .iter()
+            .map(|f| f.file_name.clone())
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["b.js", "a.js"]);
+
+        let files = vec![
+            common_file("a.js", "requires(b.js);"),
+            common_file("b.js", "requires(a.js);"),
+        ];
+        assert!(matches!(
+            order_common_files(files),
+            Err(DependencyError::Cycle { .. })
+        ));
+    }
+
+    #[test]
+    fn test_find_unused_common_files() {
+        let files = vec![
+            common_file("randomService.js", "const randomService = {};"),
+            common_file(
+                "weatherService.js",
+                "requires(randomService.js);\nconst weatherService = {};",
+            ),
+            common_file("oldService.js", "const oldService = {};"),
+            common_file("setup.js", "window.setupDone = true;"),
+        ];
+        let unused = find_unused_common_files(&files, &["this.weather = weatherService.get();"]);
+        assert_eq!(
+            unused
+                .iter()
+                .map(|f| f.file_name.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["oldService.js"]
+        );
+
+        let unused = find_unused_common_files(&files, &["myOldService.get()"]);
+        assert_eq!(unused.len(), 3);
+    }
+}
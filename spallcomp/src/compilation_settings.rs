@@ -1,14 +1,49 @@
+use std::path::PathBuf;
+
+/// Options controlling how a project is compiled. The default matches running `spall build` with no flags
 pub struct CompilationSettings {
     pub log_level: CompilationLogLevel,
     pub minify_files: bool,
     pub debug_tokens: bool,
     pub preserve_html_comments: bool,
+    pub release: bool, // hash output file names so that browsers don't use stale cached copies
+    pub output_format: OutputFormat,
+    pub split_pages: bool, // put each page in its own chunk that is loaded when the page is first visited
+    pub warn_unmatched_selectors: bool, // warn about scoped CSS selectors that match nothing in their element's markup
+    pub global_stylesheet: Option<PathBuf>, // relative to the project dir. Defaults to styles/global/index.css if it exists
+}
+
+impl Default for CompilationSettings {
+    fn default() -> CompilationSettings {
+        CompilationSettings {
+            log_level: CompilationLogLevel::Minimal,
+            minify_files: true,
+            debug_tokens: false,
+            preserve_html_comments: false,
+            release: false,
+            output_format: OutputFormat::Scripts,
+            split_pages: false,
+            warn_unmatched_selectors: false,
+            global_stylesheet: None,
+        }
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Copy, Clone, PartialOrd, PartialEq)]
 pub enum CompilationLogLevel {
+    Silent = 0,  // no logging at all, for when spallcomp is used as a library
     Minimal = 1, // basically no logging
     Brief = 2,   // log that you're compiling each file
     PerStep = 3, // log each step of compiling each file
 }
+
+/// How the compiled Javascript is packaged
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OutputFormat {
+    /// framework.js and bundle.js are plain scripts that define global classes
+    Scripts,
+    /// framework.js and every element, page and common file are ES modules with explicit imports and exports.
+    /// bundle.js is the entry module and starts the app itself
+    EsModules,
+}
@@ -0,0 +1,717 @@
+// Public entry point for using spallcomp as a library (eg from a build.rs, a file watcher or tests)
+
+use std::path::Path;
+
+use crate::compilation_settings::CompilationSettings;
+use crate::errs::{CompilationError, Diagnostics, ProjectCompilationError};
+use crate::project_compiler;
+use crate::project_fs::{DiskFs, ProjectFs};
+use crate::report::CompilationReport;
+
+/// Compiles spall projects into their `build/` directory.
+///
+/// Unlike [`compile_project`](crate::compile_project), this never exits the process
+/// or prints errors - problems with the project are returned as [`Diagnostics`].
+pub struct Compiler {
+    settings: CompilationSettings,
+    fs: Box<dyn ProjectFs>,
+}
+
+impl Compiler {
+    /// Create a compiler that uses `settings` for every project it compiles, reading and writing on disk
+    pub fn new(settings: CompilationSettings) -> Compiler {
+        Compiler::with_fs(settings, Box::new(DiskFs))
+    }
+
+    /// Create a compiler that reads projects from and writes builds to `fs` (eg a [`MemoryFs`](crate::MemoryFs))
+    pub fn with_fs(settings: CompilationSettings, fs: Box<dyn ProjectFs>) -> Compiler {
+        Compiler { settings, fs }
+    }
+
+    /// The filesystem that projects are compiled from/to, so that output can be read back from memory
+    pub fn fs(&self) -> &dyn ProjectFs {
+        self.fs.as_ref()
+    }
+
+    /// Compile the project in `project_dir`. Relative paths are relative to the current directory.
+    ///
+    /// On success, returns a report of everything that was produced (also saved as `build/spall-manifest.json`).
+    pub fn compile(&self, project_dir: &Path) -> Result<CompilationReport, Diagnostics> {
+        let project_dir = self.fs.canonicalize(project_dir).map_err(|_| {
+            CompilationError::Project(ProjectCompilationError::NoProjectDirectory {
+                path: project_dir.to_path_buf(),
+            })
+        })?;
+        project_compiler::compile_project(&project_dir, &self.settings, self.fs.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compilation_settings::CompilationLogLevel;
+    use crate::project_fs::MemoryFs;
+
+    fn quiet_settings() -> CompilationSettings {
+        CompilationSettings {
+            log_level: CompilationLogLevel::Silent,
+            minify_files: false,
+            ..Default::default()
+        }
+    }
+
+    fn memory_compiler(files: &[(&str, &str)]) -> Compiler {
+        memory_compiler_with(quiet_settings(), files)
+    }
+
+    fn memory_compiler_with(settings: CompilationSettings, files: &[(&str, &str)]) -> Compiler {
+        Compiler::with_fs(settings, Box::new(MemoryFs::with_files("/project", files)))
+    }
+
+    fn read_output(compiler: &Compiler, path: &str) -> String {
+        compiler
+            .fs()
+            .read_to_string(&Path::new("/project/build").join(path))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_compile_in_memory() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", "<head></head><body></body>"),
+            ("elements/Root.spall", "<h1>Hello</h1><Greeting />"),
+            ("elements/Greeting.spall", "<p>Hi</p>"),
+            ("pages/Index.spall", "<pageroute>/</pageroute><p>Home</p>"),
+            ("styles/Greeting.css", "p { color: red; }"),
+            ("static/site.css", "body {}"),
+        ]);
+        let report = compiler.compile(Path::new("/project")).unwrap();
+
+        assert_eq!(report.elements.len(), 2);
+        assert_eq!(report.pages[0].routes, vec!["/"]);
+        assert!(read_output(&compiler, "scripts/bundle.js").contains("class __SpallCompiledRoot"));
+        assert!(read_output(&compiler, "css/bundle.css").contains("._spGreeting p"));
+        assert_eq!(read_output(&compiler, "static/site.css"), "body {}");
+        assert!(read_output(&compiler, "index.html").contains("/scripts/framework.js"));
+        assert!(read_output(&compiler, "spall-manifest.json").contains("Greeting.spall"));
+    }
+
+    #[test]
+    fn test_release_rebuild_removes_stale_hashed_assets() {
+        let compiler = memory_compiler_with(
+            CompilationSettings {
+                release: true,
+                ..quiet_settings()
+            },
+            &[
+                ("meta/index.html", ""),
+                ("elements/Root.spall", "<p>Hi</p>"),
+                ("styles/Root.css", "p { color: red; }"),
+            ],
+        );
+        compiler.compile(Path::new("/project")).unwrap();
+        compiler
+            .fs()
+            .write(Path::new("/project/elements/Root.spall"), b"<p>Hello</p>")
+            .unwrap();
+        compiler
+            .fs()
+            .write(Path::new("/project/styles/Root.css"), b"p { color: blue; }")
+            .unwrap();
+        let report = compiler.compile(Path::new("/project")).unwrap();
+
+        for (dir, asset_paths) in [
+            ("css", vec![report.assets.scoped_css_bundle.path.clone()]),
+            (
+                "scripts",
+                vec![
+                    report.assets.bundle_script.path.clone(),
+                    report.assets.framework_script.path.clone(),
+                ],
+            ),
+        ] {
+            let mut files: Vec<String> = compiler
+                .fs()
+                .read_dir(&Path::new("/project/build").join(dir))
+                .unwrap()
+                .iter()
+                .map(|f| {
+                    f.strip_prefix("/project/build")
+                        .unwrap()
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect();
+            files.sort();
+            let mut asset_paths = asset_paths;
+            asset_paths.sort();
+            assert_eq!(files, asset_paths);
+        }
+    }
+
+    #[test]
+    fn test_build_manifest() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<Greeting />"),
+            ("elements/Greeting.spall", "<p>Hi</p>"),
+            (
+                "pages/Product.spall",
+                "<pageroute>/</pageroute><pageroute>product/${id}/</pageroute>",
+            ),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let manifest: serde_json::Value =
+            serde_json::from_str(&read_output(&compiler, "spall-manifest.json")).unwrap();
+        assert_eq!(manifest["elements"].as_array().unwrap().len(), 2);
+        assert_eq!(manifest["pages"][0]["name"], "Product");
+        assert_eq!(
+            manifest["pages"][0]["routes"],
+            serde_json::json!(["/", "product/${id}/"])
+        );
+        assert_eq!(
+            manifest["assets"]["bundle_script"]["path"],
+            "scripts/bundle.js"
+        );
+        assert_eq!(manifest["warnings"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_duplicate_page_route_warning() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<p>Hi</p>"),
+            (
+                "pages/Product.spall",
+                "<pageroute>/product/${id}</pageroute>",
+            ),
+            (
+                "pages/Item.spall",
+                "<pageroute>product/${name}/</pageroute>",
+            ),
+            ("pages/Index.spall", "<pageroute>/</pageroute>"),
+        ]);
+        let report = compiler.compile(Path::new("/project")).unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(matches!(
+            &report.warnings[0],
+            crate::errs::CompilationWarning::DuplicatePageRoute { route, page_names }
+                if route == "/product/${}" && page_names.len() == 2
+        ));
+    }
+
+    #[test]
+    fn test_compile_in_memory_reports_all_file_errors() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<p>"),
+            ("elements/Other.spall", "</p>"),
+        ]);
+        let diagnostics = compiler.compile(Path::new("/project")).err().unwrap();
+        assert_eq!(diagnostics.errors.len(), 2);
+    }
+
+    #[test]
+    fn test_html_comments() {
+        let files = [
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<div><!-- <RoutedApp /> ${this.x} --><p>Hi</p></div>",
+            ),
+        ];
+        let compiler = memory_compiler(&files);
+        let report = compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(bundle.contains("`<div ><p >"));
+        assert!(!bundle.contains("<!--"));
+        assert!(!report
+            .runtime_files
+            .contains(&"SpallRoutedApp.js".to_string()));
+
+        let compiler = memory_compiler_with(
+            CompilationSettings {
+                preserve_html_comments: true,
+                ..quiet_settings()
+            },
+            &files,
+        );
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(bundle.contains("`<div ><!-- <RoutedApp /> ${'${'}this.x} --><p >"));
+    }
+
+    #[test]
+    fn test_void_elements_and_attributes() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<div /><p>Hi<br>there</p><img src=a.png alt='say \"hi\"'><input disabled><td colspan=2>x</td>",
+            ),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(bundle.contains("<div ></div><p >"));
+        assert!(bundle.contains("<br />"));
+        assert!(bundle.contains("<img src=\"a.png\" alt=\"say &quot;hi&quot;\"/>"));
+        assert!(bundle.contains("<input disabled=\"\"/>"));
+        assert!(bundle.contains("<td colspan=\"2\">"));
+
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<p>Hi<br></br></p>"),
+        ]);
+        let diagnostics = compiler.compile(Path::new("/project")).err().unwrap();
+        assert!(diagnostics.errors[0]
+            .to_string()
+            .contains("<br> can't have any content, so it doesn't need a </br>"));
+    }
+
+    #[test]
+    fn test_scoped_css_bundle() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<p class=\"a\">a</p><Card />"),
+            ("elements/Card.spall", "<p>b</p>"),
+            ("styles/Root.css", ".a { background: url(img/bg.png); }"),
+            ("styles/Card.css", "p { color: red; }"),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let css = read_output(&compiler, "css/bundle.css");
+        assert_eq!(
+            css,
+            "._spCard p {\n    color: red;\n}\n\n._spRoot .a {\n    background: url(/static/img/bg.png);\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_whitespace_between_tags() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<div>\n    <p><b>Hello</b>  <i>world</i></p>\n</div>",
+            ),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(bundle.contains("<div ><p ><b >Hello</b> <i >world</i></p></div>"));
+    }
+
+    #[test]
+    fn test_raw_text_elements() {
+        let compiler = memory_compiler_with(
+            CompilationSettings {
+                minify_files: true,
+                ..quiet_settings()
+            },
+            &[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<script>\n    isSmall(x) { return x < 10 && this.tag != \"<b>\"; }\n</script>\n<pre>  a\n    b <b>c</b> <i>d</i></pre><code>x < y ~ z</code><textarea>  hi\n</textarea>",
+            ),
+            ],
+        );
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(bundle.contains("isSmall(x){return x<10&&this.tag!=\"<b>\"}"));
+        assert!(bundle.contains("<pre >  a\n    b <b >c</b> <i >d</i></pre>"));
+        assert!(bundle.contains("<code >x &lt; y ~ z</code>"));
+        assert!(bundle.contains("<textarea >  hi\n</textarea>"));
+    }
+
+    #[test]
+    fn test_raw_text_interpolations_keep_less_thans() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                r#"<code>${this.n < 2 ? "a" : "b"} < c</code><textarea>${this.n<2} < x</textarea>"#,
+            ),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(bundle
+            .contains(r#"<code >${SpallMarkup.escapeHtml(this.n < 2 ? "a" : "b")} &lt; c</code>"#));
+        assert!(bundle.contains("<textarea >${SpallMarkup.escapeHtml(this.n<2)} &lt; x</textarea>"));
+    }
+
+    #[test]
+    fn test_text_keeps_its_place_between_tags() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<p>Hello <b>you</b>, welcome</p><p>Hi <Label /> there</p>",
+            ),
+            ("elements/Label.spall", "<i>label</i>"),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(bundle.contains("<p >Hello <b >you</b>, welcome</p><p >Hi `)"));
+        assert!(bundle.contains("new SpallMarkupRenderable(` there</p>`)"));
+        assert!(!bundle.contains("<span"));
+    }
+
+    #[test]
+    fn test_markup_escapes() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                r#"<p>~~5 minutes, \${name} &lt;b&gt;</p><a title="\${title} ~ &lt;" href="/">Link</a><Label text="\${text} ~ &lt;" />"#,
+            ),
+            ("elements/Label.spall", "<p>${this.text}</p>"),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        // text
+        assert!(bundle.contains("<p >~5 minutes, ${'${'}name} &lt;b&gt;</p>"));
+        // attributes
+        assert!(bundle.contains(r#"<a title="${'${'}title} ~ &lt;" href="/">"#));
+        // element parameters, which are plain strings rather than markup
+        assert!(bundle.contains(r#"text:() => "\${text} ~ &lt;""#));
+    }
+
+    #[test]
+    fn test_interpolations_are_escaped() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                r#"<p>${this.name} ${raw(this.icon)} ${this.a ? '}' : this.b}</p><a title="${this.name}" !onclick="this.show(${this.id})" onmouseover="alert(${this.name})">x</a>"#,
+            ),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(bundle.contains(
+            "<p >${SpallMarkup.escapeHtml(this.name)} ${this.icon} ${SpallMarkup.escapeHtml(this.a ? '}' : this.b)}</p>"
+        ));
+        assert!(bundle.contains(r#"title="${SpallMarkup.escapeAttribute(this.name)}""#));
+        // event handlers get values as javascript literals
+        assert!(bundle.contains(".show(${SpallMarkup.escapeHandlerValue(this.id)})\""));
+        assert!(
+            bundle.contains(r#"onmouseover="alert(${SpallMarkup.escapeHandlerValue(this.name)})""#)
+        );
+        let framework = read_output(&compiler, "scripts/framework.js");
+        assert!(framework.contains("class SpallMarkup "));
+    }
+
+    #[test]
+    fn test_quotes_in_params_and_handlers() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                r#"<Label text='say "hi"' /><Label text="say \"hi\"" /><a !onclick='this.f("a")'>x</a>"#,
+            ),
+            ("elements/Label.spall", "<p>${this.text}</p>"),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert_eq!(bundle.matches(r#"text:() => "say \"hi\"""#).count(), 2);
+        assert!(bundle.contains(r#".f(&quot;a&quot;)">x</a>"#));
+    }
+
+    #[test]
+    fn test_compile_leaves_out_unused_runtime() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<h1>Hello</h1>"),
+        ]);
+        let report = compiler.compile(Path::new("/project")).unwrap();
+        let framework = read_output(&compiler, "scripts/framework.js");
+        assert!(framework.contains("class SpallApp"));
+        assert!(!framework.contains("class SpallRouter"));
+        assert!(!report.runtime_files.contains(&"SpallPage.js".to_string()));
+
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<RoutedApp />"),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+        let framework = read_output(&compiler, "scripts/framework.js");
+        assert!(framework.contains("class SpallRouter"));
+        assert!(framework.contains("class __SpallCompiledRoutedApp"));
+    }
+
+    #[test]
+    fn test_compile_split_pages() {
+        let compiler = memory_compiler_with(
+            CompilationSettings {
+                split_pages: true,
+                ..quiet_settings()
+            },
+            &[
+                ("meta/index.html", ""),
+                ("elements/Root.spall", "<RoutedApp />"),
+                ("elements/Chart.spall", "<p>Chart</p>"),
+                ("pages/Index.spall", "<pageroute>/</pageroute><Chart />"),
+            ],
+        );
+        let report = compiler.compile(Path::new("/project")).unwrap();
+
+        assert_eq!(report.assets.chunks[0].path, "scripts/chunks/Index.js");
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(!bundle.contains("class __SpallCompiledChart"));
+        assert!(bundle.contains(
+            "new SpallLazyPage(\"__SpallCompiledIndex\",[\"/scripts/chunks/Index.js\"])"
+        ));
+        let chunk = read_output(&compiler, "scripts/chunks/Index.js");
+        assert!(chunk.contains("class __SpallCompiledIndex"));
+        assert!(chunk.contains("class __SpallCompiledChart"));
+        assert!(read_output(&compiler, "scripts/framework.js").contains("class SpallLazyPage"));
+    }
+
+    #[test]
+    fn test_style_blocks() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<style>\n    p ~ a { content: \"</p>\"; }\n</style>\n<style global>body { margin: 0; }</style>\n<p>Hello</p>",
+            ),
+            ("styles/Root.css", "h1 { color: red; }"),
+        ]);
+        compiler.compile(Path::new("/project")).unwrap();
+
+        let css = read_output(&compiler, "css/bundle.css");
+        assert!(css.find("._spRoot h1").unwrap() < css.find("._spRoot p ~ a").unwrap());
+        assert!(css.contains("content: \"</p>\";"));
+        assert!(css.contains("body {\n    margin: 0;\n}"));
+        let bundle = read_output(&compiler, "scripts/bundle.js");
+        assert!(!bundle.contains("margin"));
+        assert!(!bundle.contains("<style"));
+
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<div><style>p {}</style></div>"),
+        ]);
+        let diagnostics = compiler.compile(Path::new("/project")).err().unwrap();
+        assert!(diagnostics.errors[0]
+            .to_string()
+            .contains("<style> blocks must be at the top level"));
+    }
+
+    #[test]
+    fn test_css_partials() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<p>Hello</p>\n<style>\n    @use \"theme\";\n    p { color: $accent; }\n</style>",
+            ),
+            ("styles/_partials/theme.css", "$accent: blue;"),
+            (
+                "styles/Root.css",
+                "@use \"theme\";\nh1 { & + p { color: $accent; } }",
+            ),
+        ]);
+        let report = compiler.compile(Path::new("/project")).unwrap();
+        assert!(report.warnings.is_empty());
+        let css = read_output(&compiler, "css/bundle.css");
+        assert!(css.contains("._spRoot h1 + p {\n    color: blue;\n}"));
+        assert!(css.contains("._spRoot p {\n    color: blue;\n}"));
+
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<p>Hello</p>\n<style>\n    p { color: $missing; }\n</style>",
+            ),
+        ]);
+        let diagnostics = compiler.compile(Path::new("/project")).err().unwrap();
+        assert!(diagnostics.errors[0]
+            .to_string()
+            .contains("Line 3: Variable $missing is not defined"));
+
+        // the same text earlier in the file doesn't throw off the line
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            (
+                "elements/Root.spall",
+                "<pre>\n    p { color: $missing; }\n</pre>\n<style>\n    p { color: $missing; }\n</style>",
+            ),
+        ]);
+        let diagnostics = compiler.compile(Path::new("/project")).err().unwrap();
+        assert!(diagnostics.errors[0]
+            .to_string()
+            .contains("Line 5: Variable $missing is not defined"));
+    }
+
+    #[test]
+    fn test_global_stylesheet() {
+        let compiler = memory_compiler_with(
+            CompilationSettings {
+                release: true,
+                minify_files: true,
+                ..quiet_settings()
+            },
+            &[
+                ("meta/index.html", ""),
+                ("elements/Root.spall", "<p>Hello</p>"),
+                ("styles/Root.css", "p { color: red; }"),
+                (
+                    "styles/global/index.css",
+                    "@import \"base.css\";\nbody { background: url(../../static/bg.png); }",
+                ),
+                ("styles/global/base.css", "html { margin: 0; }"),
+                ("static/bg.png", "png"),
+            ],
+        );
+        let report = compiler.compile(Path::new("/project")).unwrap();
+
+        assert_eq!(
+            report.global_css_files,
+            vec![
+                Path::new("styles/global/index.css"),
+                Path::new("styles/global/base.css")
+            ]
+        );
+        let css = read_output(&compiler, &report.assets.scoped_css_bundle.path);
+        assert!(css.starts_with("html{margin:0}body{background:url(/static/bg."));
+        assert!(css.ends_with("._spRoot p{color:red}"));
+        let image_name = css
+            .split("url(/")
+            .nth(1)
+            .unwrap()
+            .split(')')
+            .next()
+            .unwrap();
+        assert_eq!(read_output(&compiler, image_name), "png");
+        assert_eq!(read_output(&compiler, "static/bg.png"), "png");
+
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<p>Hello</p>"),
+            ("styles/global/index.css", "@import \"missing.css\";"),
+        ]);
+        let diagnostics = compiler.compile(Path::new("/project")).err().unwrap();
+        assert!(diagnostics.errors[0]
+            .to_string()
+            .contains("Imported stylesheet \"missing.css\" does not exist"));
+    }
+
+    #[test]
+    fn test_design_tokens() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<p>Hello</p>"),
+            (
+                "styles/Root.css",
+                "p { --gap: 4px; color: var(--theme-main); margin: var(--gap); padding: var(--missing); border-color: var(--other, red); }",
+            ),
+            (
+                "tokens.json",
+                r##"{"theme": {"main": "#4780ca"}, "dark": {"theme": {"main": "#213c5f"}}}"##,
+            ),
+        ]);
+        let report = compiler.compile(Path::new("/project")).unwrap();
+
+        let css = read_output(&compiler, &report.assets.scoped_css_bundle.path);
+        assert!(css.starts_with(
+            ":root {\n    --theme-main: #4780ca;\n}\n\n@media (prefers-color-scheme: dark) {\n    :root {\n        --theme-main: #213c5f;\n    }\n}\n"
+        ));
+        let bundle = read_output(&compiler, &report.assets.bundle_script.path);
+        assert!(bundle.contains("const SpallTheme = Object.freeze({"));
+        assert_eq!(
+            report
+                .warnings
+                .iter()
+                .map(|w| w.to_string())
+                .collect::<Vec<String>>(),
+            vec![format!(
+                "var(--missing) in {} is not a design token or a custom property set in the project's CSS",
+                Path::new("styles/Root.css").to_string_lossy()
+            )]
+        );
+
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<p>Hello</p>"),
+            ("tokens.json", r#"{"dark": {"text": "white"}}"#),
+        ]);
+        let diagnostics = compiler.compile(Path::new("/project")).err().unwrap();
+        assert!(diagnostics.errors[0]
+            .to_string()
+            .contains("Dark mode token \"--text\" has no normal value to replace"));
+    }
+
+    #[test]
+    fn test_scoped_css_without_element() {
+        let compiler = memory_compiler(&[
+            ("meta/index.html", ""),
+            ("elements/Root.spall", "<h1>Hello</h1>"),
+            ("styles/Button.css", "p { color: red; }"),
+        ]);
+        let diagnostics = compiler.compile(Path::new("/project")).err().unwrap();
+        assert_eq!(diagnostics.errors.len(), 1);
+        assert!(diagnostics.errors[0]
+            .to_string()
+            .contains("No element or page called \"Button\" to apply these styles to"));
+    }
+
+    #[test]
+    fn test_unmatched_selector_warnings() {
+        let settings = || CompilationSettings {
+            warn_unmatched_selectors: true,
+            ..quiet_settings()
+        };
+        let compiler = memory_compiler_with(
+            settings(),
+            &[
+                ("meta/index.html", ""),
+                (
+                    "elements/Root.spall",
+                    "<div class=\"card big\"><p>Hello</p></div>",
+                ),
+                ("styles/Root.css", ".card p, .big {} .small, a {}"),
+            ],
+        );
+        let report = compiler.compile(Path::new("/project")).unwrap();
+        let warnings: Vec<String> = report.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "Selector \".small\" in styles/Root.css does not match any tag or class in the markup of Root",
+                "Selector \"a\" in styles/Root.css does not match any tag or class in the markup of Root",
+            ]
+        );
+
+        // descendants can be in a child element's markup, so only the compounds in Root's own markup are checked
+        let compiler = memory_compiler_with(
+            settings(),
+            &[
+                ("meta/index.html", ""),
+                ("elements/Root.spall", "<div class=\"card\"><Label /></div>"),
+                ("elements/Label.spall", "<p>Hi</p>"),
+                ("styles/Root.css", ".card p {} :host > .card > a {}"),
+            ],
+        );
+        let report = compiler.compile(Path::new("/project")).unwrap();
+        let warnings: Vec<String> = report.warnings.iter().map(|w| w.to_string()).collect();
+        assert_eq!(
+            warnings,
+            vec!["Selector \":host > .card > a\" in styles/Root.css does not match any tag or class in the markup of Root"]
+        );
+    }
+
+    #[test]
+    fn test_compile_missing_project() {
+        let compiler = Compiler::new(CompilationSettings {
+            log_level: CompilationLogLevel::Silent,
+            ..Default::default()
+        });
+        let diagnostics = compiler
+            .compile(Path::new("/this/project/does/not/exist"))
+            .err()
+            .unwrap();
+        assert_eq!(diagnostics.errors.len(), 1);
+        assert!(matches!(
+            diagnostics.errors[0],
+            CompilationError::Project(ProjectCompilationError::NoProjectDirectory { .. })
+        ));
+    }
+}
@@ -0,0 +1,229 @@
+// Ordering of Javascript files that declare their dependencies with a `requires(A.js, B.js);` first line.
+// This is also included by build.rs (through #[path]) to order the runtime files, so it can't use anything else from the crate
+// and not every function is used by both.
+#![allow(dead_code)]
+
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum DependencyError {
+    // chain is the path of files that leads back round to its first file
+    Cycle { chain: Vec<String> },
+    // chain is the path of files that leads to the one that requires the missing file
+    MissingFile { chain: Vec<String>, missing: String },
+}
+
+impl fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DependencyError::Cycle { chain } => {
+                write!(f, "Dependency cycle: {}", chain.join(" -> "))
+            }
+            DependencyError::MissingFile { chain, missing } => write!(
+                f,
+                "Could not find {missing}, which is required by {}",
+                chain.join(" -> ")
+            ),
+        }
+    }
+}
+
+pub fn parse_requires_statement(file_content: &str) -> Vec<String> {
+    // Returns the files listed in the requires() statement on the first line, if there is one
+
+    match file_content.lines().next() {
+        Some(line) if line.starts_with("requires(") => line
+            .trim_end()
+            .trim_start_matches("requires(")
+            .trim_end_matches(';')
+            .trim_end_matches(')')
+            .split(',')
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+pub fn remove_requires_statement(file_content: &str) -> String {
+    // Remove the requires() statement from the first line of a file if it has one
+
+    if file_content.starts_with("requires(") {
+        file_content
+            .lines()
+            .skip(1)
+            .collect::<Vec<&str>>()
+            .join("\n")
+    } else {
+        file_content.to_string()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VisitState {
+    NotVisited,
+    Visiting,
+    Visited,
+}
+
+/// Order files so that each one comes after everything it requires.
+///
+/// `files` is a list of (file name, names of the files it requires). The result is a list of indices into `files`.
+/// Ordering is stable: files are visited in name order and their requirements in the order they're listed,
+/// so the result only changes when the files do.
+pub fn order_by_dependencies(
+    files: &[(String, Vec<String>)],
+) -> Result<Vec<usize>, DependencyError> {
+    let mut visit_order: Vec<usize> = (0..files.len()).collect();
+    visit_order.sort_by(|a, b| files[*a].0.cmp(&files[*b].0));
+
+    let mut states = vec![VisitState::NotVisited; files.len()];
+    let mut ordered = vec![];
+    let mut chain = vec![];
+    for index in visit_order {
+        visit_file(index, files, &mut states, &mut chain, &mut ordered)?;
+    }
+    Ok(ordered)
+}
+
+fn visit_file(
+    index: usize,
+    files: &[(String, Vec<String>)],
+    states: &mut [VisitState],
+    chain: &mut Vec<String>,
+    ordered: &mut Vec<usize>,
+) -> Result<(), DependencyError> {
+    // Depth first search that adds a file after all of its requirements.
+    // chain holds the names of the files currently being visited, so it can be shown in errors
+
+    let name = &files[index].0;
+    match states[index] {
+        VisitState::Visited => return Ok(()),
+        VisitState::Visiting => {
+            let start = chain.iter().position(|n| n == name).unwrap_or(0);
+            let mut cycle = chain[start..].to_vec();
+            cycle.push(name.clone());
+            return Err(DependencyError::Cycle { chain: cycle });
+        }
+        VisitState::NotVisited => (),
+    }
+
+    states[index] = VisitState::Visiting;
+    chain.push(name.clone());
+    for requirement in &files[index].1 {
+        match files.iter().position(|(n, _)| n == requirement) {
+            Some(requirement_index) => {
+                visit_file(requirement_index, files, states, chain, ordered)?
+            }
+            None => {
+                return Err(DependencyError::MissingFile {
+                    chain: chain.clone(),
+                    missing: requirement.clone(),
+                })
+            }
+        }
+    }
+    chain.pop();
+    states[index] = VisitState::Visited;
+    ordered.push(index);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(data: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+        data.iter()
+            .map(|(name, requires)| {
+                (
+                    name.to_string(),
+                    requires.iter().map(|r| r.to_string()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    fn ordered_names(data: &[(&str, &[&str])]) -> Vec<String> {
+        let files = files(data);
+        order_by_dependencies(&files)
+            .unwrap()
+            .into_iter()
+            .map(|i| files[i].0.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_requires_statement() {
+        assert_eq!(
+            parse_requires_statement("requires(A.js, B.js);\nclass C {}"),
+            vec!["A.js", "B.js"]
+        );
+        assert!(parse_requires_statement("class C {}\nrequires(A.js);").is_empty());
+        assert_eq!(
+            remove_requires_statement("requires(A.js);\nclass C {}"),
+            "class C {}"
+        );
+    }
+
+    #[test]
+    fn test_order_is_stable() {
+        let expected = vec!["A.js", "C.js", "B.js", "D.js"];
+        assert_eq!(
+            ordered_names(&[
+                ("D.js", &[]),
+                ("B.js", &["C.js"]),
+                ("C.js", &[]),
+                ("A.js", &[]),
+            ]),
+            expected
+        );
+        assert_eq!(
+            ordered_names(&[
+                ("A.js", &[]),
+                ("C.js", &[]),
+                ("B.js", &["C.js"]),
+                ("D.js", &[]),
+            ]),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_requirements_come_first() {
+        assert_eq!(
+            ordered_names(&[
+                ("App.js", &["Renderer.js", "Router.js"]),
+                ("Renderer.js", &["Utils.js"]),
+                ("Router.js", &["Utils.js"]),
+                ("Utils.js", &[]),
+            ]),
+            vec!["Utils.js", "Renderer.js", "Router.js", "App.js"]
+        );
+    }
+
+    #[test]
+    fn test_cycle() {
+        let result = order_by_dependencies(&files(&[
+            ("A.js", &["B.js"]),
+            ("B.js", &["C.js"]),
+            ("C.js", &["B.js"]),
+        ]));
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Dependency cycle: B.js -> C.js -> B.js"
+        );
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let result = order_by_dependencies(&files(&[("A.js", &["B.js"]), ("B.js", &["Gone.js"])]));
+        assert_eq!(
+            result.unwrap_err(),
+            DependencyError::MissingFile {
+                chain: vec!["A.js".to_string(), "B.js".to_string()],
+                missing: "Gone.js".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,259 @@
+// Design tokens are values like theme colours and spacing that both the CSS and the Javascript need.
+// They are kept in tokens.json in the project dir, as an object of token names to values where nested objects group
+// tokens under a prefix: {"theme": {"main": "#4780ca"}} gives the custom property --theme-main and SpallTheme.theme.main.
+// A top level "dark" object holds replacement values for when the browser prefers a dark colour scheme.
+//
+// The tokens become :root custom properties at the start of the CSS bundle, and a frozen SpallTheme object that is
+// bundled as if it were the first common file, so that ES modules import it like any other common file.
+
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::common_files::CommonFile;
+use crate::errs;
+
+pub const THEME_OBJECT_NAME: &str = "SpallTheme";
+const DARK_GROUP_NAME: &str = "dark";
+
+pub struct DesignTokens {
+    pub properties: Vec<(String, String)>, // custom property names (with the --) and their values
+    pub dark_properties: Vec<(String, String)>, // replacement values for a dark colour scheme
+    tree: Map<String, Value>,              // the whole file, for SpallTheme
+}
+
+pub fn parse_design_tokens(file_content: &str) -> Result<DesignTokens, errs::DesignTokensError> {
+    let tree = match serde_json::from_str(file_content) {
+        Ok(Value::Object(tree)) => tree,
+        Ok(_) => {
+            return Err(errs::DesignTokensError::InvalidJson {
+                reason: "expected an object of token names to values".to_string(),
+            })
+        }
+        Err(e) => {
+            return Err(errs::DesignTokensError::InvalidJson {
+                reason: e.to_string(),
+            })
+        }
+    };
+
+    let mut properties = vec![];
+    let mut dark_properties = vec![];
+    for (name, value) in &tree {
+        if name != DARK_GROUP_NAME {
+            flatten_token(&format!("--{name}"), name, value, &mut properties)?;
+            continue;
+        }
+        let Value::Object(dark_tree) = value else {
+            return Err(errs::DesignTokensError::InvalidValue {
+                name: name.clone(),
+                reason: "it has to be an object of the tokens to change in dark mode",
+            });
+        };
+        for (dark_name, dark_value) in dark_tree {
+            flatten_token(
+                &format!("--{dark_name}"),
+                dark_name,
+                dark_value,
+                &mut dark_properties,
+            )?;
+        }
+    }
+
+    // Dark mode can only change tokens, otherwise there would be no value in light mode
+    for (name, _) in &dark_properties {
+        if !properties.iter().any(|(n, _)| n == name) {
+            return Err(errs::DesignTokensError::UnknownDarkToken { name: name.clone() });
+        }
+    }
+
+    Ok(DesignTokens {
+        properties,
+        dark_properties,
+        tree,
+    })
+}
+
+impl DesignTokens {
+    /// The tokens as :root custom properties, followed by the dark mode ones under a prefers-color-scheme query.
+    /// Not yet normalized or minified
+    pub fn css(&self) -> String {
+        let declarations = |properties: &[(String, String)], indent: &str| {
+            properties
+                .iter()
+                .map(|(name, value)| format!("{indent}    {name}: {value};\n"))
+                .collect::<String>()
+        };
+
+        let mut css = format!(":root {{\n{}}}\n", declarations(&self.properties, ""));
+        if !self.dark_properties.is_empty() {
+            css += &format!(
+                "\n@media (prefers-color-scheme: dark) {{\n    :root {{\n{}    }}\n}}\n",
+                declarations(&self.dark_properties, "    ")
+            );
+        }
+        css
+    }
+
+    /// SpallTheme, as a common file declared by tokens_file
+    pub fn theme_script(&self, tokens_file: &Path) -> CommonFile {
+        CommonFile {
+            source_file: tokens_file.to_path_buf(),
+            file_name: format!("{THEME_OBJECT_NAME}.js"),
+            requires: vec![],
+            declared_names: vec![THEME_OBJECT_NAME.to_string()],
+            default_export: None,
+            default_imports: vec![],
+            content: format!("const {THEME_OBJECT_NAME} = {};", frozen_object(&self.tree)),
+        }
+    }
+}
+
+fn flatten_token(
+    property_name: &str,
+    key: &str,
+    value: &Value,
+    properties: &mut Vec<(String, String)>,
+) -> Result<(), errs::DesignTokensError> {
+    // Add the custom properties for a token, or for every token in a group
+
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(errs::DesignTokensError::InvalidName {
+            name: key.to_string(),
+        });
+    }
+
+    let invalid_value = |reason| errs::DesignTokensError::InvalidValue {
+        name: property_name.to_string(),
+        reason,
+    };
+    match value {
+        Value::Object(group) => {
+            for (child_key, child_value) in group {
+                flatten_token(
+                    &format!("{property_name}-{child_key}"),
+                    child_key,
+                    child_value,
+                    properties,
+                )?;
+            }
+        }
+        Value::String(text) if text.contains([';', '{', '}']) => {
+            return Err(invalid_value("it contains a ; { or }"));
+        }
+        Value::String(text) if text.trim().is_empty() => {
+            return Err(invalid_value("it is empty"));
+        }
+        Value::String(text) => properties.push((property_name.to_string(), text.clone())),
+        Value::Number(number) => properties.push((property_name.to_string(), number.to_string())),
+        _ => {
+            return Err(invalid_value(
+                "it has to be a string, a number or an object of more tokens",
+            ))
+        }
+    }
+    Ok(())
+}
+
+fn frozen_object(tree: &Map<String, Value>) -> String {
+    // Javascript for an object with the same contents as tree, where it and every object in it are frozen
+
+    let entries = tree
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::Object(group) => frozen_object(group),
+                other => other.to_string(),
+            };
+            format!("{}: {value}", Value::String(key.clone()))
+        })
+        .collect::<Vec<String>>();
+    format!("Object.freeze({{{}}})", entries.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flattening() {
+        let tokens = parse_design_tokens(
+            r##"{"theme": {"main": "#4780ca", "main-4": "#4479be"}, "gap": 4, "font": "'Open Sans', sans-serif"}"##,
+        )
+        .unwrap();
+        assert_eq!(
+            tokens.properties,
+            vec![
+                ("--font".to_string(), "'Open Sans', sans-serif".to_string()),
+                ("--gap".to_string(), "4".to_string()),
+                ("--theme-main".to_string(), "#4780ca".to_string()),
+                ("--theme-main-4".to_string(), "#4479be".to_string()),
+            ]
+        );
+        assert_eq!(
+            tokens.css(),
+            ":root {\n    --font: 'Open Sans', sans-serif;\n    --gap: 4;\n    --theme-main: #4780ca;\n    --theme-main-4: #4479be;\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_dark_mode() {
+        let tokens = parse_design_tokens(
+            r##"{"text": "black", "theme": {"main": "#4780ca"}, "dark": {"theme": {"main": "#213c5f"}}}"##,
+        )
+        .unwrap();
+        assert_eq!(
+            tokens.dark_properties,
+            vec![("--theme-main".to_string(), "#213c5f".to_string())]
+        );
+        assert_eq!(
+            tokens.css(),
+            ":root {\n    --text: black;\n    --theme-main: #4780ca;\n}\n\n@media (prefers-color-scheme: dark) {\n    :root {\n        --theme-main: #213c5f;\n    }\n}\n"
+        );
+
+        let result = parse_design_tokens(r##"{"text": "black", "dark": {"background": "black"}}"##);
+        assert!(matches!(
+            result,
+            Err(errs::DesignTokensError::UnknownDarkToken { name }) if name == "--background"
+        ));
+    }
+
+    #[test]
+    fn test_invalid_tokens() {
+        assert!(matches!(
+            parse_design_tokens(r##"{"theme": {"main": "red;"}}"##),
+            Err(errs::DesignTokensError::InvalidValue { name, .. }) if name == "--theme-main"
+        ));
+        assert!(matches!(
+            parse_design_tokens(r##"{"sizes": [1, 2]}"##),
+            Err(errs::DesignTokensError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            parse_design_tokens(r##"{"main colour": "red"}"##),
+            Err(errs::DesignTokensError::InvalidName { name }) if name == "main colour"
+        ));
+        assert!(matches!(
+            parse_design_tokens(r##"["red"]"##),
+            Err(errs::DesignTokensError::InvalidJson { .. })
+        ));
+    }
+
+    #[test]
+    fn test_theme_script() {
+        let tokens = parse_design_tokens(
+            r##"{"gap": 4, "theme": {"main": "#4780ca"}, "dark": {"theme": {"main": "#213c5f"}}}"##,
+        )
+        .unwrap();
+        let script = tokens.theme_script(Path::new("tokens.json"));
+        assert_eq!(script.file_name, "SpallTheme.js");
+        assert_eq!(script.declared_names, vec!["SpallTheme"]);
+        assert_eq!(
+            script.content,
+            "const SpallTheme = Object.freeze({\"dark\": Object.freeze({\"theme\": Object.freeze({\"main\": \"#213c5f\"})}), \"gap\": 4, \"theme\": Object.freeze({\"main\": \"#4780ca\"})});"
+        );
+    }
+}
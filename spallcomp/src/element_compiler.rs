@@ -1,18 +1,21 @@
 // Converts a .spall file into a javascript file
 
-use std::fs;
-use std::path::Path;
-
+use crate::common::tokeniser_utils;
 use crate::compilation_settings::*;
 use crate::errs;
+use crate::es_modules;
 use crate::logging;
+use crate::scoped_css;
+use crate::scoped_css::preprocessor::CssPartials;
 use crate::tag_attribute::TagAttribute;
 use crate::tag_type::TagType;
 use crate::{parser, tokeniser};
 
 const ROOT_ELEMENT_NAME: &str = "Root";
 // Elements that aren't put into the final markup
-const IGNORED_ELEMENT_NAMES: [&'static str; 3] = ["title", "pageroute", "script"];
+const IGNORED_ELEMENT_NAMES: [&'static str; 4] = ["title", "pageroute", "script", "style"];
+// <style> blocks with this attribute apply to the whole document rather than being scoped to the element
+const GLOBAL_STYLE_ATTRIBUTE: &str = "global";
 
 #[derive(Clone, PartialEq)]
 pub enum ElementType {
@@ -51,28 +54,20 @@ pub struct CompiledElement {
     pub content: String,
     pub element_name: String,
     pub compiled_element_name: String,
+    pub element_type: ElementType,
+    pub page_routes: Vec<String>,
+    pub referenced_elements: Vec<String>, // names of the elements used in the markup, without duplicates
+    pub markup_names: MarkupNames,
+    pub styles: Vec<String>, // compiled CSS of the <style> blocks, for the CSS bundle
 }
 
-pub fn compile_element_file(
-    file_path: &Path,
-    compilation_settings: &CompilationSettings,
-    element_type: ElementType,
-    element_id: i32,
-) -> Result<CompiledElement, errs::FileCompilationError> {
-    // todo: if is not a .spall file: crash
-
-    let file_content = fs::read_to_string(file_path).expect(&format!(
-        "Failed reading element file: {}",
-        file_path.to_string_lossy()
-    ));
-    let element_name = file_path.file_stem().unwrap().to_str().unwrap();
-    compile_element(
-        &file_content,
-        &element_name,
-        compilation_settings,
-        element_type,
-        element_id,
-    )
+// Tag and class names used in an element's own markup (not its child elements'), for checking its scoped CSS against
+#[derive(Default)]
+pub struct MarkupNames {
+    pub tags: Vec<String>, // lowercase
+    pub classes: Vec<String>,
+    pub has_dynamic_classes: bool, // some classes are only known at render time (eg class="${x}" or !class)
+    pub has_child_elements: bool, // so descendant selectors might match markup that isn't listed here
 }
 
 // How the general flow of compilation works:
@@ -90,6 +85,7 @@ pub fn compile_element(
     compilation_settings: &CompilationSettings,
     element_type: ElementType,
     element_id: i32,
+    css_partials: &CssPartials,
 ) -> Result<CompiledElement, errs::FileCompilationError> {
     // Preparation
 
@@ -132,7 +128,24 @@ pub fn compile_element(
 
     logging::log_per_step("Actually compiling", compilation_settings.log_level);
     let class_body = find_class_body(&tree).unwrap_or("".to_string());
-    let mut chunks = compile_chunks_from_tree(&tree);
+    let styles = compile_style_blocks(
+        &tree,
+        file_content,
+        element_name,
+        css_partials,
+        compilation_settings,
+    )?;
+    let app_reference = match compilation_settings.output_format {
+        OutputFormat::Scripts => "SpallApp.instance".to_string(),
+        OutputFormat::EsModules => format!("window.{}", es_modules::APP_GLOBAL_NAME),
+    };
+    let mut chunks = compile_chunks_from_tree(
+        &tree,
+        &app_reference,
+        compilation_settings.preserve_html_comments,
+    );
+    let referenced_elements = find_referenced_elements(&chunks);
+    let markup_names = find_markup_names(&tree);
     chunks = concat_successive_compile_chunks(&chunks);
     let compiled_render_func = compile_chunks(&chunks);
 
@@ -156,9 +169,13 @@ pub fn compile_element(
             )
         }
     };
+    let export_keyword = match compilation_settings.output_format {
+        OutputFormat::Scripts => "",
+        OutputFormat::EsModules => "export ",
+    };
     let mut result = format!(
         r#"
-        class {compiled_element_name} extends {base_class} {{
+        {export_keyword}class {compiled_element_name} extends {base_class} {{
             constructor(id, parentId, spallApp, path) {{
                 super('{element_name}', id, parentId, spallApp, path);
             }}
@@ -174,14 +191,13 @@ pub fn compile_element(
     "#
     );
 
+    let mut page_routes = vec![];
     if element_type == ElementType::Page {
-        // add code to register as page
-
-        let mut page_routes = vec![];
-        // add code to register as page
-        tree.depth_first_map(&mut |node, _is_entering| {
+        // add code to register as page.
+        // Each node is visited when entering and leaving it, so only take routes when entering or they'd be listed twice
+        tree.depth_first_map(&mut |node, is_entering| {
             if let parser::NodeData::Markup(inner_data) = &node.data {
-                if inner_data.tag_name == "pageroute" {
+                if inner_data.tag_name == "pageroute" && is_entering {
                     page_routes.push(inner_data.inner_text.clone());
                 }
             };
@@ -191,16 +207,51 @@ pub fn compile_element(
             return Err(errs::FileCompilationError::NoPageRoutes);
         }
 
-        result += &compile_all_page_routes(&page_routes, &compiled_element_name);
+        // When pages are split into chunks, the route table is in the main bundle instead (see page_chunks.rs)
+        if !compilation_settings.split_pages {
+            result += &compile_all_page_routes(
+                &page_routes,
+                &compiled_element_name,
+                compilation_settings.output_format,
+            );
+        }
     }
 
     Ok(CompiledElement {
         content: result,
         element_name: element_name.to_string(),
         compiled_element_name: compiled_element_name.to_string(),
+        element_type,
+        page_routes,
+        referenced_elements,
+        markup_names,
+        styles,
     })
 }
 
+/// Compile an element with logging turned off, for testing the build steps that use compiled elements
+#[cfg(test)]
+pub fn compile_test_element(
+    file_content: &str,
+    element_name: &str,
+    element_type: ElementType,
+    compilation_settings: CompilationSettings,
+) -> CompiledElement {
+    let compilation_settings = CompilationSettings {
+        log_level: CompilationLogLevel::Silent,
+        ..compilation_settings
+    };
+    compile_element(
+        file_content,
+        element_name,
+        &compilation_settings,
+        element_type,
+        1,
+        &CssPartials::new(),
+    )
+    .unwrap()
+}
+
 fn generate_compiled_element_name(element_name: &str) -> String {
     format!("__SpallCompiled{element_name}")
 }
@@ -224,6 +275,7 @@ fn debug_tokens(tokens: &Vec<tokeniser::Token>) {
             tokeniser::Token::Tag(inner_data) => inner_data.to_string(),
             tokeniser::Token::Content(inner_data) => inner_data.to_string(),
             tokeniser::Token::InlineJavascript(inner_data) => inner_data.to_string(),
+            tokeniser::Token::Comment(inner_data) => inner_data.to_string(),
         })
         .collect::<Vec<String>>()
         .join(" ");
@@ -238,6 +290,12 @@ fn check_token_syntax(tokens: &Vec<tokeniser::Token>) -> Result<(), errs::Markup
                     tag_name: tag.name.clone(),
                 });
             }
+            if tag.tag_type == TagType::End && tokeniser::VOID_ELEMENTS.contains(&tag.name.as_str())
+            {
+                return Err(errs::MarkupSyntaxError::VoidElementEndTag {
+                    tag_name: tag.name.clone(),
+                });
+            }
         }
     }
     Ok(())
@@ -248,6 +306,151 @@ fn escape_quotes(data: &str, quote_char: char, escape_char: char) -> String {
         .replace(quote_char, format!("{escape_char}{quote_char}").as_str())
 }
 
+fn escape_param_quotes(value: &str) -> String {
+    // Static element parameters are written out as "double quoted" Javascript strings, so escape any quotes in them
+    // that aren't already escaped. Other backslash escapes are left for Javascript to read
+
+    let mut result = String::with_capacity(value.len());
+    let mut is_escaped = false;
+    for char in value.chars() {
+        match char {
+            '"' if !is_escaped => result += "\\\"",
+            '\n' => result += "\\n",
+            _ => result.push(char),
+        }
+        is_escaped = char == '\\' && !is_escaped;
+    }
+    result
+}
+
+fn escape_attribute_quotes(value: &str) -> String {
+    // Values are always written out in double quotes, but they might have been in single quotes (or none) in the source.
+    // Quotes inside ${} are part of the Javascript, so are left alone
+
+    let mut result = String::with_capacity(value.len());
+    let mut interpolation_depth = 0;
+    let mut chars = value.chars().peekable();
+    while let Some(char) = chars.next() {
+        match char {
+            // An escaped \${ isn't an interpolation, so its quotes still need escaping
+            '\\' if interpolation_depth == 0 && chars.peek() == Some(&'$') => {
+                result.push(char);
+                result.push(chars.next().unwrap_or_default());
+                continue;
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                interpolation_depth += 1;
+                result.push(char);
+                result.push(chars.next().unwrap_or_default());
+                continue;
+            }
+            '{' if interpolation_depth > 0 => interpolation_depth += 1,
+            '}' if interpolation_depth > 0 => interpolation_depth -= 1,
+            '"' if interpolation_depth == 0 => {
+                result += "&quot;";
+                continue;
+            }
+            _ => (),
+        }
+        result.push(char);
+    }
+    result
+}
+
+// Javascript that inserts the text "${" into a template string
+const LITERAL_INTERPOLATION_START: &str = "${'${'}";
+
+fn escape_interpolation(data: &str) -> String {
+    // Stop ${} being run when data is put into a markup template string, by making each ${ insert the text "${".
+    // (A \ before it wouldn't work, since escape_quotes escapes the backslash)
+
+    data.replace("${", LITERAL_INTERPOLATION_START)
+}
+
+fn unescape_interpolation(data: &str) -> String {
+    // Markup text and attribute values can have \${ for a literal ${, which needs the same treatment once it's in the
+    // template string
+
+    data.replace("\\${", LITERAL_INTERPOLATION_START)
+}
+
+// Runtime functions that make an interpolated value safe to put into markup, and the function that marks a value as
+// trusted HTML that doesn't need escaping
+const ESCAPE_HTML_FUNCTION: &str = "SpallMarkup.escapeHtml";
+const ESCAPE_ATTRIBUTE_FUNCTION: &str = "SpallMarkup.escapeAttribute";
+const ESCAPE_HANDLER_FUNCTION: &str = "SpallMarkup.escapeHandlerValue";
+const RAW_FUNCTION_NAME: &str = "raw";
+
+fn escape_interpolated_values(data: &str, escape_function: &str) -> String {
+    // Wrap the Javascript in each ${} so that its value is escaped when rendered, apart from ${raw(x)} which becomes ${x}.
+    // A \${ is a literal ${ so is left alone
+
+    let mut result = String::with_capacity(data.len());
+    let mut remaining = data;
+    while let Some(start) = remaining.find("${") {
+        let is_literal = remaining[..start].ends_with('\\');
+        result += &remaining[..start + 2];
+        remaining = &remaining[start + 2..];
+        if is_literal {
+            continue;
+        }
+        // An unclosed ${ is broken Javascript anyway, so leave it for the minifier or browser to complain about
+        let Some(length) = interpolation_length(remaining) else {
+            break;
+        };
+        let expression = &remaining[..length];
+        match raw_function_argument(expression) {
+            Some(argument) => result += argument,
+            None => result += &format!("{escape_function}({expression})"),
+        }
+        remaining = &remaining[length..];
+    }
+    result + remaining
+}
+
+fn interpolation_length(data: &str) -> Option<usize> {
+    // Find the length of the Javascript at the start of data, up to the } that closes the ${ before it.
+    // Braces inside strings don't count
+
+    let mut bracket_depth = 0;
+    let mut chars = data.char_indices();
+    while let Some((idx, char)) = chars.next() {
+        match char {
+            '\'' | '"' => {
+                let string = tokeniser_utils::read_string(char, '\\', &data[idx..]);
+                chars.nth(string.chars().count().saturating_sub(2));
+            }
+            '{' => bracket_depth += 1,
+            '}' if bracket_depth == 0 => return Some(idx),
+            '}' => bracket_depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+fn raw_function_argument(expression: &str) -> Option<&str> {
+    // If the whole expression is a call to raw(), get what's passed to it
+
+    let argument = expression
+        .trim()
+        .strip_prefix(RAW_FUNCTION_NAME)?
+        .trim_start()
+        .strip_prefix('(')?
+        .strip_suffix(')')?;
+    // Make sure the brackets are the same call, rather than something like raw(a) + raw(b)
+    let mut bracket_depth = 0;
+    for char in argument.chars() {
+        match char {
+            '(' => bracket_depth += 1,
+            ')' if bracket_depth == 0 => return None,
+            ')' => bracket_depth -= 1,
+            _ => (),
+        }
+    }
+    Some(argument)
+}
+
 fn find_class_body(tree: &parser::Tree) -> Option<String> {
     let mut result = None;
     tree.depth_first_map(&mut |node, _is_entering| {
@@ -260,7 +463,127 @@ fn find_class_body(tree: &parser::Tree) -> Option<String> {
     result
 }
 
-fn compile_chunks_from_tree(tree: &parser::Tree) -> Vec<CompileChunk> {
+fn compile_style_blocks(
+    tree: &parser::Tree,
+    file_content: &str,
+    element_name: &str,
+    css_partials: &CssPartials,
+    compilation_settings: &CompilationSettings,
+) -> Result<Vec<String>, errs::FileCompilationError> {
+    // Compile the CSS in the element's <style> blocks, which is scoped in the same way as styles/{element_name}.css.
+    // They have to be at the top level of the file, as a <style> inside other markup looks like it would only apply there.
+    // Lines in CSS errors are moved along to be lines of the whole file rather than of the block
+
+    let mut styles = vec![];
+    for (index, node) in tree.nodes.iter().enumerate() {
+        let parser::NodeData::Markup(inner_data) = &node.data else {
+            continue;
+        };
+        if inner_data.tag_name != "style" {
+            continue;
+        }
+        if node.parent != Some(tree.root) {
+            return Err(errs::FileCompilationError::MarkupSyntaxError(
+                errs::MarkupSyntaxError::NestedStyleTag,
+            ));
+        }
+        let is_global = inner_data
+            .tag_attributes
+            .iter()
+            .any(|attribute| attribute.name == GLOBAL_STYLE_ATTRIBUTE);
+        logging::log_per_step(
+            format!("Compiling <style> block {index}").as_str(),
+            compilation_settings.log_level,
+        );
+        let result = if is_global {
+            scoped_css::compiler::compile_global_css(
+                &inner_data.inner_text,
+                css_partials,
+                compilation_settings,
+            )
+        } else {
+            scoped_css::compiler::compile_scoped_css(
+                &inner_data.inner_text,
+                element_name,
+                css_partials,
+                compilation_settings,
+            )
+        };
+        styles.push(result.map_err(|e| match e {
+            errs::FileCompilationError::CssSourceError(mut e) if e.partial_name.is_none() => {
+                e.line += file_content[..inner_data.inner_text_offset]
+                    .matches('\n')
+                    .count();
+                errs::FileCompilationError::CssSourceError(e)
+            }
+            e => e,
+        })?);
+    }
+    Ok(styles)
+}
+
+fn find_referenced_elements(chunks: &[CompileChunk]) -> Vec<String> {
+    let mut referenced_elements: Vec<String> = vec![];
+    for chunk in chunks {
+        if let CompileChunk::Renderable(renderables) = chunk {
+            for renderable in renderables {
+                if let Renderable::Element { tag_name, .. } = renderable {
+                    if !referenced_elements.contains(tag_name) {
+                        referenced_elements.push(tag_name.clone());
+                    }
+                }
+            }
+        }
+    }
+    referenced_elements
+}
+
+fn find_markup_names(tree: &parser::Tree) -> MarkupNames {
+    let mut markup_names = MarkupNames::default();
+    tree.depth_first_map(&mut |node, is_entering| {
+        let parser::NodeData::Markup(inner_data) = &node.data else {
+            return;
+        };
+        let is_html_tag = inner_data
+            .tag_name
+            .chars()
+            .next()
+            .is_some_and(|c| !c.is_uppercase());
+        if !is_entering || IGNORED_ELEMENT_NAMES.contains(&inner_data.tag_name.as_str()) {
+            return;
+        }
+        if !is_html_tag {
+            // (the root of the tree has no tag name)
+            markup_names.has_child_elements |= !inner_data.tag_name.is_empty();
+            return;
+        }
+        let tag_name = inner_data.tag_name.to_lowercase();
+        if !markup_names.tags.contains(&tag_name) {
+            markup_names.tags.push(tag_name);
+        }
+        for attribute in inner_data
+            .tag_attributes
+            .iter()
+            .filter(|a| a.name == "class")
+        {
+            if attribute.is_dynamic || attribute.value.contains("${") {
+                markup_names.has_dynamic_classes = true;
+            }
+            for class in attribute.value.split_whitespace() {
+                if !markup_names.classes.iter().any(|c| c == class) {
+                    markup_names.classes.push(class.to_string());
+                }
+            }
+        }
+    });
+    markup_names
+}
+
+fn compile_chunks_from_tree(
+    tree: &parser::Tree,
+    app_reference: &str,
+    preserve_html_comments: bool,
+) -> Vec<CompileChunk> {
     let mut chunks = vec![];
     // I don't know why the code for tracking the path stack works, but it does
     let mut path_stack = vec![0];
@@ -287,17 +610,33 @@ fn compile_chunks_from_tree(tree: &parser::Tree) -> Vec<CompileChunk> {
             // generate a compile chunk
             match &node.data {
                 parser::NodeData::Markup(inner_data) => {
-                    let renderable = renderable_from_node_visit(inner_data, is_entering, &path);
+                    let renderable =
+                        renderable_from_node_visit(inner_data, is_entering, &path, app_reference);
                     match renderable {
                         Some(v) => chunks.push(CompileChunk::Renderable(vec![v])),
                         _ => (),
                     }
                 }
+                parser::NodeData::Text(inner_data) => {
+                    if is_entering {
+                        chunks.push(CompileChunk::Renderable(vec![Renderable::Markup(
+                            compile_text(&inner_data.value),
+                        )]));
+                    }
+                }
                 parser::NodeData::InlineJavascript(inner_data) => {
                     if is_entering {
                         chunks.push(CompileChunk::Javascript(inner_data.value.clone()));
                     }
                 }
+                parser::NodeData::Comment(inner_data) => {
+                    if is_entering && preserve_html_comments {
+                        chunks.push(CompileChunk::Renderable(vec![Renderable::Markup(format!(
+                            "<!--{}-->",
+                            escape_interpolation(&inner_data.value)
+                        ))]));
+                    }
+                }
             }
         }
     });
@@ -308,6 +647,7 @@ fn renderable_from_node_visit(
     node_data: &parser::NodeMarkupData,
     is_entering: bool,
     path: &str,
+    app_reference: &str,
 ) -> Option<Renderable> {
     if IGNORED_ELEMENT_NAMES.contains(&node_data.tag_name.as_str()) {
         return None;
@@ -335,34 +675,89 @@ fn renderable_from_node_visit(
             None
         }
     } else {
-        let tag_attributes = compile_tag_attributes(&node_data.tag_attributes, path);
+        let tag_attributes = compile_tag_attributes(&node_data.tag_attributes, path, app_reference);
+        // Only void elements can be self-closing in HTML, a browser would read <div /> as an unclosed <div>
+        let is_void = tokeniser::VOID_ELEMENTS.contains(&node_data.tag_name.as_str());
         let markup_string = match (node_data.is_standalone, is_entering) {
-            (true, true) => format!("<{} {}/>", node_data.tag_name, tag_attributes),
-            (true, false) => return None,
-            (false, true) => format!(
-                "<{} {}>{}",
-                node_data.tag_name, tag_attributes, node_data.inner_text
+            (true, true) if is_void => format!("<{} {}/>", node_data.tag_name, tag_attributes),
+            (true, true) => format!(
+                "<{} {}></{}>",
+                node_data.tag_name, tag_attributes, node_data.tag_name
             ),
+            (true, false) => return None,
+            (false, true) => format!("<{} {}>", node_data.tag_name, tag_attributes),
             (false, false) => format!("</{}>", node_data.tag_name),
         };
         Some(Renderable::Markup(markup_string))
     }
 }
 
-fn compile_tag_attributes(tag_attributes: &Vec<TagAttribute>, _tag_path: &str) -> String {
+fn compile_text(text: &str) -> String {
+    unescape_interpolation(&escape_interpolated_values(
+        &escape_less_thans(text),
+        ESCAPE_HTML_FUNCTION,
+    ))
+}
+
+fn escape_less_thans(text: &str) -> String {
+    // Raw text like the content of a <code> can contain a <, which mustn't start a tag in the final markup.
+    // A < inside ${} is part of the Javascript so is left alone
+
+    let mut result = String::with_capacity(text.len());
+    let mut remaining = text;
+    while let Some(start) = remaining.find("${") {
+        let is_literal = remaining[..start].ends_with('\\');
+        result += &remaining[..start + 2].replace('<', "&lt;");
+        remaining = &remaining[start + 2..];
+        if is_literal {
+            continue;
+        }
+        let Some(length) = interpolation_length(remaining) else {
+            break;
+        };
+        result += &remaining[..length];
+        remaining = &remaining[length..];
+    }
+    result + &remaining.replace('<', "&lt;")
+}
+
+// inline on* attributes are parsed as javascript by the browser
+fn is_event_handler(attribute_name: &str) -> bool {
+    attribute_name.to_ascii_lowercase().starts_with("on")
+}
+
+fn compile_tag_attributes(
+    tag_attributes: &Vec<TagAttribute>,
+    _tag_path: &str,
+    app_reference: &str, // how to get the SpallApp from the global scope that inline event handlers run in
+) -> String {
     tag_attributes
         .iter()
         .map(|x| {
             // for this.x() callbacks, get context for the "this" by lookups through the renderer
             if x.is_dynamic && x.value.starts_with("this.") {
-                let this_removed = x.value.replacen("this.", "", 1);
+                let this_removed = escape_interpolated_values(
+                    &escape_attribute_quotes(&x.value.replacen("this.", "", 1)),
+                    ESCAPE_HANDLER_FUNCTION,
+                );
                 // take advantage of the way that strings are inserted into js to inject some stuff from runtime into the html
                 format!(
-                    "{}=\"SpallApp.instance.renderer.getElementById(${{this.id}}).{}\"",
+                    "{}=\"{app_reference}.renderer.getElementById(${{this.id}}).{}\"",
                     x.name, this_removed
                 )
             } else {
-                format!("{}=\"{}\"", x.name, x.value)
+                format!(
+                    "{}=\"{}\"",
+                    x.name,
+                    unescape_interpolation(&escape_interpolated_values(
+                        &escape_attribute_quotes(&x.value),
+                        if is_event_handler(&x.name) {
+                            ESCAPE_HANDLER_FUNCTION
+                        } else {
+                            ESCAPE_ATTRIBUTE_FUNCTION
+                        }
+                    ))
+                )
             }
         })
         .collect::<Vec<String>>()
@@ -482,7 +877,7 @@ fn renderables_to_string(renderables: &Vec<Renderable>) -> String {
                     .map(|p| if p.is_dynamic {
                         format!("{}:() => {}", p.name, p.value)
                     } else {
-                        format!("{}:() => \"{}\"", p.name, p.value)
+                        format!("{}:() => \"{}\"", p.name, escape_param_quotes(&p.value))
                     })
                     .collect::<Vec<String>>()
                     .join(",")
@@ -494,18 +889,30 @@ fn renderables_to_string(renderables: &Vec<Renderable>) -> String {
     stringified_renderables.join(", ")
 }
 
-fn compile_all_page_routes(raw_page_routes: &Vec<String>, element_name: &str) -> String {
-    raw_page_routes
+fn compile_all_page_routes(
+    raw_page_routes: &Vec<String>,
+    element_name: &str,
+    output_format: OutputFormat,
+) -> String {
+    // Scripts register pages into the global route list,
+    // while modules export their routes for the entry module to pass to the router
+    let compiled_routes = raw_page_routes
         .iter()
-        .map(|route| {
-            let compiled_route = compile_page_route(route);
-            format!("SpallRouter.routeList.push([{compiled_route},{element_name}]);")
-        })
-        .collect::<Vec<String>>()
-        .join("\n")
+        .map(|route| format!("[{},{element_name}]", compile_page_route(route)));
+    match output_format {
+        OutputFormat::Scripts => compiled_routes
+            .map(|route| format!("SpallRouter.routeList.push({route});"))
+            .collect::<Vec<String>>()
+            .join("\n"),
+        OutputFormat::EsModules => format!(
+            "export const {} = [{}];",
+            es_modules::PAGE_ROUTES_EXPORT_NAME,
+            compiled_routes.collect::<Vec<String>>().join(",")
+        ),
+    }
 }
 
-fn compile_page_route(raw_route: &str) -> String {
+pub(crate) fn compile_page_route(raw_route: &str) -> String {
     let sections = raw_route.split('/').filter(|x| !x.trim().is_empty());
     // todo: if sections contain ${} but it's not at the start + end then cry.
     // todo: check validity of route name
@@ -1,4 +1,60 @@
 use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Serializer};
+
+pub use crate::dependency_order::DependencyError;
+
+pub(crate) fn io_error(
+    action: &'static str,
+    path: &Path,
+) -> impl FnOnce(io::Error) -> CompilationError {
+    // For use with map_err - converts an io error into our own error type
+
+    let path = path.to_path_buf();
+    move |error| {
+        CompilationError::Project(ProjectCompilationError::Io {
+            action,
+            path,
+            error,
+        })
+    }
+}
+
+/// Everything that went wrong in a failed compilation, plus any warnings found before it failed
+#[derive(Debug)]
+pub struct Diagnostics {
+    pub errors: Vec<CompilationError>,
+    pub warnings: Vec<CompilationWarning>,
+}
+
+impl Diagnostics {
+    pub fn new(errors: Vec<CompilationError>) -> Diagnostics {
+        Diagnostics {
+            errors,
+            warnings: vec![],
+        }
+    }
+}
+
+impl From<CompilationError> for Diagnostics {
+    fn from(error: CompilationError) -> Diagnostics {
+        Diagnostics::new(vec![error])
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let lines = self
+            .warnings
+            .iter()
+            .map(|w| format!("Warning: {w}"))
+            .chain(self.errors.iter().map(|e| e.to_string()))
+            .collect::<Vec<String>>();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
 
 #[derive(Debug)]
 pub enum CompilationError {
@@ -24,15 +80,35 @@ impl fmt::Display for CompilationError {
 
 #[derive(Debug)]
 pub enum ProjectCompilationError {
+    NoProjectDirectory {
+        path: PathBuf,
+    },
     NoElementsDirectory,
     NoMetaDirectory,
     NoRootElement,
     NoMetaIndex,
+    Io {
+        action: &'static str, // eg "reading" or "creating"
+        path: PathBuf,
+        error: io::Error,
+    },
+    InvalidIgnoreFile {
+        line_number: usize,
+        line: String,
+        reason: String,
+    },
+    CommonFileDependencies(DependencyError),
+    NoGlobalStylesheet {
+        path: PathBuf,
+    },
 }
 
 impl fmt::Display for ProjectCompilationError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
+            ProjectCompilationError::NoProjectDirectory { path } => {
+                write!(f, "Project directory {} does not exist", path.to_string_lossy())
+            }
             ProjectCompilationError::NoElementsDirectory => {
                 write!(f, "Could not find elements/ directory, are you sure there is a spall project located here?")
             }
@@ -41,6 +117,27 @@ impl fmt::Display for ProjectCompilationError {
                 write!(f, "No root element (elements/Root.spall) defined.")
             }
             ProjectCompilationError::NoMetaIndex => write!(f, "No index.html defined in meta/ dir"),
+            ProjectCompilationError::Io {
+                action,
+                path,
+                error,
+            } => write!(f, "Failed {action} {}: {error}", path.to_string_lossy()),
+            ProjectCompilationError::InvalidIgnoreFile {
+                line_number,
+                line,
+                reason,
+            } => write!(
+                f,
+                "Invalid pattern \"{line}\" on line {line_number} of .spallignore: {reason}"
+            ),
+            ProjectCompilationError::NoGlobalStylesheet { path } => write!(
+                f,
+                "Global stylesheet {} does not exist",
+                path.to_string_lossy()
+            ),
+            ProjectCompilationError::CommonFileDependencies(inner_error) => {
+                write!(f, "Could not order the files in common/. {inner_error}")
+            }
         }
     }
 }
@@ -50,7 +147,12 @@ pub enum FileCompilationError {
     InvalidElementName { name: String },
     NoPageRoutes,
     CssSyntaxError(CssSyntaxError),
+    CssSourceError(CssSourceError),
     MarkupSyntaxError(MarkupSyntaxError),
+    ScopedCssError(ScopedCssError),
+    GlobalCssError(GlobalCssError),
+    DesignTokensError(DesignTokensError),
+    CommonFileError(CommonFileError),
 }
 
 impl fmt::Display for FileCompilationError {
@@ -63,7 +165,114 @@ impl fmt::Display for FileCompilationError {
                 write!(f, "No page route was defined")
             }
             FileCompilationError::CssSyntaxError(e) => e.fmt(f),
+            FileCompilationError::CssSourceError(e) => e.fmt(f),
             FileCompilationError::MarkupSyntaxError(e) => e.fmt(f),
+            FileCompilationError::ScopedCssError(e) => e.fmt(f),
+            FileCompilationError::GlobalCssError(e) => e.fmt(f),
+            FileCompilationError::DesignTokensError(e) => e.fmt(f),
+            FileCompilationError::CommonFileError(e) => e.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScopedCssError {
+    NoMatchingSpall { element_name: String },
+}
+
+impl fmt::Display for ScopedCssError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScopedCssError::NoMatchingSpall { element_name } => write!(
+                f,
+                "No element or page called \"{element_name}\" to apply these styles to (expected elements/{element_name}.spall or pages/{element_name}.spall)"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum GlobalCssError {
+    ImportNotFound { url: String },
+    CircularImport { url: String },
+}
+
+impl fmt::Display for GlobalCssError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GlobalCssError::ImportNotFound { url } => {
+                write!(f, "Imported stylesheet \"{url}\" does not exist")
+            }
+            GlobalCssError::CircularImport { url } => {
+                write!(f, "Importing \"{url}\" would make it import itself")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DesignTokensError {
+    InvalidJson { reason: String },
+    InvalidName { name: String },
+    InvalidValue { name: String, reason: &'static str },
+    UnknownDarkToken { name: String },
+}
+
+impl fmt::Display for DesignTokensError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DesignTokensError::InvalidJson { reason } => {
+                write!(f, "Design tokens are not valid JSON: {reason}")
+            }
+            DesignTokensError::InvalidName { name } => write!(
+                f,
+                "Token name \"{name}\" is not valid (only letters, digits, - and _ are allowed)"
+            ),
+            DesignTokensError::InvalidValue { name, reason } => {
+                write!(f, "Value of token \"{name}\" is not valid because {reason}")
+            }
+            DesignTokensError::UnknownDarkToken { name } => write!(
+                f,
+                "Dark mode token \"{name}\" has no normal value to replace"
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CommonFileError {
+    NamespaceImport {
+        namespace: String,
+        specifier: String,
+    },
+    AnonymousDefaultExport,
+    MissingDefaultExport {
+        local_name: String,
+        file_name: String,
+    },
+}
+
+impl fmt::Display for CommonFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommonFileError::NamespaceImport {
+                namespace,
+                specifier,
+            } => write!(
+                f,
+                "Namespace import \"* as {namespace}\" from \"{specifier}\" is not supported since common files share one scope (import the names you use instead)"
+            ),
+            CommonFileError::AnonymousDefaultExport => write!(
+                f,
+                "export default needs a named class or function, eg export default class WeatherService {{ ... }}"
+            ),
+            CommonFileError::MissingDefaultExport {
+                local_name,
+                file_name,
+            } => write!(
+                f,
+                "\"{local_name}\" is imported as the default export of {file_name}, but it doesn't export a class or function as default"
+            ),
         }
     }
 }
@@ -71,12 +280,80 @@ impl fmt::Display for FileCompilationError {
 #[derive(Debug)]
 pub enum CssSyntaxError {
     UnexpectedEndOfFile,
+    UnexpectedBlockEnd,
+    InvalidSelector {
+        selector: String,
+        reason: &'static str,
+    },
+    MissingColon {
+        property_name: String,
+    },
+    DeclarationOutsideRule {
+        declaration: String,
+    },
+    ParentSelectorOutsideRule {
+        selector: String,
+    },
+    UndefinedVariable {
+        name: String,
+    },
+    UnknownPartial {
+        name: String,
+    },
+    CircularUse {
+        name: String,
+    },
 }
 
 impl fmt::Display for CssSyntaxError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             CssSyntaxError::UnexpectedEndOfFile => write!(f, "Unexpected end of file"),
+            CssSyntaxError::UnexpectedBlockEnd => write!(f, "Unexpected }} without a matching {{"),
+            CssSyntaxError::InvalidSelector { selector, reason } => {
+                write!(f, "Selector \"{selector}\" is not valid because {reason}")
+            }
+            CssSyntaxError::MissingColon { property_name } => {
+                write!(f, "Expected a : after property \"{property_name}\"")
+            }
+            CssSyntaxError::DeclarationOutsideRule { declaration } => {
+                write!(f, "Property \"{declaration}\" is not inside a rule")
+            }
+            CssSyntaxError::ParentSelectorOutsideRule { selector } => write!(
+                f,
+                "Selector \"{selector}\" uses & but is not nested inside another rule"
+            ),
+            CssSyntaxError::UndefinedVariable { name } => {
+                write!(f, "Variable ${name} is not defined")
+            }
+            CssSyntaxError::UnknownPartial { name } => write!(
+                f,
+                "There is no partial called \"{name}\" (expected styles/_partials/{name}.css)"
+            ),
+            CssSyntaxError::CircularUse { name } => {
+                write!(f, "Partial \"{name}\" ends up using itself through @use")
+            }
+        }
+    }
+}
+
+/// A CSS error along with the line it was found on
+#[derive(Debug)]
+pub struct CssSourceError {
+    pub line: usize,
+    pub partial_name: Option<String>, // set when the error is in a partial that was pulled in with @use
+    pub error: CssSyntaxError,
+}
+
+impl fmt::Display for CssSourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.partial_name {
+            Some(partial_name) => write!(
+                f,
+                "Line {} of partial \"{partial_name}\": {}",
+                self.line, self.error
+            ),
+            None => write!(f, "Line {}: {}", self.line, self.error),
         }
     }
 }
@@ -87,6 +364,8 @@ pub enum MarkupSyntaxError {
     UnbalancedTag(UnbalancedTag),
     OrphanedNode,
     UnmatchedTokenTypes,
+    NestedStyleTag,
+    VoidElementEndTag { tag_name: String },
 }
 
 impl fmt::Display for MarkupSyntaxError {
@@ -102,6 +381,13 @@ impl fmt::Display for MarkupSyntaxError {
             MarkupSyntaxError::UnmatchedTokenTypes => {
                 format!("Token types do not match (have you missed an open/close tag?)")
             }
+            MarkupSyntaxError::NestedStyleTag => {
+                "<style> blocks must be at the top level of the file, not inside other tags"
+                    .to_string()
+            }
+            MarkupSyntaxError::VoidElementEndTag { tag_name } => {
+                format!("<{tag_name}> can't have any content, so it doesn't need a </{tag_name}>")
+            }
         };
         write!(f, "Syntax error in markup. {main_text}")
     }
@@ -139,3 +425,83 @@ impl fmt::Display for UnbalancedTag {
         }
     }
 }
+
+// Problems that don't stop compilation but that the user should know about
+#[derive(Debug)]
+pub enum CompilationWarning {
+    DuplicatePageRoute {
+        route: String,
+        page_names: Vec<String>,
+    },
+    UnexpectedFile {
+        path: PathBuf, // relative to project dir
+        expected_extension: &'static str,
+    },
+    UnusedCommonFile {
+        path: PathBuf, // relative to project dir
+    },
+    UnmatchedSelector {
+        path: PathBuf, // relative to project dir
+        selector: String,
+        element_name: String,
+    },
+    UnresolvedCssUrl {
+        path: PathBuf, // relative to project dir
+        url: String,
+    },
+    UnknownCssVariable {
+        path: PathBuf, // relative to project dir
+        name: String,  // including the --
+    },
+}
+
+impl fmt::Display for CompilationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompilationWarning::DuplicatePageRoute { route, page_names } => write!(
+                f,
+                "Route \"{route}\" is used by multiple pages ({})",
+                page_names.join(", ")
+            ),
+            CompilationWarning::UnexpectedFile {
+                path,
+                expected_extension,
+            } => write!(
+                f,
+                "Skipping {} (expected only .{expected_extension} files, add it to .spallignore to hide this warning)",
+                path.to_string_lossy()
+            ),
+            CompilationWarning::UnusedCommonFile { path } => write!(
+                f,
+                "{} is not used by any element or page",
+                path.to_string_lossy()
+            ),
+            CompilationWarning::UnmatchedSelector {
+                path,
+                selector,
+                element_name,
+            } => write!(
+                f,
+                "Selector \"{selector}\" in {} does not match any tag or class in the markup of {element_name}",
+                path.to_string_lossy()
+            ),
+            CompilationWarning::UnresolvedCssUrl { path, url } => write!(
+                f,
+                "url({url}) in {} is not a file in static/, so it is left as it is",
+                path.to_string_lossy()
+            ),
+            CompilationWarning::UnknownCssVariable { path, name } => write!(
+                f,
+                "var({name}) in {} is not a design token or a custom property set in the project's CSS",
+                path.to_string_lossy()
+            ),
+        }
+    }
+}
+
+// Warnings are written into the build manifest as plain messages
+impl Serialize for CompilationWarning {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,417 @@
+// Packages the compiled Javascript as ES modules (OutputFormat::EsModules).
+// Every element, page and common file becomes its own module in scripts/modules/, importing what it uses from
+// framework.js and the other modules. What a module uses is found by looking for the names that the others export,
+// the same way as finding which common files are used.
+// bundle.js becomes the entry module - it imports the root element and the routes of every page and starts the app.
+// When pages are split, the entry module doesn't import the pages - its route table loads them with import() instead.
+
+use crate::common_files::{self, CommonFile};
+use crate::element_compiler::{self, CompiledElement, ElementType};
+use crate::output_assets::{self, ScriptFile};
+
+// Inline event handlers run in the global scope, so they need one global to find the app through
+pub const APP_GLOBAL_NAME: &str = "__spallApp";
+// What each page module exports its [route sections, page class] list as
+pub const PAGE_ROUTES_EXPORT_NAME: &str = "__spallRoutes";
+
+const MODULES_DIR_NAME: &str = "scripts/modules";
+const COMMON_MODULES_DIR_NAME: &str = "common";
+const ROOT_ELEMENT_NAME: &str = "Root";
+
+pub struct EsModuleOutput {
+    pub entry: String,
+    pub modules: Vec<ScriptFile>,
+}
+
+// A module that hasn't been given its final directory yet. location is relative to the modules dir
+struct ModuleSource {
+    location: String,
+    exports: Vec<String>,
+    content: String,
+}
+
+/// Turn compiled elements/pages and (ordered) common files into modules.
+///
+/// `framework_name` is the output name of framework.js and `framework_classes` is what it exports.
+/// If `hash_names` is set, the modules dir gets a hash of all the modules in its name.
+/// If `lazy_pages` is set, page modules are only loaded when their route is first visited.
+pub fn build_es_modules(
+    compiled_elements: &[&CompiledElement],
+    common_files: &[CommonFile],
+    framework_name: &str,
+    framework_classes: &[&str],
+    minify: bool,
+    hash_names: bool,
+    lazy_pages: bool,
+) -> EsModuleOutput {
+    let mut sources = vec![];
+    for element in compiled_elements {
+        let mut exports = vec![element.compiled_element_name.clone()];
+        if element.element_type == ElementType::Page && !lazy_pages {
+            exports.push(PAGE_ROUTES_EXPORT_NAME.to_string());
+        }
+        sources.push(ModuleSource {
+            location: format!("{}.js", element.element_name),
+            exports,
+            content: element.content.clone(),
+        });
+    }
+    for common_file in common_files {
+        let mut content = common_file.content.clone();
+        if !common_file.declared_names.is_empty() {
+            // The semicolon stops the minifier joining the export onto the end of the last statement
+            content += &format!(";\nexport {{ {} }};", common_file.declared_names.join(", "));
+        }
+        sources.push(ModuleSource {
+            location: format!("{COMMON_MODULES_DIR_NAME}/{}", common_file.file_name),
+            exports: common_file.declared_names.clone(),
+            content,
+        });
+    }
+
+    // Everything that a module can import, and where from (relative to the modules dir).
+    // The framework is one level up, in scripts/
+    let framework_location = format!("../{}", framework_name.trim_start_matches("scripts/"));
+    let mut importable: Vec<(&str, &str)> = framework_classes
+        .iter()
+        .map(|class| (*class, framework_location.as_str()))
+        .collect();
+    for source in &sources {
+        for export in &source.exports {
+            // Page routes are only imported by the entry module
+            if export != PAGE_ROUTES_EXPORT_NAME {
+                importable.push((export, &source.location));
+            }
+        }
+    }
+
+    let mut module_contents = vec![];
+    for source in &sources {
+        let mut content = format!(
+            "{}\n{}",
+            import_statements(&source.content, &source.location, &importable),
+            source.content
+        );
+        if minify {
+            content = minifier::js::minify(&content).to_string();
+        }
+        module_contents.push(content);
+    }
+
+    // Imports between modules are relative, so the modules dir name can be hashed after they're generated
+    let modules_dir =
+        output_assets::asset_name(MODULES_DIR_NAME, module_contents.join("\n"), hash_names);
+    let mut entry = entry_module(
+        compiled_elements,
+        common_files,
+        &modules_dir,
+        framework_name,
+        framework_classes,
+        lazy_pages,
+    );
+    if minify {
+        entry = minifier::js::minify(&entry).to_string();
+    }
+
+    EsModuleOutput {
+        entry,
+        modules: sources
+            .iter()
+            .zip(module_contents)
+            .map(|(source, content)| ScriptFile {
+                name: format!("{modules_dir}/{}", source.location),
+                content,
+            })
+            .collect(),
+    }
+}
+
+fn import_statements(content: &str, location: &str, importable: &[(&str, &str)]) -> String {
+    // Import everything from other modules that is mentioned in content, with one statement per module
+
+    let mut imports: Vec<(&str, Vec<&str>)> = vec![];
+    for (name, module_location) in importable {
+        if *module_location == location || !common_files::contains_identifier(content, name) {
+            continue;
+        }
+        match imports.iter_mut().find(|(l, _)| l == module_location) {
+            Some((_, names)) => names.push(name),
+            None => imports.push((module_location, vec![name])),
+        }
+    }
+    imports
+        .iter()
+        .map(|(module_location, names)| {
+            format!(
+                "import {{ {} }} from \"{}\";",
+                names.join(", "),
+                relative_specifier(location, module_location)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn relative_specifier(from_file: &str, to_file: &str) -> String {
+    // Path to import to_file from from_file, where both are relative to the same directory
+
+    let from_dir: Vec<&str> = from_file.split('/').collect();
+    let from_dir = &from_dir[..from_dir.len() - 1];
+    let to_parts: Vec<&str> = to_file.split('/').collect();
+
+    let mut common_length = 0;
+    while common_length < from_dir.len()
+        && common_length < to_parts.len() - 1
+        && from_dir[common_length] == to_parts[common_length]
+    {
+        common_length += 1;
+    }
+    // ".." in to_file just becomes part of the path, which is what we want
+    let ups = from_dir.len() - common_length;
+    let rest = to_parts[common_length..].join("/");
+    if ups == 0 && !rest.starts_with("../") {
+        format!("./{rest}")
+    } else {
+        format!("{}{rest}", "../".repeat(ups))
+    }
+}
+
+fn entry_module(
+    compiled_elements: &[&CompiledElement],
+    common_files: &[CommonFile],
+    modules_dir: &str,
+    framework_name: &str,
+    framework_classes: &[&str],
+    lazy_pages: bool,
+) -> String {
+    // The entry module (bundle.js) - imports the root element and every page's routes, then starts the app.
+    // It lives in scripts/, like framework.js
+
+    let modules_path = format!("./{}", modules_dir.trim_start_matches("scripts/"));
+    let mut lines = vec![];
+
+    // Common files that don't export anything are only run for their side effects (eg adding to window)
+    for common_file in common_files {
+        if common_file.declared_names.is_empty() {
+            lines.push(format!(
+                "import \"{modules_path}/{COMMON_MODULES_DIR_NAME}/{}\";",
+                common_file.file_name
+            ));
+        }
+    }
+
+    let mut root_element_name = String::new();
+    let mut routes = vec![];
+    for element in compiled_elements {
+        if element.element_type == ElementType::Basic && element.element_name == ROOT_ELEMENT_NAME {
+            root_element_name = element.compiled_element_name.clone();
+            lines.push(format!(
+                "import {{ {root_element_name} }} from \"{modules_path}/{}.js\";",
+                element.element_name
+            ));
+        } else if element.element_type == ElementType::Page && lazy_pages {
+            // import() in SpallLazyPage is relative to framework.js, so use an absolute url
+            let module_url =
+                output_assets::asset_url(&format!("{modules_dir}/{}.js", element.element_name));
+            for route in &element.page_routes {
+                routes.push(format!(
+                    "[{},new SpallLazyPage(\"{}\",[\"{module_url}\"],true)]",
+                    element_compiler::compile_page_route(route),
+                    element.compiled_element_name
+                ));
+            }
+        } else if element.element_type == ElementType::Page {
+            let route_name = format!("{PAGE_ROUTES_EXPORT_NAME}{}", element.element_name);
+            lines.push(format!(
+                "import {{ {PAGE_ROUTES_EXPORT_NAME} as {route_name} }} from \"{modules_path}/{}.js\";",
+                element.element_name
+            ));
+            routes.push(format!("...{route_name}"));
+        }
+    }
+
+    lines.push(format!(
+        "SpallApp.instance = new SpallApp({{ rootElement: {root_element_name}, routes: [{}] }});",
+        routes.join(", ")
+    ));
+    lines.push(format!("window.{APP_GLOBAL_NAME} = SpallApp.instance;"));
+
+    // Import whatever the entry module uses from the framework (always SpallApp, and the route classes when pages are lazy)
+    let content = lines.join("\n");
+    let used_framework_classes: Vec<&str> = framework_classes
+        .iter()
+        .copied()
+        .filter(|class| common_files::contains_identifier(&content, class))
+        .collect();
+    format!(
+        "import {{ {} }} from \"./{}\";\n{content}\n",
+        used_framework_classes.join(", "),
+        framework_name.trim_start_matches("scripts/")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compilation_settings::{CompilationSettings, OutputFormat};
+    use std::path::PathBuf;
+
+    fn compile(
+        content: &str,
+        name: &str,
+        element_type: ElementType,
+        split_pages: bool,
+    ) -> CompiledElement {
+        let settings = CompilationSettings {
+            output_format: OutputFormat::EsModules,
+            split_pages,
+            ..Default::default()
+        };
+        element_compiler::compile_test_element(content, name, element_type, settings)
+    }
+
+    #[test]
+    fn test_relative_specifier() {
+        assert_eq!(
+            relative_specifier("Root.js", "Greeting.js"),
+            "./Greeting.js"
+        );
+        assert_eq!(
+            relative_specifier("Root.js", "common/api.js"),
+            "./common/api.js"
+        );
+        assert_eq!(relative_specifier("common/api.js", "Root.js"), "../Root.js");
+        assert_eq!(
+            relative_specifier("Root.js", "../framework.js"),
+            "../framework.js"
+        );
+        assert_eq!(
+            relative_specifier("common/api.js", "../framework.js"),
+            "../../framework.js"
+        );
+    }
+
+    #[test]
+    fn test_build_es_modules() {
+        let root = compile(
+            "<Greeting /><p>${api.name}</p>",
+            "Root",
+            ElementType::Basic,
+            false,
+        );
+        let greeting = compile("<p>Hi</p>", "Greeting", ElementType::Basic, false);
+        let index = compile(
+            "<pageroute>/</pageroute>",
+            "Index",
+            ElementType::Page,
+            false,
+        );
+        let common_files = vec![
+            common_files::read_common_file(PathBuf::from("api.js"), "const api = {};").unwrap(),
+            common_files::read_common_file(PathBuf::from("setup.js"), "window.x = 1;").unwrap(),
+        ];
+        let output = build_es_modules(
+            &[&root, &greeting, &index],
+            &common_files,
+            "scripts/framework.js",
+            &[
+                "SpallApp",
+                "SpallRootElement",
+                "SpallElement",
+                "SpallPage",
+                "SpallElementRenderable",
+                "SpallMarkupRenderable",
+                "SpallStringRouteSection",
+            ],
+            false,
+            false,
+            false,
+        );
+
+        let module = |name: &str| {
+            output
+                .modules
+                .iter()
+                .find(|m| m.name == name)
+                .map(|m| m.content.clone())
+                .unwrap()
+        };
+        let root_module = module("scripts/modules/Root.js");
+        assert!(root_module.contains("import { SpallRootElement, SpallElementRenderable, SpallMarkupRenderable } from \"../framework.js\";"));
+        assert!(root_module.contains("import { __SpallCompiledGreeting } from \"./Greeting.js\";"));
+        assert!(root_module.contains("import { api } from \"./common/api.js\";"));
+        assert!(root_module.contains("export class __SpallCompiledRoot"));
+        assert!(module("scripts/modules/common/api.js").ends_with("export { api };"));
+        assert!(module("scripts/modules/Index.js")
+            .contains("export const __spallRoutes = [[[],__SpallCompiledIndex]];"));
+
+        assert_eq!(
+            output.entry,
+            r#"import { SpallApp } from "./framework.js";
+import "./modules/common/setup.js";
+import { __SpallCompiledRoot } from "./modules/Root.js";
+import { __spallRoutes as __spallRoutesIndex } from "./modules/Index.js";
+SpallApp.instance = new SpallApp({ rootElement: __SpallCompiledRoot, routes: [...__spallRoutesIndex] });
+window.__spallApp = SpallApp.instance;
+"#
+        );
+    }
+
+    #[test]
+    fn test_lazy_page_modules() {
+        let root = compile("<RoutedApp />", "Root", ElementType::Basic, false);
+        let about = compile(
+            "<pageroute>/about</pageroute>",
+            "About",
+            ElementType::Page,
+            true,
+        );
+        let output = build_es_modules(
+            &[&root, &about],
+            &[],
+            "scripts/framework.js",
+            &[
+                "SpallApp",
+                "SpallRootElement",
+                "SpallPage",
+                "SpallLazyPage",
+                "SpallStringRouteSection",
+            ],
+            false,
+            false,
+            true,
+        );
+
+        assert!(!output.modules[1].content.contains(PAGE_ROUTES_EXPORT_NAME));
+        assert_eq!(
+            output.entry,
+            r#"import { SpallApp, SpallLazyPage, SpallStringRouteSection } from "./framework.js";
+import { __SpallCompiledRoot } from "./modules/Root.js";
+SpallApp.instance = new SpallApp({ rootElement: __SpallCompiledRoot, routes: [[[new SpallStringRouteSection("about")],new SpallLazyPage("__SpallCompiledAbout",["/scripts/modules/About.js"],true)]] });
+window.__spallApp = SpallApp.instance;
+"#
+        );
+    }
+
+    #[test]
+    fn test_hashed_modules_dir() {
+        let root = compile("<p>Hi</p>", "Root", ElementType::Basic, false);
+        let output = build_es_modules(
+            &[&root],
+            &[],
+            "scripts/framework.1234abcd.js",
+            &["SpallRootElement"],
+            false,
+            true,
+            false,
+        );
+        assert!(output.modules[0].name.starts_with("scripts/modules."));
+        assert!(output.modules[0]
+            .content
+            .contains("from \"../framework.1234abcd.js\""));
+        assert!(output.entry.contains(&format!(
+            "from \"./{}\"",
+            output.modules[0].name.trim_start_matches("scripts/")
+        )));
+    }
+}
@@ -0,0 +1,523 @@
+// Builds the project's global stylesheet: styles/global/index.css (or the file given in the settings) along with the
+// stylesheets it @imports. Local imports are inlined since the app ships a single CSS file, while imports from other
+// sites (eg a font provider) are kept and moved to the top, where CSS requires @import to be.
+//
+// Each file is preprocessed on its own (see scoped_css::preprocessor) so that errors point at the right file and line.
+// That means $variables don't carry across @import - shared ones go in a partial that each file can @use.
+// url()s are relative to the file they're in, so local ones are pointed at the file's copy in build/static.
+// In release builds that copy gets a hash in its name, like the other output assets.
+
+use std::path::{Component, Path, PathBuf};
+
+use crate::common::tokeniser_utils;
+use crate::errs;
+use crate::output_assets;
+use crate::project_fs::ProjectFs;
+use crate::project_paths::ProjectPaths;
+use crate::scoped_css::preprocessor::{self, CssPartials};
+use crate::scoped_css::tokeniser;
+
+pub const GLOBAL_CSS_ENTRY_NAME: &str = "index.css";
+
+pub struct GlobalCss {
+    pub plain_css: String, // preprocessed but not yet normalized or minified
+    pub source_files: Vec<PathBuf>, // every file that was included, in the order they were read
+    pub static_assets: Vec<HashedStaticAsset>, // copies of static files to save, when hashing names
+    pub warnings: Vec<errs::CompilationWarning>,
+}
+
+// A static file referenced by the global stylesheet, which is saved again under a name with a hash of its content
+pub struct HashedStaticAsset {
+    pub source_file: PathBuf,
+    pub name: String, // relative to the build dir, eg static/img/logo.3fa9c1d2.png
+}
+
+// A leading @import statement of a stylesheet
+struct CssImport {
+    statement: String, // the whole statement including the ;, for keeping imports from other sites
+    url: String,
+    conditions: String, // any layer(), supports() and media query that the import only applies under
+}
+
+pub fn build_global_css(
+    entry_file: &Path,
+    project_paths: &ProjectPaths,
+    css_partials: &CssPartials,
+    hash_names: bool,
+    fs: &dyn ProjectFs,
+) -> Result<GlobalCss, errs::CompilationError> {
+    let mut builder = GlobalCssBuilder {
+        project_paths,
+        css_partials,
+        hash_names,
+        fs,
+        import_stack: vec![entry_file.to_path_buf()],
+        external_imports: vec![],
+        result: GlobalCss {
+            plain_css: String::new(),
+            source_files: vec![],
+            static_assets: vec![],
+            warnings: vec![],
+        },
+    };
+    let css = builder.read_stylesheet(entry_file)?;
+    let mut result = builder.result;
+    result.plain_css = builder
+        .external_imports
+        .iter()
+        .map(|statement| format!("{statement}\n"))
+        .collect::<String>()
+        + &css;
+    Ok(result)
+}
+
+struct GlobalCssBuilder<'a> {
+    project_paths: &'a ProjectPaths,
+    css_partials: &'a CssPartials,
+    hash_names: bool,
+    fs: &'a dyn ProjectFs,
+    import_stack: Vec<PathBuf>, // the file being read and the files that imported it, for finding import loops
+    external_imports: Vec<String>,
+    result: GlobalCss,
+}
+
+impl GlobalCssBuilder<'_> {
+    fn read_stylesheet(&mut self, path: &Path) -> Result<String, errs::CompilationError> {
+        // Read a stylesheet and everything it imports, as plain css with urls rewritten
+
+        let file_error = |inner_error| errs::CompilationError::File {
+            file_name: path.to_string_lossy().to_string(),
+            inner_error,
+        };
+        let content = self
+            .fs
+            .read_to_string(path)
+            .map_err(errs::io_error("reading", path))?;
+        self.result.source_files.push(path.to_path_buf());
+
+        let (imports, rest) = split_imports(&content);
+        let mut css = String::new();
+        for import in imports {
+            if is_external_url(&import.url) {
+                self.external_imports.push(import.statement);
+                continue;
+            }
+            let import_path = self.resolve_url(path, &import.url);
+            if self.import_stack.contains(&import_path) {
+                return Err(file_error(errs::FileCompilationError::GlobalCssError(
+                    errs::GlobalCssError::CircularImport { url: import.url },
+                )));
+            }
+            // Stylesheets imported from more than one place are only included the first time
+            if self.result.source_files.contains(&import_path) {
+                continue;
+            }
+            if !is_file(&import_path, self.fs) {
+                return Err(file_error(errs::FileCompilationError::GlobalCssError(
+                    errs::GlobalCssError::ImportNotFound { url: import.url },
+                )));
+            }
+            self.import_stack.push(import_path.clone());
+            let imported_css = self.read_stylesheet(&import_path)?;
+            self.import_stack.pop();
+            css += &wrap_in_conditions(&imported_css, &import.conditions);
+        }
+
+        let plain_css = preprocessor::preprocess(&rest, self.css_partials)
+            .map_err(|e| file_error(errs::FileCompilationError::CssSourceError(e)))?;
+        css += &rewrite_urls(&plain_css, &mut |url| self.static_asset_url(path, url));
+        Ok(css)
+    }
+
+    fn resolve_url(&self, css_file: &Path, url: &str) -> PathBuf {
+        // Find the file that a local url refers to. Urls starting with / are relative to the project dir,
+        // since that's where static/ is served from
+
+        let base_dir = if url.starts_with('/') {
+            self.project_paths.root_dir.as_path()
+        } else {
+            css_file.parent().unwrap_or(Path::new(""))
+        };
+        normalize_path(&base_dir.join(url.trim_start_matches('/')))
+    }
+
+    fn static_asset_url(&mut self, css_file: &Path, url: &str) -> Option<String> {
+        // The url that a url() in css_file should have in the bundle, or None if it should be left alone
+
+        if url.is_empty() || url.starts_with('#') || is_external_url(url) {
+            return None;
+        }
+        // Keep any query or fragment, eg the #icon of icons.svg#icon
+        let (url_path, suffix) = url.split_at(url.find(['?', '#']).unwrap_or(url.len()));
+        let target = self.resolve_url(css_file, url_path);
+        let static_path = target
+            .strip_prefix(&self.project_paths.static_dir)
+            .ok()
+            .filter(|_| is_file(&target, self.fs));
+        let Some(static_path) = static_path else {
+            self.result
+                .warnings
+                .push(errs::CompilationWarning::UnresolvedCssUrl {
+                    path: css_file
+                        .strip_prefix(&self.project_paths.root_dir)
+                        .unwrap_or(css_file)
+                        .to_path_buf(),
+                    url: url.to_string(),
+                });
+            return None;
+        };
+
+        let name = Path::new("static")
+            .join(static_path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        let name = if !self.hash_names {
+            name
+        } else if let Some(asset) = self
+            .result
+            .static_assets
+            .iter()
+            .find(|a| a.source_file == target)
+        {
+            asset.name.clone()
+        } else {
+            let hashed_name = output_assets::asset_name(&name, self.fs.read(&target).ok()?, true);
+            self.result.static_assets.push(HashedStaticAsset {
+                source_file: target,
+                name: hashed_name.clone(),
+            });
+            hashed_name
+        };
+        Some(output_assets::asset_url(&name) + suffix)
+    }
+}
+
+fn is_file(path: &Path, fs: &dyn ProjectFs) -> bool {
+    fs.exists(path) && !fs.is_dir(path)
+}
+
+pub fn is_external_url(url: &str) -> bool {
+    url.contains("://") || url.starts_with("//") || url.to_lowercase().starts_with("data:")
+}
+
+pub fn normalize_path(path: &Path) -> PathBuf {
+    // Remove . and .. from a path without touching the filesystem, as the file might not exist
+
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                result.pop();
+            }
+            other => result.push(other),
+        }
+    }
+    result
+}
+
+fn starts_with_ignore_case(text: &str, prefix: &str) -> bool {
+    text.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+fn split_imports(css: &str) -> (Vec<CssImport>, String) {
+    // Take the @import statements off the start of a stylesheet (browsers ignore any after the first rule).
+    // @charset goes too, as the bundle is always utf-8.
+    // Removed statements are replaced by the line breaks they had, so that lines in errors still match up
+
+    let mut imports = vec![];
+    let mut rest = String::new();
+    let mut idx = 0;
+    loop {
+        // Find the next statement. Whitespace and comments before it are kept
+        let mut statement_start = idx;
+        loop {
+            statement_start +=
+                css[statement_start..].len() - css[statement_start..].trim_start().len();
+            match css[statement_start..]
+                .strip_prefix("/*")
+                .and_then(|c| c.find("*/"))
+            {
+                Some(end) => statement_start += 2 + end + 2,
+                None => break,
+            }
+        }
+        let at_import = starts_with_ignore_case(&css[statement_start..], "@import");
+        if !at_import && !starts_with_ignore_case(&css[statement_start..], "@charset") {
+            break;
+        }
+        let (text, stop_char, length) =
+            tokeniser::read_until_top_level(&css[statement_start..], &[';']);
+        if stop_char.is_none() {
+            break;
+        }
+        let statement_end = statement_start + length + 1;
+        rest += &css[idx..statement_start];
+        rest += &"\n".repeat(css[statement_start..statement_end].matches('\n').count());
+        if at_import {
+            match parse_import(&text["@import".len()..]) {
+                Some((url, conditions)) => imports.push(CssImport {
+                    statement: css[statement_start..statement_end].to_string(),
+                    url,
+                    conditions,
+                }),
+                // Not an import that a browser would understand, so leave it for the browser to ignore
+                None => rest += &css[statement_start..statement_end],
+            }
+        }
+        idx = statement_end;
+    }
+    rest += &css[idx..];
+    (imports, rest)
+}
+
+fn parse_import(prelude: &str) -> Option<(String, String)> {
+    // Split the part of an @import after the @import into its url and conditions
+
+    let prelude = prelude.trim();
+    let (url, length) = if prelude.starts_with(['"', '\'']) {
+        read_string(prelude)?
+    } else if starts_with_ignore_case(prelude, "url(") {
+        read_url_function(prelude)?
+    } else {
+        return None;
+    };
+    Some((url, prelude[length..].trim().to_string()))
+}
+
+fn read_string(text: &str) -> Option<(String, usize)> {
+    // Read the quoted string at the start of text. Returns its content and length including the quotes
+
+    let quote_char = text.chars().next()?;
+    let mut idx = 1;
+    while let Some(c) = text[idx..].chars().next() {
+        if c == quote_char {
+            return Some((text[1..idx].to_string(), idx + 1));
+        }
+        idx += if c == '\\' {
+            tokeniser_utils::escape_length(&text[idx..])
+        } else {
+            c.len_utf8()
+        };
+    }
+    None
+}
+
+fn read_url_function(text: &str) -> Option<(String, usize)> {
+    // Read the url(...) at the start of text. Returns the url without any quotes and the length up to the )
+
+    let inner_start = "url(".len();
+    let inner = &text[inner_start..];
+    let leading_space = inner.len() - inner.trim_start().len();
+    let inner = inner.trim_start();
+    if inner.starts_with(['"', '\'']) {
+        let (url, length) = read_string(inner)?;
+        let after_string = &inner[length..];
+        let trailing_space = after_string.len() - after_string.trim_start().len();
+        after_string.trim_start().starts_with(')').then(|| {
+            (
+                url,
+                inner_start + leading_space + length + trailing_space + 1,
+            )
+        })
+    } else {
+        let end = inner.find(')')?;
+        Some((
+            inner[..end].trim().to_string(),
+            inner_start + leading_space + end + 1,
+        ))
+    }
+}
+
+fn wrap_in_conditions(css: &str, conditions: &str) -> String {
+    // Put the css of an import inside the at-rules that its conditions stand for,
+    // eg layer(base) supports(display: grid) screen becomes @layer base { @supports (display: grid) { @media screen { ... } } }
+
+    let mut conditions = conditions.trim();
+    let mut at_rules = vec![];
+    if starts_with_ignore_case(conditions, "layer(") {
+        let (inner, length) = bracket_content(&conditions["layer".len()..]);
+        at_rules.push(format!("@layer {inner}"));
+        conditions = conditions["layer".len() + length..].trim_start();
+    } else if starts_with_ignore_case(conditions, "layer")
+        && !conditions["layer".len()..].starts_with(|c: char| c.is_alphanumeric() || c == '-')
+    {
+        at_rules.push("@layer".to_string());
+        conditions = conditions["layer".len()..].trim_start();
+    }
+    if starts_with_ignore_case(conditions, "supports(") {
+        let (inner, length) = bracket_content(&conditions["supports".len()..]);
+        at_rules.push(format!("@supports ({inner})"));
+        conditions = conditions["supports".len() + length..].trim_start();
+    }
+    if !conditions.is_empty() {
+        at_rules.push(format!("@media {conditions}"));
+    }
+
+    at_rules
+        .iter()
+        .rev()
+        .fold(css.to_string(), |inner, at_rule| {
+            format!("{at_rule} {{\n{inner}}}\n")
+        })
+}
+
+fn bracket_content(text: &str) -> (&str, usize) {
+    // Given text starting with a (, return what is inside the brackets and the length including them
+
+    let end = tokeniser_utils::top_level_chars(&text[1..])
+        .iter()
+        .find(|(_, c)| *c == ')')
+        .map(|(idx, _)| idx + 1)
+        .unwrap_or(text.len());
+    (&text[1..end], (end + 1).min(text.len()))
+}
+
+pub fn rewrite_urls(css: &str, rewrite: &mut dyn FnMut(&str) -> Option<String>) -> String {
+    // Replace the url in each url() that rewrite gives a new one for. Strings and comments are left alone
+
+    let mut result = String::new();
+    let mut idx = 0;
+    while let Some(c) = css[idx..].chars().next() {
+        let rest = &css[idx..];
+        let length = if c == '"' || c == '\'' {
+            read_string(rest)
+                .map(|(_, length)| length)
+                .unwrap_or(rest.len())
+        } else if rest.starts_with("/*") {
+            rest.find("*/").map(|end| end + 2).unwrap_or(rest.len())
+        } else if c == '\\' {
+            tokeniser_utils::escape_length(rest)
+        } else if starts_with_ignore_case(rest, "url(")
+            && !result.ends_with(|c: char| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            match read_url_function(rest) {
+                Some((url, length)) => {
+                    match rewrite(&url) {
+                        Some(new_url) if rest[4..].trim_start().starts_with(['"', '\'']) => {
+                            result += &format!("url(\"{new_url}\")")
+                        }
+                        Some(new_url) => result += &format!("url({new_url})"),
+                        None => result += &rest[..length],
+                    }
+                    idx += length;
+                    continue;
+                }
+                None => rest.len(),
+            }
+        } else {
+            c.len_utf8()
+        };
+        result += &rest[..length];
+        idx += length;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_fs::MemoryFs;
+
+    fn build(files: &[(&str, &str)], hash_names: bool) -> Result<GlobalCss, String> {
+        let fs = MemoryFs::with_files("/project", files);
+        build_global_css(
+            Path::new("/project/styles/global/index.css"),
+            &ProjectPaths::new(Path::new("/project")),
+            &CssPartials::new(),
+            hash_names,
+            &fs,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_imports_are_inlined() {
+        let global_css = build(
+            &[
+                (
+                    "styles/global/index.css",
+                    "@charset \"utf-8\";\n@import \"reset.css\";\n@import url('https://fonts.example.com/x.css');\n@import url(parts/print.css) print;\n@import 'reset.css';\nbody { margin: 0; }",
+                ),
+                ("styles/global/reset.css", "/* reset */\n* { box-sizing: border-box; }"),
+                ("styles/global/parts/print.css", "@import \"../reset.css\";\nnav { display: none; }"),
+            ],
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            global_css.plain_css,
+            "@import url('https://fonts.example.com/x.css');\n/* reset */\n* {\nbox-sizing: border-box;\n}\n@media print {\nnav {\ndisplay: none;\n}\n}\nbody {\nmargin: 0;\n}\n"
+        );
+        assert_eq!(global_css.source_files.len(), 3);
+    }
+
+    #[test]
+    fn test_import_errors() {
+        assert_eq!(
+            build(&[("styles/global/index.css", "@import \"missing.css\";")], false).err().unwrap(),
+            "Error compiling /project/styles/global/index.css:\n    Imported stylesheet \"missing.css\" does not exist"
+        );
+        assert!(build(
+            &[
+                ("styles/global/index.css", "@import \"a.css\";"),
+                ("styles/global/a.css", "@import \"index.css\";"),
+            ],
+            false
+        )
+        .err()
+        .unwrap()
+        .contains("Importing \"index.css\" would make it import itself"));
+        assert!(build(
+            &[
+                ("styles/global/index.css", "@import \"a.css\";"),
+                ("styles/global/a.css", "\n\np { color }"),
+            ],
+            false
+        )
+        .err()
+        .unwrap()
+        .contains("a.css:\n    Line 3: Expected a : after property \"color\""));
+    }
+
+    #[test]
+    fn test_urls_point_at_static_files() {
+        let files = [
+            (
+                "styles/global/index.css",
+                "a { background: url(\"../../static/img/bg.png\"), url(/static/img/bg.png?v=2); content: \"url(x.png)\"; }\nb { mask: url(#m) url(data:x) url(missing.png); }",
+            ),
+            ("static/img/bg.png", "png"),
+        ];
+        let global_css = build(&files, false).unwrap();
+        assert!(global_css
+            .plain_css
+            .contains("background: url(\"/static/img/bg.png\"), url(/static/img/bg.png?v=2);"));
+        assert!(global_css.plain_css.contains("content: \"url(x.png)\";"));
+        assert!(global_css
+            .plain_css
+            .contains("mask: url(#m) url(data:x) url(missing.png);"));
+        assert!(global_css.static_assets.is_empty());
+        assert_eq!(
+            global_css.warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+            vec!["url(missing.png) in styles/global/index.css is not a file in static/, so it is left as it is"]
+        );
+
+        let global_css = build(&files, true).unwrap();
+        assert_eq!(global_css.static_assets.len(), 1);
+        let name = &global_css.static_assets[0].name;
+        assert!(name.starts_with("static/img/bg.") && name.ends_with(".png"));
+        assert!(global_css.plain_css.contains(&format!("url(/{name}?v=2)")));
+    }
+
+    #[test]
+    fn test_wrap_in_conditions() {
+        assert_eq!(wrap_in_conditions("p {}\n", ""), "p {}\n");
+        assert_eq!(
+            wrap_in_conditions("p {}\n", "layer(base) supports(display: grid) screen and (min-width: 1px)"),
+            "@layer base {\n@supports (display: grid) {\n@media screen and (min-width: 1px) {\np {}\n}\n}\n}\n"
+        );
+        assert_eq!(wrap_in_conditions("p {}\n", "layer"), "@layer {\np {}\n}\n");
+    }
+}
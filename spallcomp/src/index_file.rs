@@ -0,0 +1,277 @@
+// Links the compiled output into meta/index.html.
+// Placeholders in the index file are replaced with the tags for the output assets.
+// Hard-coded references to the default (unhashed) asset paths are rewritten to point to the real files,
+// and any asset that isn't mentioned at all is injected at the end of the <head>.
+// The entry module of an ES module build starts the app itself, so the inline script that would start it is removed.
+
+use crate::compilation_settings::OutputFormat;
+use crate::output_assets::{self, OutputAssetNames};
+
+pub const SCRIPTS_PLACEHOLDER: &str = "<!-- spall:scripts -->";
+pub const STYLES_PLACEHOLDER: &str = "<!-- spall:styles -->";
+// What the inline script in index.html runs to start the app
+const INLINE_BOOTSTRAP: &str = "SpallApp.instance = new SpallApp();";
+
+pub fn link_assets(
+    index_content: &str,
+    asset_names: &OutputAssetNames,
+    output_format: OutputFormat,
+) -> String {
+    // With ES modules, framework.js is loaded through the entry module's imports so only the bundle is linked
+    let mut scripts = vec![];
+    if output_format == OutputFormat::Scripts {
+        scripts.push((
+            output_assets::FRAMEWORK_SCRIPT_NAME,
+            &asset_names.framework_script,
+        ));
+    }
+    scripts.push((
+        output_assets::BUNDLE_SCRIPT_NAME,
+        &asset_names.bundle_script,
+    ));
+    let styles = [(
+        output_assets::SCOPED_CSS_BUNDLE_NAME,
+        &asset_names.scoped_css_bundle,
+    )];
+
+    let mut result = match output_format {
+        OutputFormat::Scripts => index_content.to_string(),
+        OutputFormat::EsModules => remove_inline_bootstrap(index_content),
+    };
+    let mut injected_tags = vec![];
+
+    let script_tags: Vec<String> = scripts
+        .iter()
+        .map(|(_, name)| script_tag(name, output_format))
+        .collect();
+    let style_tags = styles.map(|(_, name)| style_tag(name));
+    for (placeholder, tags, assets) in [
+        (STYLES_PLACEHOLDER, style_tags.as_slice(), styles.as_slice()),
+        (
+            SCRIPTS_PLACEHOLDER,
+            script_tags.as_slice(),
+            scripts.as_slice(),
+        ),
+    ] {
+        if result.contains(placeholder) {
+            result = replace_placeholder(&result, placeholder, tags);
+            continue;
+        }
+        for (default_name, real_name) in assets.iter() {
+            let mut default_urls = vec![output_assets::asset_url(default_name)];
+            if *default_name == output_assets::SCOPED_CSS_BUNDLE_NAME {
+                default_urls.push(output_assets::asset_url(
+                    output_assets::FORMER_SCOPED_CSS_BUNDLE_NAME,
+                ));
+            }
+            let linked_urls: Vec<&String> = default_urls
+                .iter()
+                .filter(|url| result.contains(*url))
+                .collect();
+            if !linked_urls.is_empty() {
+                for default_url in linked_urls {
+                    result = result.replace(default_url, &output_assets::asset_url(real_name));
+                }
+            } else if default_name.ends_with(".css") {
+                injected_tags.push(style_tag(real_name));
+            } else {
+                injected_tags.push(script_tag(real_name, output_format));
+            }
+        }
+    }
+
+    if !injected_tags.is_empty() {
+        result = inject_into_head(&result, &injected_tags.join("\n"));
+    }
+    result
+}
+
+fn replace_placeholder(index_content: &str, placeholder: &str, tags: &[String]) -> String {
+    // Replace every occurrence of placeholder with tags, each on its own line with the placeholder's indentation
+
+    let mut result = "".to_string();
+    let mut remaining = index_content;
+    while let Some(idx) = remaining.find(placeholder) {
+        let line_start = remaining[..idx].rfind('\n').map_or(0, |x| x + 1);
+        let indent = &remaining[line_start..idx];
+        let indent = if indent.trim().is_empty() { indent } else { "" };
+
+        result += &remaining[..idx];
+        result += &tags.join(&format!("\n{indent}"));
+        remaining = &remaining[idx + placeholder.len()..];
+    }
+    result + remaining
+}
+
+fn remove_inline_bootstrap(index_content: &str) -> String {
+    // Remove each inline <script> that only starts the app. It would run before the deferred entry module, when
+    // SpallApp isn't defined. If the script is on lines of its own then they're removed too
+
+    let mut result = "".to_string();
+    let mut remaining = index_content;
+    while let Some(start) = remaining.find("<script>") {
+        let body_start = start + "<script>".len();
+        let Some(body_length) = remaining[body_start..].find("</script>") else {
+            break;
+        };
+        let end = body_start + body_length + "</script>".len();
+        if remaining[body_start..body_start + body_length].trim() != INLINE_BOOTSTRAP {
+            result += &remaining[..end];
+            remaining = &remaining[end..];
+            continue;
+        }
+
+        let line_start = remaining[..start].rfind('\n').map_or(0, |x| x + 1);
+        if remaining[line_start..start].trim().is_empty() {
+            result += &remaining[..line_start];
+            remaining = remaining[end..]
+                .strip_prefix('\n')
+                .unwrap_or(&remaining[end..]);
+        } else {
+            result += &remaining[..start];
+            remaining = &remaining[end..];
+        }
+    }
+    result + remaining
+}
+
+fn script_tag(asset_name: &str, output_format: OutputFormat) -> String {
+    let type_attribute = match output_format {
+        OutputFormat::Scripts => "",
+        OutputFormat::EsModules => r#" type="module""#,
+    };
+    format!(
+        r#"<script{type_attribute} src="{}"></script>"#,
+        output_assets::asset_url(asset_name)
+    )
+}
+
+fn style_tag(asset_name: &str) -> String {
+    format!(
+        r#"<link rel="stylesheet" href="{}" />"#,
+        output_assets::asset_url(asset_name)
+    )
+}
+
+fn inject_into_head(index_content: &str, tags: &str) -> String {
+    // Put tags just before </head>. If there is no head then just put them at the start
+
+    match index_content.find("</head>") {
+        Some(idx) => format!("{}{tags}\n{}", &index_content[..idx], &index_content[idx..]),
+        None => format!("{tags}\n{index_content}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashed_names() -> OutputAssetNames {
+        OutputAssetNames {
+            framework_script: "scripts/framework.aaaa.js".to_string(),
+            bundle_script: "scripts/bundle.bbbb.js".to_string(),
+            scoped_css_bundle: "css/bundle.cccc.css".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_link_assets_placeholders() {
+        let result = link_assets(
+            "<head><!-- spall:styles --></head><body></body><!-- spall:scripts -->",
+            &hashed_names(),
+            OutputFormat::Scripts,
+        );
+        assert_eq!(
+            result,
+            r#"<head><link rel="stylesheet" href="/css/bundle.cccc.css" /></head><body></body><script src="/scripts/framework.aaaa.js"></script>
+<script src="/scripts/bundle.bbbb.js"></script>"#
+        );
+    }
+
+    #[test]
+    fn test_link_assets_placeholder_indentation() {
+        let result = link_assets(
+            "<head>\n    <!-- spall:styles -->\n</head>\n    <!-- spall:scripts -->",
+            &hashed_names(),
+            OutputFormat::Scripts,
+        );
+        assert_eq!(
+            result,
+            r#"<head>
+    <link rel="stylesheet" href="/css/bundle.cccc.css" />
+</head>
+    <script src="/scripts/framework.aaaa.js"></script>
+    <script src="/scripts/bundle.bbbb.js"></script>"#
+        );
+    }
+
+    #[test]
+    fn test_link_assets_rewrites_default_paths() {
+        let result = link_assets(
+            r#"<link rel="stylesheet" href="/static/bundle.css" /><script src="/scripts/framework.js"></script><script src="/scripts/bundle.js"></script>"#,
+            &hashed_names(),
+            OutputFormat::Scripts,
+        );
+        assert_eq!(
+            result,
+            r#"<link rel="stylesheet" href="/css/bundle.cccc.css" /><script src="/scripts/framework.aaaa.js"></script><script src="/scripts/bundle.bbbb.js"></script>"#
+        );
+    }
+
+    #[test]
+    fn test_link_assets_rewrites_current_css_path() {
+        let result = link_assets(
+            r#"<link rel="stylesheet" href="/css/bundle.css" /><!-- spall:scripts -->"#,
+            &hashed_names(),
+            OutputFormat::Scripts,
+        );
+        assert_eq!(
+            result,
+            r#"<link rel="stylesheet" href="/css/bundle.cccc.css" /><script src="/scripts/framework.aaaa.js"></script>
+<script src="/scripts/bundle.bbbb.js"></script>"#
+        );
+    }
+
+    #[test]
+    fn test_link_assets_es_modules() {
+        let result = link_assets(
+            "<!-- spall:styles --><!-- spall:scripts -->",
+            &hashed_names(),
+            OutputFormat::EsModules,
+        );
+        assert_eq!(
+            result,
+            r#"<link rel="stylesheet" href="/css/bundle.cccc.css" /><script type="module" src="/scripts/bundle.bbbb.js"></script>"#
+        );
+    }
+
+    #[test]
+    fn test_link_assets_es_modules_removes_inline_bootstrap() {
+        let index = "<!-- spall:styles --><body></body>\n    <!-- spall:scripts -->\n    <script>\n        SpallApp.instance = new SpallApp();\n    </script>\n    <script>console.log(1);</script>\n</html>";
+        let result = link_assets(index, &hashed_names(), OutputFormat::EsModules);
+        assert_eq!(
+            result,
+            "<link rel=\"stylesheet\" href=\"/css/bundle.cccc.css\" /><body></body>\n    <script type=\"module\" src=\"/scripts/bundle.bbbb.js\"></script>\n    <script>console.log(1);</script>\n</html>"
+        );
+
+        // Scripts need it to start the app
+        let result = link_assets(index, &hashed_names(), OutputFormat::Scripts);
+        assert!(result.contains(INLINE_BOOTSTRAP));
+    }
+
+    #[test]
+    fn test_link_assets_injects_missing() {
+        let result = link_assets(
+            "<head><title>a</title></head>",
+            &hashed_names(),
+            OutputFormat::Scripts,
+        );
+        assert_eq!(
+            result,
+            r#"<head><title>a</title><link rel="stylesheet" href="/css/bundle.cccc.css" />
+<script src="/scripts/framework.aaaa.js"></script>
+<script src="/scripts/bundle.bbbb.js"></script>
+</head>"#
+        );
+    }
+}
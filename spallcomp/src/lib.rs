@@ -1,51 +1,98 @@
+//! Compiler for spall projects.
+//!
+//! Library users should use [`Compiler`]:
+//! ```no_run
+//! use spallcomp::{CompilationSettings, Compiler};
+//!
+//! let compiler = Compiler::new(CompilationSettings::default());
+//! match compiler.compile(std::path::Path::new("my_project")) {
+//!     Ok(report) => println!("Built {} pages", report.pages.len()),
+//!     Err(diagnostics) => eprintln!("{diagnostics}"),
+//! }
+//! ```
+//! [`compile_project`] is the command line interface used by `spall build`.
+
 mod cli;
 mod common;
+mod common_files;
 mod compilation_settings;
+mod compiler;
+mod dependency_order;
+mod design_tokens;
 mod element_compiler;
-mod errs;
+pub mod errs;
+mod es_modules;
+mod global_css;
+mod index_file;
 mod logging;
+mod output_assets;
+mod page_chunks;
 mod parser;
 mod project_compiler;
+mod project_fs;
+mod project_index;
+mod project_paths;
+pub mod report;
+mod runtime;
 mod scoped_css;
 mod tag_attribute;
 mod tag_type;
 mod tokeniser;
 
-pub fn compile_project(raw_args: &Vec<String>) {
+pub use compilation_settings::{CompilationLogLevel, CompilationSettings, OutputFormat};
+pub use compiler::Compiler;
+pub use errs::Diagnostics;
+pub use project_fs::{DiskFs, MemoryFs, ProjectFs};
+pub use report::CompilationReport;
+
+/// Compile a project from command line arguments, printing progress and exiting the process if compilation fails
+pub fn compile_project(raw_args: &Vec<String>) -> CompilationReport {
     // Parse args and modify them as needed
     let args = cli::parse_args(raw_args);
     let settings = compilation_settings_from_args(&args);
-    let final_path = &std::env::current_dir()
-        .unwrap()
-        .join(args.project_path)
-        .canonicalize()
-        .unwrap();
+    let project_path = std::path::Path::new(&args.project_path);
 
-    let result = project_compiler::compile_project(final_path, settings);
-    if let Err(e) = result {
-        handle_compilation_error(e);
-        std::process::exit(1);
-    } else {
-        println!("Done!");
+    match Compiler::new(settings).compile(project_path) {
+        Ok(compilation_report) => {
+            for warning in &compilation_report.warnings {
+                println!("Warning: {warning}");
+            }
+            println!("Done!");
+            compilation_report
+        }
+        Err(diagnostics) => {
+            println!("{diagnostics}");
+            std::process::exit(1);
+        }
     }
 }
 
-fn compilation_settings_from_args(
-    args: &cli::Options,
-) -> compilation_settings::CompilationSettings {
-    compilation_settings::CompilationSettings {
+fn compilation_settings_from_args(args: &cli::Options) -> CompilationSettings {
+    CompilationSettings {
         log_level: match args.verbosity {
-            0 => compilation_settings::CompilationLogLevel::Minimal,
-            1 => compilation_settings::CompilationLogLevel::Brief,
-            2 => compilation_settings::CompilationLogLevel::PerStep,
-            other => panic!("Max verbosity is 2 (you said {other})"),
+            0 => CompilationLogLevel::Minimal,
+            1 => CompilationLogLevel::Brief,
+            2 => CompilationLogLevel::PerStep,
+            other => {
+                println!("Max verbosity is 2 (you said {other})");
+                std::process::exit(1);
+            }
         },
         debug_tokens: args.debug_tokens,
         minify_files: !args.do_not_minify,
         preserve_html_comments: args.preserve_html_comments,
+        release: args.release,
+        output_format: if args.es_modules {
+            OutputFormat::EsModules
+        } else {
+            OutputFormat::Scripts
+        },
+        split_pages: args.split_pages,
+        warn_unmatched_selectors: args.warn_unmatched_selectors,
+        global_stylesheet: if args.global_stylesheet.is_empty() {
+            None
+        } else {
+            Some(args.global_stylesheet.clone().into())
+        },
     }
 }
-
-fn handle_compilation_error(e: errs::CompilationError) {
-    println!("{e}");
-}
@@ -1,13 +1,9 @@
 use crate::compilation_settings::CompilationLogLevel;
 
-pub fn log_always(data: &str) {
-    // Log something that should always be logged, such as what project we're doing
+pub fn log_always(data: &str, log_level: CompilationLogLevel) {
+    // Log something that should always be logged (unless we're silent), such as what project we're doing
 
-    log(
-        data,
-        CompilationLogLevel::Minimal,
-        CompilationLogLevel::Minimal,
-    );
+    log(data, log_level, CompilationLogLevel::Minimal);
 }
 
 pub fn log_brief(data: &str, log_level: CompilationLogLevel) {
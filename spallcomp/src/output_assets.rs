@@ -0,0 +1,87 @@
+// Naming of the files that the compiler produces and that need to be linked from index.html.
+// In release builds the names contain a hash of the file content so that browsers don't keep using stale cached copies.
+
+pub const FRAMEWORK_SCRIPT_NAME: &str = "scripts/framework.js";
+pub const BUNDLE_SCRIPT_NAME: &str = "scripts/bundle.js";
+pub const SCOPED_CSS_BUNDLE_NAME: &str = "css/bundle.css";
+// Where the CSS bundle was written before it had its own directory, which older index files still link to
+pub const FORMER_SCOPED_CSS_BUNDLE_NAME: &str = "static/bundle.css";
+
+// Paths of the output assets, relative to the build directory (eg "scripts/bundle.3fa9c1d2.js")
+pub struct OutputAssetNames {
+    pub framework_script: String,
+    pub bundle_script: String,
+    pub scoped_css_bundle: String,
+}
+
+impl OutputAssetNames {
+    pub fn new(
+        framework_script: &str,
+        bundle_script: &str,
+        scoped_css_bundle: &str,
+        hash_names: bool,
+    ) -> OutputAssetNames {
+        OutputAssetNames {
+            framework_script: asset_name(FRAMEWORK_SCRIPT_NAME, framework_script, hash_names),
+            bundle_script: asset_name(BUNDLE_SCRIPT_NAME, bundle_script, hash_names),
+            scoped_css_bundle: asset_name(SCOPED_CSS_BUNDLE_NAME, scoped_css_bundle, hash_names),
+        }
+    }
+}
+
+// A script that is loaded by the app itself rather than linked from index.html, eg a module or a page chunk
+pub struct ScriptFile {
+    pub name: String, // relative to the build dir, like the other output assets
+    pub content: String,
+}
+
+pub fn asset_name(base_name: &str, content: impl AsRef<[u8]>, hash_name: bool) -> String {
+    // Turn a name like scripts/bundle.js into scripts/bundle.3fa9c1d2.js if we're hashing names
+
+    if !hash_name {
+        return base_name.to_string();
+    }
+    let hash = content_hash(content.as_ref());
+    match base_name.rsplit_once('.') {
+        Some((stem, extension)) => format!("{stem}.{hash}.{extension}"),
+        None => format!("{base_name}.{hash}"),
+    }
+}
+
+pub fn asset_url(asset_name: &str) -> String {
+    // Absolute url that the asset can be fetched from by the browser
+
+    format!("/{asset_name}")
+}
+
+fn content_hash(content: &[u8]) -> String {
+    // Short hash of file content for cache busting. Uses FNV-1a since it's tiny and stable between builds/rust versions,
+    // unlike the std hasher.
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in content {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)[..8].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asset_name() {
+        assert_eq!(
+            asset_name("scripts/bundle.js", "abc", false),
+            "scripts/bundle.js"
+        );
+
+        let hashed = asset_name("scripts/bundle.js", "abc", true);
+        assert!(hashed.starts_with("scripts/bundle."));
+        assert!(hashed.ends_with(".js"));
+        assert_eq!(hashed.len(), "scripts/bundle..js".len() + 8);
+        assert_eq!(hashed, asset_name("scripts/bundle.js", "abc", true));
+        assert_ne!(hashed, asset_name("scripts/bundle.js", "abcd", true));
+    }
+}
@@ -0,0 +1,320 @@
+// Splits the compiled elements into chunks that are loaded when a page is first visited (CompilationSettings::split_pages).
+// The root element and everything it uses (directly or through other elements) stay in bundle.js since they're needed straight away.
+// Every page gets a chunk with the page and the elements that only it uses.
+// Elements used by several pages go into a shared chunk for that set of pages, so that they're only fetched once.
+// bundle.js gets a route table of SpallLazyPages that know which chunks to load for each page.
+
+use crate::element_compiler::{self, CompiledElement, ElementType};
+use crate::output_assets::{self, ScriptFile};
+
+const CHUNKS_DIR_NAME: &str = "scripts/chunks";
+const ROOT_ELEMENT_NAME: &str = "Root";
+
+pub struct PageChunkOutput {
+    pub main_contents: Vec<String>, // the elements that stay in bundle.js
+    pub route_table: String,        // goes at the end of bundle.js
+    pub chunks: Vec<ScriptFile>,
+}
+
+// Which elements go where. Elements are referred to by their index in the list of compiled elements
+struct ChunkPlan {
+    main_elements: Vec<usize>,
+    chunks: Vec<PlannedChunk>,
+}
+
+struct PlannedChunk {
+    name: String,
+    page: Option<usize>, // the page whose chunk this is, or None for shared chunks
+    used_by_pages: Vec<usize>,
+    elements: Vec<usize>,
+}
+
+/// Split `compiled_elements` into bundle.js content and page chunks, as plain scripts.
+///
+/// If `hash_names` is set, the chunk names contain a hash of their content (the route table refers to the final names)
+pub fn build_page_chunks(
+    compiled_elements: &[&CompiledElement],
+    minify: bool,
+    hash_names: bool,
+) -> PageChunkOutput {
+    let plan = plan_chunks(compiled_elements);
+
+    let mut chunks = vec![];
+    for planned_chunk in &plan.chunks {
+        let mut contents: Vec<String> = planned_chunk
+            .elements
+            .iter()
+            .map(|index| compiled_elements[*index].content.clone())
+            .collect();
+        // Let the SpallLazyPage know that its page is here
+        if let Some(page) = planned_chunk.page {
+            contents.push(format!(
+                "SpallLazyPage.loadedPages.{0} = {0};",
+                compiled_elements[page].compiled_element_name
+            ));
+        }
+        let mut content = contents.join(";\n");
+        if minify {
+            content = minifier::js::minify(&content).to_string();
+        }
+        chunks.push(ScriptFile {
+            name: output_assets::asset_name(
+                &format!("{CHUNKS_DIR_NAME}/{}.js", planned_chunk.name),
+                &content,
+                hash_names,
+            ),
+            content,
+        });
+    }
+
+    // Each page loads the shared chunks it uses, then its own chunk
+    let mut route_table = vec![];
+    for (chunk_index, planned_chunk) in plan.chunks.iter().enumerate() {
+        let Some(page) = planned_chunk.page else {
+            continue;
+        };
+        let mut chunk_urls: Vec<String> = plan
+            .chunks
+            .iter()
+            .zip(&chunks)
+            .filter(|(c, _)| c.page.is_none() && c.used_by_pages.contains(&page))
+            .map(|(_, chunk)| format!("\"{}\"", output_assets::asset_url(&chunk.name)))
+            .collect();
+        chunk_urls.push(format!(
+            "\"{}\"",
+            output_assets::asset_url(&chunks[chunk_index].name)
+        ));
+
+        let compiled_page = compiled_elements[page];
+        for route in &compiled_page.page_routes {
+            route_table.push(format!(
+                "SpallRouter.routeList.push([{},new SpallLazyPage(\"{}\",[{}])]);",
+                element_compiler::compile_page_route(route),
+                compiled_page.compiled_element_name,
+                chunk_urls.join(",")
+            ));
+        }
+    }
+
+    PageChunkOutput {
+        main_contents: plan
+            .main_elements
+            .iter()
+            .map(|index| compiled_elements[*index].content.clone())
+            .collect(),
+        route_table: route_table.join("\n"),
+        chunks,
+    }
+}
+
+fn plan_chunks(compiled_elements: &[&CompiledElement]) -> ChunkPlan {
+    let used_by_root = compiled_elements
+        .iter()
+        .position(|e| e.element_type == ElementType::Basic && e.element_name == ROOT_ELEMENT_NAME)
+        .map(|root| find_used_elements(compiled_elements, root))
+        .unwrap_or_default();
+    let pages: Vec<(usize, Vec<usize>)> = compiled_elements
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.element_type == ElementType::Page)
+        .map(|(index, _)| (index, find_used_elements(compiled_elements, index)))
+        .collect();
+
+    // Group the elements that aren't needed by the root by which pages use them.
+    // Elements that nothing uses stay in bundle.js, as there's no page to load them with
+    let mut main_elements = vec![];
+    let mut groups: Vec<(Vec<usize>, Vec<usize>)> = vec![];
+    for (index, element) in compiled_elements.iter().enumerate() {
+        if element.element_type == ElementType::Page {
+            continue;
+        }
+        let using_pages: Vec<usize> = pages
+            .iter()
+            .filter(|(_, used)| used.contains(&index))
+            .map(|(page, _)| *page)
+            .collect();
+        if used_by_root.contains(&index) || using_pages.is_empty() {
+            main_elements.push(index);
+            continue;
+        }
+        match groups.iter_mut().find(|(p, _)| *p == using_pages) {
+            Some((_, elements)) => elements.push(index),
+            None => groups.push((using_pages, vec![index])),
+        }
+    }
+
+    // Shared chunks come first so that they're listed before the page chunks that need them
+    let mut chunks = vec![];
+    for (using_pages, elements) in &groups {
+        if using_pages.len() > 1 {
+            chunks.push(PlannedChunk {
+                name: format!(
+                    "shared-{}",
+                    using_pages
+                        .iter()
+                        .map(|page| compiled_elements[*page].element_name.as_str())
+                        .collect::<Vec<&str>>()
+                        .join("-")
+                ),
+                page: None,
+                used_by_pages: using_pages.clone(),
+                elements: elements.clone(),
+            });
+        }
+    }
+    for (page, _) in &pages {
+        let mut elements = vec![*page];
+        if let Some((_, page_elements)) = groups.iter().find(|(p, _)| *p == [*page]) {
+            elements.extend(page_elements);
+        }
+        chunks.push(PlannedChunk {
+            name: compiled_elements[*page].element_name.clone(),
+            page: Some(*page),
+            used_by_pages: vec![*page],
+            elements,
+        });
+    }
+
+    ChunkPlan {
+        main_elements,
+        chunks,
+    }
+}
+
+fn find_used_elements(compiled_elements: &[&CompiledElement], start: usize) -> Vec<usize> {
+    // Indices of the elements that start uses, directly or through other elements.
+    // Names that aren't compiled elements (eg RoutedApp, which is part of the runtime) are skipped
+
+    let mut used = vec![];
+    let mut to_visit = vec![start];
+    while let Some(index) = to_visit.pop() {
+        for name in &compiled_elements[index].referenced_elements {
+            let referenced = compiled_elements
+                .iter()
+                .position(|e| e.element_type == ElementType::Basic && e.element_name == *name);
+            if let Some(referenced) = referenced {
+                if referenced != start && !used.contains(&referenced) {
+                    used.push(referenced);
+                    to_visit.push(referenced);
+                }
+            }
+        }
+    }
+    used
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compilation_settings::CompilationSettings;
+
+    fn compile(content: &str, name: &str, element_type: ElementType) -> CompiledElement {
+        let settings = CompilationSettings {
+            split_pages: true,
+            ..Default::default()
+        };
+        element_compiler::compile_test_element(content, name, element_type, settings)
+    }
+
+    fn chunk_names(output: &PageChunkOutput) -> Vec<&str> {
+        output.chunks.iter().map(|c| c.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_plan_chunks() {
+        let elements = [
+            compile("<Header /><RoutedApp />", "Root", ElementType::Basic),
+            compile("<p>Header</p>", "Header", ElementType::Basic),
+            compile("<Chart />", "Graph", ElementType::Basic),
+            compile("<p>Chart</p>", "Chart", ElementType::Basic),
+            compile("<p>Button</p>", "Button", ElementType::Basic),
+            compile("<p>Unused</p>", "Unused", ElementType::Basic),
+            compile(
+                "<pageroute>/</pageroute><Graph /><Button /><Header />",
+                "Index",
+                ElementType::Page,
+            ),
+            compile(
+                "<pageroute>/about</pageroute><Button />",
+                "About",
+                ElementType::Page,
+            ),
+            compile(
+                "<pageroute>/stats</pageroute><Button />",
+                "Stats",
+                ElementType::Page,
+            ),
+        ];
+        let elements: Vec<&CompiledElement> = elements.iter().collect();
+        let plan = plan_chunks(&elements);
+
+        assert_eq!(plan.main_elements, vec![0, 1, 5]);
+        let chunks: Vec<(&str, &Vec<usize>)> = plan
+            .chunks
+            .iter()
+            .map(|c| (c.name.as_str(), &c.elements))
+            .collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("shared-Index-About-Stats", &vec![4]),
+                ("Index", &vec![6, 2, 3]),
+                ("About", &vec![7]),
+                ("Stats", &vec![8]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_page_chunks() {
+        let elements = [
+            compile("<RoutedApp />", "Root", ElementType::Basic),
+            compile("<p>Shared</p>", "Shared", ElementType::Basic),
+            compile(
+                "<pageroute>/</pageroute><pageroute>/home</pageroute><Shared />",
+                "Index",
+                ElementType::Page,
+            ),
+            compile(
+                "<pageroute>/about/${name}</pageroute><Shared />",
+                "About",
+                ElementType::Page,
+            ),
+        ];
+        let elements: Vec<&CompiledElement> = elements.iter().collect();
+        let output = build_page_chunks(&elements, false, false);
+
+        assert_eq!(
+            chunk_names(&output),
+            vec![
+                "scripts/chunks/shared-Index-About.js",
+                "scripts/chunks/Index.js",
+                "scripts/chunks/About.js"
+            ]
+        );
+        assert_eq!(output.main_contents.len(), 1);
+        assert!(output.chunks[1]
+            .content
+            .ends_with("SpallLazyPage.loadedPages.__SpallCompiledIndex = __SpallCompiledIndex;"));
+        assert!(!output.chunks[1].content.contains("SpallRouter.routeList"));
+        assert_eq!(
+            output.route_table,
+            r#"SpallRouter.routeList.push([[],new SpallLazyPage("__SpallCompiledIndex",["/scripts/chunks/shared-Index-About.js","/scripts/chunks/Index.js"])]);
+SpallRouter.routeList.push([[new SpallStringRouteSection("home")],new SpallLazyPage("__SpallCompiledIndex",["/scripts/chunks/shared-Index-About.js","/scripts/chunks/Index.js"])]);
+SpallRouter.routeList.push([[new SpallStringRouteSection("about"), new SpallPropertyRouteSection("name")],new SpallLazyPage("__SpallCompiledAbout",["/scripts/chunks/shared-Index-About.js","/scripts/chunks/About.js"])]);"#
+        );
+    }
+
+    #[test]
+    fn test_hashed_chunk_names() {
+        let elements = [
+            compile("<RoutedApp />", "Root", ElementType::Basic),
+            compile("<pageroute>/</pageroute>", "Index", ElementType::Page),
+        ];
+        let elements: Vec<&CompiledElement> = elements.iter().collect();
+        let output = build_page_chunks(&elements, false, true);
+        let chunk_name = &output.chunks[0].name;
+        assert!(chunk_name.starts_with("scripts/chunks/Index."));
+        assert!(output.route_table.contains(&format!("\"/{chunk_name}\"")));
+    }
+}
@@ -5,14 +5,14 @@ use crate::tag_attribute::TagAttribute;
 use crate::tag_type::TagType;
 use crate::tokeniser;
 
-// Spans are not used to contain the inner text of these tags
-static SPANLESS_INNER_TEXTS: [&'static str; 3] = ["script", "title", "pageroute"];
+// The content of these tags isn't markup, so it is kept as their inner text rather than as text nodes
+static INNER_TEXT_TAGS: [&'static str; 4] = ["script", "style", "title", "pageroute"];
 
 pub type NodeIndex = usize;
 
 pub struct Node {
-    // The inner text of a node goes before its children when it is rendered.
-    // If you want to intersperse text and children then you can just use spans as children to hold the text.
+    // Text in markup is held by text nodes, which are children in the same order as the tags around them.
+    // Only tags whose content isn't rendered (like <script>) have an inner text instead.
     pub data: NodeData,
     pub children: Vec<NodeIndex>,
     pub parent: Option<NodeIndex>,
@@ -20,7 +20,9 @@ pub struct Node {
 
 pub enum NodeData {
     Markup(NodeMarkupData),
+    Text(NodeTextData),
     InlineJavascript(NodeInlineJavascriptData),
+    Comment(NodeCommentData),
 }
 
 // We can't pass specific enum variants around so just make structs that the enum wraps
@@ -29,10 +31,17 @@ pub struct NodeMarkupData {
     pub tag_attributes: Vec<TagAttribute>,
     pub is_standalone: bool,
     pub inner_text: String,
+    pub inner_text_offset: usize, // where the inner text starts in the source file
+}
+pub struct NodeTextData {
+    pub value: String,
 }
 pub struct NodeInlineJavascriptData {
     pub value: String,
 }
+pub struct NodeCommentData {
+    pub value: String,
+}
 
 pub struct Tree {
     pub nodes: Vec<Node>,
@@ -48,6 +57,7 @@ impl Tree {
                 tag_attributes: vec![],
                 is_standalone: false,
                 inner_text: "".to_string(),
+                inner_text_offset: 0,
             }),
             children: vec![],
             parent: None,
@@ -122,6 +132,9 @@ pub fn parse_element(tokens: &Vec<tokeniser::Token>) -> Result<Tree, errs::Marku
             tokeniser::Token::InlineJavascript(inner_token) => {
                 read_javascript_token(&mut tree, &mut node_stack, &inner_token)?
             }
+            tokeniser::Token::Comment(inner_token) => {
+                read_comment_token(&mut tree, &node_stack, inner_token)?
+            }
         }
     }
 
@@ -163,6 +176,7 @@ fn read_tag_token(
                         tag_attributes: token.attributes.clone(),
                         is_standalone: false,
                         inner_text: "".to_string(),
+                        inner_text_offset: 0,
                     }),
                     parent: None,
                     children: vec![],
@@ -210,6 +224,7 @@ fn read_tag_token(
                         tag_attributes: token.attributes.clone(),
                         is_standalone: true,
                         inner_text: "".to_string(),
+                        inner_text_offset: 0,
                     }),
                     parent: None,
                     children: vec![],
@@ -225,33 +240,28 @@ fn read_content_token(
     node_stack: &mut Vec<NodeIndex>,
     token: &tokeniser::ContentToken,
 ) -> Result<(), errs::MarkupSyntaxError> {
-    // Transform a content token into a span node
+    // Transform a content token into a text node, or the inner text of its parent if that isn't markup
 
-    let mut wrap_in_span = true;
     let parent_idx = *node_stack
         .last()
         .ok_or(errs::MarkupSyntaxError::OrphanedNode)?;
-    let parent = tree.get_node_mut(parent_idx);
-    if let NodeData::Markup(inner_data) = &parent.data {
-        wrap_in_span = !SPANLESS_INNER_TEXTS.contains(&inner_data.tag_name.as_str())
-    }
-    if wrap_in_span {
-        tree.add_node(
-            parent_idx,
-            Node {
-                data: NodeData::Markup(NodeMarkupData {
-                    tag_name: "span".to_string(),
-                    tag_attributes: vec![],
-                    is_standalone: false,
-                    inner_text: token.value.to_string(),
-                }),
-                parent: None,
-                children: vec![],
-            },
-        );
-    } else if let NodeData::Markup(inner_data) = &mut parent.data {
-        inner_data.inner_text = token.value.clone();
+    if let NodeData::Markup(inner_data) = &mut tree.get_node_mut(parent_idx).data {
+        if INNER_TEXT_TAGS.contains(&inner_data.tag_name.as_str()) {
+            inner_data.inner_text = token.value.clone();
+            inner_data.inner_text_offset = token.offset;
+            return Ok(());
+        }
     }
+    tree.add_node(
+        parent_idx,
+        Node {
+            data: NodeData::Text(NodeTextData {
+                value: token.value.clone(),
+            }),
+            parent: None,
+            children: vec![],
+        },
+    );
     Ok(())
 }
 
@@ -278,3 +288,26 @@ fn read_javascript_token(
 
     Ok(())
 }
+
+fn read_comment_token(
+    tree: &mut Tree,
+    node_stack: &[NodeIndex],
+    token: &tokeniser::CommentToken,
+) -> Result<(), errs::MarkupSyntaxError> {
+    // Comments are kept in the tree, it's up to the element compiler whether they end up in the markup
+
+    tree.add_node(
+        *node_stack
+            .last()
+            .ok_or(errs::MarkupSyntaxError::OrphanedNode)?,
+        Node {
+            data: NodeData::Comment(NodeCommentData {
+                value: token.value.clone(),
+            }),
+            parent: None,
+            children: vec![],
+        },
+    );
+
+    Ok(())
+}
@@ -1,132 +1,352 @@
-use std::collections::HashMap;
-use std::fs;
 use std::path::{Path, PathBuf};
 
-use fs_extra;
-use include_dir::{include_dir, Dir, DirEntry};
-use itertools::Itertools;
-use minifier;
-
+use crate::common_files;
 use crate::compilation_settings::*;
+use crate::design_tokens;
 use crate::element_compiler;
 use crate::errs;
+use crate::es_modules;
+use crate::global_css;
+use crate::index_file;
 use crate::logging;
+use crate::output_assets::{self, OutputAssetNames, ScriptFile};
+use crate::page_chunks;
+use crate::project_fs::ProjectFs;
+use crate::project_index::{self, ProjectIndex};
+use crate::project_paths::ProjectPaths;
+use crate::report;
+use crate::runtime;
 use crate::scoped_css;
+use crate::scoped_css::preprocessor::CssPartials;
+
+const RUNTIME_VERSION: &str = env!("CARGO_PKG_VERSION");
+// Built into the runtime rather than compiled from the project
+const ROUTED_APP_ELEMENT_NAME: &str = "RoutedApp";
 
-#[allow(dead_code)]
-struct ProjectPaths {
-    root_dir: PathBuf,
-    build_dir: PathBuf,
-    build_scripts_dir: PathBuf,
-    meta_dir: PathBuf,
-    elements_dir: PathBuf,
-    pages_dir: PathBuf,
-    common_dir: PathBuf,
-    static_dir: PathBuf,
-    build_static_dir: PathBuf,
-    scoped_css_dir: PathBuf,
+struct CompiledElementFile {
+    source_file: PathBuf,
+    element: element_compiler::CompiledElement,
 }
 
-impl ProjectPaths {
-    pub fn new(project_dir: &Path) -> ProjectPaths {
-        ProjectPaths {
-            root_dir: project_dir.to_path_buf(),
-            build_dir: project_dir.join("build"),
-            build_scripts_dir: project_dir.join("build/scripts"),
-            meta_dir: project_dir.join("meta"),
-            elements_dir: project_dir.join("elements"),
-            pages_dir: project_dir.join("pages"),
-            common_dir: project_dir.join("common"),
-            static_dir: project_dir.join("static"),
-            build_static_dir: project_dir.join("build/static"),
-            scoped_css_dir: project_dir.join("styles"),
-        }
-    }
+struct CompiledScopedCssFile {
+    source_file: PathBuf,
+    source_content: String,
+    scope_name: String,
+    content: String,
+}
+
+struct CompiledDesignTokens {
+    tokens: design_tokens::DesignTokens,
+    css: String,
 }
 
-const FRAMEWORK_RUNTIME_FILES: Dir = include_dir!("$CARGO_MANIFEST_DIR/runtime");
+struct CompiledGlobalCss {
+    source_files: Vec<PathBuf>,
+    static_assets: Vec<global_css::HashedStaticAsset>,
+    content: String,
+}
+
+// The scripts and CSS that were saved to the build dir
+struct OutputContents<'a> {
+    runtime: &'a str,
+    bundle: &'a str,
+    scoped_css_bundle: &'a str,
+    module_files: &'a [ScriptFile],
+    chunk_files: &'a [ScriptFile],
+}
 
 pub fn compile_project(
     project_dir: &Path,
-    compilation_settings: CompilationSettings,
-) -> Result<(), errs::CompilationError> {
-    // Entry point of the compiler
+    compilation_settings: &CompilationSettings,
+    fs: &dyn ProjectFs,
+) -> Result<report::CompilationReport, errs::Diagnostics> {
+    // Entry point of the compiler.
+    // Warnings are collected as we go so they can be given back even if compilation fails.
+    // On success they are moved into the report by compile_project_inner
+
+    let mut warnings = vec![];
+    compile_project_inner(project_dir, compilation_settings, fs, &mut warnings).map_err(
+        |mut diagnostics| {
+            diagnostics.warnings = warnings;
+            diagnostics
+        },
+    )
+}
 
-    logging::log_always(format!("Compiling project {}", project_dir.to_string_lossy()).as_str());
+fn compile_project_inner(
+    project_dir: &Path,
+    compilation_settings: &CompilationSettings,
+    fs: &dyn ProjectFs,
+    warnings: &mut Vec<errs::CompilationWarning>,
+) -> Result<report::CompilationReport, errs::Diagnostics> {
+    logging::log_always(
+        format!("Compiling project {}", project_dir.to_string_lossy()).as_str(),
+        compilation_settings.log_level,
+    );
 
     logging::log_brief("Preparing for compilation", compilation_settings.log_level);
 
     let project_paths = ProjectPaths::new(project_dir);
 
-    check_required_dirs_exist(&project_paths)?;
+    check_required_dirs_exist(&project_paths, fs)?;
 
-    logging::log_per_step("Setting up build directory", compilation_settings.log_level);
-    setup_build_dir(&project_paths);
-    copy_index_file(&project_paths)?;
-    copy_static_files(&project_paths);
+    let index_content = read_index_file(&project_paths, fs)?;
+    let project_index = project_index::index_project(&project_paths, fs, warnings)?;
 
-    // Build JSruntime (should not do this every compilation but it's easier than writing it in macros)
-    logging::log_per_step(
-        "Building and saving runtime",
-        compilation_settings.log_level,
-    );
-    let mut runtime = build_framework_runtime();
-    if compilation_settings.minify_files {
-        runtime = minifier::js::minify(&runtime).to_string();
-    }
-    write_framework_runtime(&project_paths, &runtime);
+    logging::log_per_step("Setting up build directory", compilation_settings.log_level);
+    setup_build_dir(&project_paths, fs)?;
+    copy_static_files(&project_paths, &project_index, fs)?;
 
-    // Compile elements and pages
+    // Compile elements and pages.
+    // Errors in individual files don't stop the other files from being compiled, so that they can all be reported at once
     logging::log_brief(
         "Compiling elements and pages",
         compilation_settings.log_level,
     );
+    let css_partials = read_css_partials(&project_index, fs)?;
     let mut last_element_id = 0;
+    let mut file_errors = vec![];
     let mut compiled_files = compile_elements(
-        &project_paths.elements_dir,
-        &compilation_settings,
+        &project_index.elements,
+        &css_partials,
+        compilation_settings,
         element_compiler::ElementType::Basic,
         &mut last_element_id,
+        &mut file_errors,
+        fs,
     )?;
     compiled_files.extend(compile_elements(
-        &project_paths.pages_dir,
-        &compilation_settings,
+        &project_index.pages,
+        &css_partials,
+        compilation_settings,
         element_compiler::ElementType::Page,
         &mut last_element_id,
+        &mut file_errors,
+        fs,
     )?);
 
-    check_root_element_exists(&compiled_files)?;
+    // Manage scoped CSS
+    logging::log_brief("Compiling scoped CSS", compilation_settings.log_level);
+    let design_tokens =
+        compile_design_tokens(&project_paths, compilation_settings, &mut file_errors, fs)?;
+    let scoped_css_files = compile_scoped_css_files(
+        &project_index,
+        &css_partials,
+        compilation_settings,
+        &mut file_errors,
+        fs,
+    )?;
+    let global_css = compile_global_stylesheet(
+        &project_paths,
+        &css_partials,
+        compilation_settings,
+        &mut file_errors,
+        warnings,
+        fs,
+    )?;
+    if compilation_settings.warn_unmatched_selectors {
+        warnings.extend(find_unmatched_selectors(
+            &project_paths,
+            &scoped_css_files,
+            &css_partials,
+            &compiled_files,
+        ));
+    }
+    if let Some(design_tokens) = &design_tokens {
+        warnings.extend(find_unknown_css_variables(
+            &project_paths,
+            &design_tokens.tokens,
+            global_css.as_ref(),
+            &scoped_css_files,
+            &compiled_files,
+        ));
+    }
 
-    // Get common files too
-    let mut compiled_file_contents: Vec<String> =
-        compiled_files.iter().map(|x| x.content.clone()).collect();
-    compiled_file_contents.extend(compile_common_files(&project_paths));
+    if !file_errors.is_empty() {
+        return Err(errs::Diagnostics::new(file_errors));
+    }
+    check_root_element_exists(&compiled_files)?;
+    warnings.extend(find_duplicate_page_routes(&compiled_files));
+
+    // Only include the parts of the runtime that the app uses.
+    // The runtime files are put in order at cargo build time, so this is just picking and joining them
+    logging::log_per_step("Building runtime", compilation_settings.log_level);
+    let runtime_files = runtime::select_runtime_files(&find_runtime_usage(
+        &project_index,
+        &compiled_files,
+        compilation_settings,
+    ));
+
+    let mut common_files = compile_common_files(
+        &project_paths,
+        &project_index,
+        &compiled_files,
+        fs,
+        warnings,
+    )?;
+    if let Some(design_tokens) = &design_tokens {
+        common_files.insert(
+            0,
+            design_tokens
+                .tokens
+                .theme_script(&project_paths.tokens_file),
+        );
+    }
 
-    // Bundle JS and save
+    // Bundle JS
     logging::log_brief("Bundling application", compilation_settings.log_level);
-    let mut bundle = bundle_compiled_javascript_files(&compiled_file_contents);
-    if compilation_settings.minify_files {
-        bundle = minifier::js::minify(&bundle).to_string();
+    let (runtime, bundle, module_files, chunk_files) = match compilation_settings.output_format {
+        OutputFormat::Scripts => {
+            let runtime = runtime::build_framework_runtime(&runtime_files);
+            let compiled_elements: Vec<&element_compiler::CompiledElement> =
+                compiled_files.iter().map(|f| &f.element).collect();
+            // When splitting pages, only the elements needed straight away go in the bundle, along with the route table
+            let (mut bundle_contents, route_table, chunk_files) =
+                if compilation_settings.split_pages {
+                    let page_chunks = page_chunks::build_page_chunks(
+                        &compiled_elements,
+                        compilation_settings.minify_files,
+                        compilation_settings.release,
+                    );
+                    (
+                        page_chunks.main_contents,
+                        Some(page_chunks.route_table),
+                        page_chunks.chunks,
+                    )
+                } else {
+                    (
+                        compiled_elements
+                            .iter()
+                            .map(|e| e.content.clone())
+                            .collect(),
+                        None,
+                        vec![],
+                    )
+                };
+            bundle_contents.extend(common_files.into_iter().map(|f| f.content));
+            bundle_contents.extend(route_table);
+            let bundle = bundle_compiled_javascript_files(&bundle_contents);
+            (
+                minify_javascript(runtime, compilation_settings),
+                minify_javascript(bundle, compilation_settings),
+                vec![],
+                chunk_files,
+            )
+        }
+        OutputFormat::EsModules => {
+            // The modules import framework.js, so its final name is needed before building them
+            let runtime = minify_javascript(
+                runtime::build_framework_module(&runtime_files),
+                compilation_settings,
+            );
+            let framework_name = output_assets::asset_name(
+                output_assets::FRAMEWORK_SCRIPT_NAME,
+                &runtime,
+                compilation_settings.release,
+            );
+            let es_module_output = es_modules::build_es_modules(
+                &compiled_files
+                    .iter()
+                    .map(|f| &f.element)
+                    .collect::<Vec<_>>(),
+                &common_files,
+                &framework_name,
+                &runtime::runtime_classes(&runtime_files),
+                compilation_settings.minify_files,
+                compilation_settings.release,
+                compilation_settings.split_pages,
+            );
+            (
+                runtime,
+                es_module_output.entry,
+                es_module_output.modules,
+                vec![],
+            )
+        }
+    };
+
+    let scoped_css_bundle = bundle_css_files(
+        design_tokens.as_ref(),
+        global_css.as_ref(),
+        &scoped_css_files,
+        &compiled_files,
+        compilation_settings.minify_files,
+    );
+    warnings.sort_by_key(|w| w.to_string());
+
+    // Save everything, then link it into the index file
+    logging::log_brief("Saving output", compilation_settings.log_level);
+    let asset_names = OutputAssetNames::new(
+        &runtime,
+        &bundle,
+        &scoped_css_bundle,
+        compilation_settings.release,
+    );
+    save_output_asset(&project_paths, &asset_names.framework_script, &runtime, fs)?;
+    save_output_asset(&project_paths, &asset_names.bundle_script, &bundle, fs)?;
+    for script_file in module_files.iter().chain(&chunk_files) {
+        save_output_asset(&project_paths, &script_file.name, &script_file.content, fs)?;
     }
-    save_javascript_bundle(&project_paths, &bundle);
+    save_output_asset(
+        &project_paths,
+        &asset_names.scoped_css_bundle,
+        &scoped_css_bundle,
+        fs,
+    )?;
+    save_static_assets(&project_paths, global_css.as_ref(), fs)?;
+    save_output_asset(
+        &project_paths,
+        "index.html",
+        &index_file::link_assets(
+            &index_content,
+            &asset_names,
+            compilation_settings.output_format,
+        ),
+        fs,
+    )?;
 
-    // Manage scoped CSS
-    logging::log_brief("Compiling scoped CSS", compilation_settings.log_level);
-    let scoped_css_files = compile_scoped_css_files(&project_paths, &compilation_settings)?;
-    let scoped_css_bundle = bundle_scoped_css_files(&scoped_css_files);
-    save_scoped_css_bundle(&project_paths, scoped_css_bundle);
+    let mut compilation_report = build_report(
+        &project_paths,
+        &compiled_files,
+        &scoped_css_files,
+        &asset_names,
+        global_css.as_ref(),
+        &runtime_files,
+        &OutputContents {
+            runtime: &runtime,
+            bundle: &bundle,
+            scoped_css_bundle: &scoped_css_bundle,
+            module_files: &module_files,
+            chunk_files: &chunk_files,
+        },
+    );
+    compilation_report.warnings = std::mem::take(warnings);
+    save_output_asset(
+        &project_paths,
+        report::MANIFEST_FILE_NAME,
+        &compilation_report.to_json(),
+        fs,
+    )?;
 
-    Ok(())
+    Ok(compilation_report)
 }
 
-fn check_required_dirs_exist(project_paths: &ProjectPaths) -> Result<(), errs::CompilationError> {
+fn read_file(path: &Path, fs: &dyn ProjectFs) -> Result<String, errs::CompilationError> {
+    fs.read_to_string(path)
+        .map_err(errs::io_error("reading", path))
+}
+
+fn check_required_dirs_exist(
+    project_paths: &ProjectPaths,
+    fs: &dyn ProjectFs,
+) -> Result<(), errs::CompilationError> {
     // Check that the essential directories for a spall project are present in the project.
 
-    if !project_paths.elements_dir.exists() {
+    if !fs.exists(&project_paths.elements_dir) {
         Err(errs::CompilationError::Project(
             errs::ProjectCompilationError::NoElementsDirectory,
         ))
-    } else if !project_paths.meta_dir.exists() {
+    } else if !fs.exists(&project_paths.meta_dir) {
         Err(errs::CompilationError::Project(
             errs::ProjectCompilationError::NoMetaDirectory,
         ))
@@ -135,202 +355,165 @@ fn check_required_dirs_exist(project_paths: &ProjectPaths) -> Result<(), errs::C
     }
 }
 
-fn setup_build_dir(project_paths: &ProjectPaths) {
-    // Create build directory and build scripts directory
+fn setup_build_dir(
+    project_paths: &ProjectPaths,
+    fs: &dyn ProjectFs,
+) -> Result<(), errs::CompilationError> {
+    // Create build directory and empty build scripts and css directories.
+    // The output dirs are cleaned because hashed file names would otherwise pile up between builds.
 
-    if !project_paths.build_dir.is_dir() {
-        fs::create_dir(&project_paths.build_dir).expect("Failed to create build directory");
+    let build_dir = &project_paths.build_dir;
+    if !fs.is_dir(build_dir) {
+        fs.create_dir_all(build_dir)
+            .map_err(errs::io_error("creating", build_dir))?;
     }
-    if !project_paths.build_scripts_dir.is_dir() {
-        fs::create_dir(&project_paths.build_scripts_dir)
-            .expect("Failed creating build scripts dir");
+    for output_dir in [
+        &project_paths.build_scripts_dir,
+        &project_paths.build_css_dir,
+    ] {
+        if fs.is_dir(output_dir) {
+            fs.remove(output_dir)
+                .map_err(errs::io_error("deleting", output_dir))?;
+        }
+        fs.create_dir_all(output_dir)
+            .map_err(errs::io_error("creating", output_dir))?;
     }
+    Ok(())
 }
 
-fn copy_index_file(project_paths: &ProjectPaths) -> Result<(), errs::CompilationError> {
-    fs::copy(
-        project_paths.meta_dir.join("index.html"),
-        project_paths.build_dir.join("index.html"),
-    )
-    .map(|_| ())
-    .or(Err(errs::CompilationError::Project(
-        errs::ProjectCompilationError::NoMetaIndex,
-    )))
+fn read_index_file(
+    project_paths: &ProjectPaths,
+    fs: &dyn ProjectFs,
+) -> Result<String, errs::CompilationError> {
+    fs.read_to_string(&project_paths.meta_dir.join("index.html"))
+        .or(Err(errs::CompilationError::Project(
+            errs::ProjectCompilationError::NoMetaIndex,
+        )))
 }
 
-fn copy_static_files(project_paths: &ProjectPaths) {
+fn copy_static_files(
+    project_paths: &ProjectPaths,
+    project_index: &ProjectIndex,
+    fs: &dyn ProjectFs,
+) -> Result<(), errs::CompilationError> {
     // Clean existing directory
-    if project_paths.build_static_dir.exists() {
-        (if project_paths.build_static_dir.is_dir() {
-            fs::remove_dir_all(&project_paths.build_static_dir)
-        } else {
-            fs::remove_file(&project_paths.build_static_dir)
-        })
-        .expect("Failed to delete old static directory");
-    }
-    // Copy files if exists
-    if project_paths.build_dir.exists() {
-        let mut options = fs_extra::dir::CopyOptions::new();
-        // options.mirror_copy = true;
-        options.copy_inside = true;
-        fs_extra::dir::copy(
-            &project_paths.static_dir,
-            &project_paths.build_static_dir,
-            &options,
-        )
-        .expect("Failed copying static directory");
-    }
-}
-
-fn build_framework_runtime() -> String {
-    // Yes I know it's not efficient to build it each time, but it would be very painful to do this all through macros.
-    // Admittedly pretty shoddy, especially the dependency order part
-
-    // Read files from binary, turn them into a map of name to content.
-    let file_map: HashMap<String, String> = FRAMEWORK_RUNTIME_FILES
-        .find("**/*.js")
-        .unwrap()
-        .filter_map(|entry| match entry {
-            DirEntry::File(f) => Some((
-                f.path().to_string_lossy().to_string(),
-                f.contents_utf8().unwrap().to_string(),
-            )),
-            _ => None,
-        })
-        .collect();
-
-    // Part 1 of determining the order in which the files should be appended to make sure dependencies are fulfilled
-    let mut file_order: Vec<String> = vec![];
-    for tuple in &file_map {
-        let deps = parse_framework_file_dependencies(&tuple.1);
-        add_dependencies_for_file(&deps, &file_map, &mut file_order);
-        file_order.push(tuple.0.to_string());
+    let build_static_dir = &project_paths.build_static_dir;
+    if fs.exists(build_static_dir) {
+        fs.remove(build_static_dir).map_err(errs::io_error(
+            "deleting old static directory",
+            build_static_dir,
+        ))?;
     }
-
-    // Part 2 of determining them - remove duplicates.
-    // Then processing them to remove the require, and concatenating.
-    file_order
-        .iter()
-        .unique()
-        .map(|x| remove_require_statement(file_map.get(x).unwrap()))
-        .collect::<Vec<String>>()
-        .join("\n")
-}
-
-fn parse_framework_file_dependencies(file_content: &str) -> Vec<String> {
-    // Returns a list of files it depends on and returns a copy of the file with require statement removed
-    if file_content.starts_with("requires(") {
-        let requirements: Vec<String> = file_content
-            .lines()
-            .next()
-            .unwrap()
-            .replacen("requires(", "", 1)
-            .replace(");", "")
-            .split(",")
-            .map(|x| x.trim().to_string())
-            .collect();
-        requirements
-    } else {
-        vec![]
-    }
-}
-
-fn add_dependencies_for_file(
-    file_dependencies: &Vec<String>,
-    file_map: &HashMap<String, String>,
-    dependency_accumulator: &mut Vec<String>,
-) {
-    // Recursive function to find the dependencies for a file and add them to the accumulator before then adding the file itself.
-
-    for file in file_dependencies {
-        if !file_map.contains_key(file) {
-            panic!("Could not find file {}", file);
+    // Copy the files one by one so that ignored ones are left out
+    for path in &project_index.static_files {
+        let relative_path = path.strip_prefix(&project_paths.static_dir).unwrap_or(path);
+        let target = build_static_dir.join(relative_path);
+        if let Some(parent) = target.parent() {
+            fs.create_dir_all(parent)
+                .map_err(errs::io_error("creating", parent))?;
         }
-        add_dependencies_for_file(
-            &parse_framework_file_dependencies(file_map.get(file).unwrap()),
-            file_map,
-            dependency_accumulator,
-        );
-        dependency_accumulator.push(file.to_string());
-    }
-}
-
-fn remove_require_statement(text: &str) -> String {
-    // Remove the requires() statement from the first line of a framework file if it has one
-    if text.starts_with("requires(") {
-        text.lines().skip(1).collect::<Vec<&str>>().join("\n")
-    } else {
-        text.to_string()
+        let content = fs.read(path).map_err(errs::io_error("reading", path))?;
+        fs.write(&target, &content)
+            .map_err(errs::io_error("copying", path))?;
     }
+    Ok(())
 }
 
-fn write_framework_runtime(project_paths: &ProjectPaths, framework_runtime: &str) {
-    // Write compiled framework runtime to where it belongs in the build dir
-
-    fs::write(
-        project_paths.build_dir.join("scripts/framework.js"),
-        framework_runtime,
-    )
-    .expect("Error copying framework scripts");
-}
 fn compile_elements(
-    element_directory: &Path,
+    element_files: &[PathBuf],
+    css_partials: &CssPartials,
     compilation_settings: &CompilationSettings,
     element_types: element_compiler::ElementType,
     last_element_id: &mut i32,
-) -> Result<Vec<element_compiler::CompiledElement>, errs::CompilationError> {
-    // Compile all the elements in the folder as element_types elements.
+    file_errors: &mut Vec<errs::CompilationError>,
+    fs: &dyn ProjectFs,
+) -> Result<Vec<CompiledElementFile>, errs::CompilationError> {
+    // Compile all the element files as element_types elements.
     // last_element_id is an out parameter, perhaps this is bad
-    // but it makes the calling function's code a lot simpler
+    // but it makes the calling function's code a lot simpler.
+    // Errors in the elements themselves are put into file_errors, errors reading the files are returned.
 
-    let element_files = fs::read_dir(element_directory).unwrap();
     let mut compiled_elements = vec![];
-
-    for entry in element_files {
+    for path in element_files {
         *last_element_id += 1;
-        let p = &entry.unwrap().path();
-        let file_name = p.as_path();
-        compiled_elements.push(
-            element_compiler::compile_element_file(
-                file_name,
-                compilation_settings,
-                element_types.clone(),
-                *last_element_id,
-            )
-            // Convert FileCompilationErrors to CompilationErrors
-            .or_else(|e| {
-                Err(errs::CompilationError::File {
-                    file_name: file_name.to_string_lossy().to_string(),
-                    inner_error: e,
-                })
-            })?,
+        let file_content = read_file(path, fs)?;
+        let element_name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let result = element_compiler::compile_element(
+            &file_content,
+            &element_name,
+            compilation_settings,
+            element_types.clone(),
+            *last_element_id,
+            css_partials,
         );
+        match result {
+            Ok(element) => compiled_elements.push(CompiledElementFile {
+                source_file: path.clone(),
+                element,
+            }),
+            // Convert FileCompilationErrors to CompilationErrors
+            Err(e) => file_errors.push(errs::CompilationError::File {
+                file_name: path.to_string_lossy().to_string(),
+                inner_error: e,
+            }),
+        }
     }
     Ok(compiled_elements)
 }
 
-fn compile_common_files(project_paths: &ProjectPaths) -> Vec<String> {
-    // Compile the common/service files - really just involves reading and concatenating them
+fn compile_common_files(
+    project_paths: &ProjectPaths,
+    project_index: &ProjectIndex,
+    compiled_elements: &[CompiledElementFile],
+    fs: &dyn ProjectFs,
+    warnings: &mut Vec<errs::CompilationWarning>,
+) -> Result<Vec<common_files::CommonFile>, errs::CompilationError> {
+    // Compile the common/service files - read them, put them in dependency order and strip out their imports.
+    // Files that no element uses are still included, but get a warning
+
+    let mut common_files = vec![];
+    for path in &project_index.common_files {
+        let file_content = read_file(path, fs)?;
+        let common_file =
+            common_files::read_common_file(path.clone(), &file_content).map_err(|e| {
+                errs::CompilationError::File {
+                    file_name: path.to_string_lossy().to_string(),
+                    inner_error: errs::FileCompilationError::CommonFileError(e),
+                }
+            })?;
+        common_files.push(common_file);
+    }
+    common_files::link_default_imports(&mut common_files)?;
+    let common_files = common_files::order_common_files(common_files).map_err(|e| {
+        errs::CompilationError::Project(errs::ProjectCompilationError::CommonFileDependencies(e))
+    })?;
 
-    if project_paths.common_dir.exists() {
-        let common_files = fs::read_dir(&project_paths.common_dir).unwrap();
-        common_files
-            .map(|entry| {
-                let p = &entry.unwrap().path();
-                fs::read_to_string(p).unwrap()
-            })
-            .collect()
-    } else {
-        vec![]
+    let element_contents: Vec<&str> = compiled_elements
+        .iter()
+        .map(|e| e.element.content.as_str())
+        .collect();
+    for unused_file in common_files::find_unused_common_files(&common_files, &element_contents) {
+        warnings.push(errs::CompilationWarning::UnusedCommonFile {
+            path: unused_file
+                .source_file
+                .strip_prefix(&project_paths.root_dir)
+                .unwrap_or(&unused_file.source_file)
+                .to_path_buf(),
+        });
     }
+
+    Ok(common_files)
 }
 
 fn check_root_element_exists(
-    compiled_elements: &Vec<element_compiler::CompiledElement>,
+    compiled_elements: &[CompiledElementFile],
 ) -> Result<(), errs::CompilationError> {
     // If the root element does not exist, gives an error
 
-    if compiled_elements.iter().any(|e| e.element_name == "Root") {
+    if compiled_elements
+        .iter()
+        .any(|e| e.element.element_name == "Root")
+    {
         Ok(())
     } else {
         Err(errs::CompilationError::Project(
@@ -339,63 +522,475 @@ fn check_root_element_exists(
     }
 }
 
+fn find_runtime_usage(
+    project_index: &ProjectIndex,
+    compiled_elements: &[CompiledElementFile],
+    compilation_settings: &CompilationSettings,
+) -> runtime::RuntimeUsage {
+    // Figure out which optional parts of the runtime are needed, so that the rest can be left out of framework.js
+
+    runtime::RuntimeUsage {
+        pages: !project_index.pages.is_empty(),
+        routed_app: compiled_elements.iter().any(|e| {
+            e.element
+                .referenced_elements
+                .iter()
+                .any(|name| name == ROUTED_APP_ELEMENT_NAME)
+        }),
+        debug_logging: !compilation_settings.release,
+        lazy_pages: compilation_settings.split_pages && !project_index.pages.is_empty(),
+    }
+}
+
+fn find_duplicate_page_routes(
+    compiled_elements: &[CompiledElementFile],
+) -> Vec<errs::CompilationWarning> {
+    // Warn about routes that are used by more than one page, since only one of them can ever be shown.
+    // Routes are compared with their parameter names and surrounding slashes stripped, as those don't affect matching.
+
+    let mut route_to_pages: Vec<(String, Vec<String>)> = vec![];
+    for compiled_element in compiled_elements {
+        for route in &compiled_element.element.page_routes {
+            let normalized_route = normalize_page_route(route);
+            let page_name = compiled_element.element.element_name.clone();
+            match route_to_pages
+                .iter_mut()
+                .find(|(r, _)| *r == normalized_route)
+            {
+                Some((_, page_names)) => page_names.push(page_name),
+                None => route_to_pages.push((normalized_route, vec![page_name])),
+            }
+        }
+    }
+
+    route_to_pages
+        .into_iter()
+        .filter(|(_, page_names)| page_names.len() > 1)
+        .map(
+            |(route, page_names)| errs::CompilationWarning::DuplicatePageRoute {
+                route: format!("/{route}"),
+                page_names,
+            },
+        )
+        .collect()
+}
+
+fn normalize_page_route(route: &str) -> String {
+    route
+        .split('/')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| if s.starts_with("${") { "${}" } else { s.trim() })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+fn minify_javascript(javascript: String, compilation_settings: &CompilationSettings) -> String {
+    if compilation_settings.minify_files {
+        minifier::js::minify(&javascript).to_string()
+    } else {
+        javascript
+    }
+}
+
 fn bundle_compiled_javascript_files(compiled_files: &Vec<String>) -> String {
     // Bundle all the compiled
 
     compiled_files.join(";\n") // minifier gets a bit too excited if we don't have semicolons after some lines, so add extra ones.
 }
 
-fn save_javascript_bundle(project_paths: &ProjectPaths, bundle: &str) {
-    // Save the javascript bundle to build dir
+fn compile_scoped_css_files(
+    project_index: &ProjectIndex,
+    css_partials: &CssPartials,
+    compilation_settings: &CompilationSettings,
+    file_errors: &mut Vec<errs::CompilationError>,
+    fs: &dyn ProjectFs,
+) -> Result<Vec<CompiledScopedCssFile>, errs::CompilationError> {
+    // Read and compile all the scoped CSS, determining element name to target based on the file name.
+    // A file whose element doesn't exist is an error, since its styles would never apply to anything
+
+    let element_names: Vec<String> = project_index
+        .elements
+        .iter()
+        .chain(&project_index.pages)
+        .map(|path| file_stem(path))
+        .collect();
+    let mut compiled_files = vec![];
+    for path in &project_index.scoped_css_files {
+        let file_content = read_file(path, fs)?;
+        let scope_name = file_stem(path);
+        let result = if element_names.contains(&scope_name) {
+            scoped_css::compiler::compile_scoped_css(
+                &file_content,
+                &scope_name,
+                css_partials,
+                compilation_settings,
+            )
+        } else {
+            Err(errs::FileCompilationError::ScopedCssError(
+                errs::ScopedCssError::NoMatchingSpall {
+                    element_name: scope_name.clone(),
+                },
+            ))
+        };
+        match result {
+            Ok(content) => compiled_files.push(CompiledScopedCssFile {
+                source_file: path.clone(),
+                source_content: file_content,
+                scope_name,
+                content,
+            }),
+            Err(e) => file_errors.push(errs::CompilationError::File {
+                file_name: path.to_string_lossy().to_string(),
+                inner_error: e,
+            }),
+        }
+    }
+    Ok(compiled_files)
+}
 
-    let target_file = project_paths.build_scripts_dir.join("bundle.js");
-    fs::write(target_file, bundle).expect("Failed saving bundle");
+fn find_unmatched_selectors(
+    project_paths: &ProjectPaths,
+    scoped_css_files: &[CompiledScopedCssFile],
+    css_partials: &CssPartials,
+    compiled_elements: &[CompiledElementFile],
+) -> Vec<errs::CompilationWarning> {
+    // Warn about selectors in scoped CSS files that can't match anything in their element's markup.
+    // Elements that failed to compile have no markup to check against, but they're reported as errors anyway
+
+    let mut warnings = vec![];
+    for file in scoped_css_files {
+        let Some(compiled_element) = compiled_elements
+            .iter()
+            .find(|f| f.element.element_name == file.scope_name)
+        else {
+            continue;
+        };
+        let path = file
+            .source_file
+            .strip_prefix(&project_paths.root_dir)
+            .unwrap_or(&file.source_file);
+        warnings.extend(
+            scoped_css::compiler::find_unmatched_selectors(
+                &file.source_content,
+                css_partials,
+                &compiled_element.element.markup_names,
+            )
+            .into_iter()
+            .map(|selector| errs::CompilationWarning::UnmatchedSelector {
+                path: path.to_path_buf(),
+                selector,
+                element_name: file.scope_name.clone(),
+            }),
+        );
+    }
+    warnings
 }
 
-fn compile_scoped_css_files(
+fn read_css_partials(
+    project_index: &ProjectIndex,
+    fs: &dyn ProjectFs,
+) -> Result<CssPartials, errs::CompilationError> {
+    // Read the partials in styles/_partials, which are used by their file name without the .css (eg @use "theme";)
+
+    let mut css_partials = CssPartials::new();
+    for path in &project_index.css_partials {
+        css_partials.insert(file_stem(path), read_file(path, fs)?);
+    }
+    Ok(css_partials)
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string()
+}
+
+fn compile_global_stylesheet(
     project_paths: &ProjectPaths,
+    css_partials: &CssPartials,
     compilation_settings: &CompilationSettings,
-) -> Result<Vec<String>, errs::CompilationError> {
-    // Read and compile all the scoped CSS
-
-    if project_paths.scoped_css_dir.exists() {
-        let css_files = fs::read_dir(&project_paths.scoped_css_dir).unwrap();
-        let mut compiled_files = vec![];
-        for file in css_files {
-            let p = &file.unwrap().path();
-            let compiled_file =
-                scoped_css::compiler::compile_scoped_css_file(p, compilation_settings).or_else(
-                    |e| {
-                        Err(errs::CompilationError::File {
-                            file_name: p.to_string_lossy().to_string(),
-                            inner_error: e,
-                        })
-                    },
-                )?;
-            compiled_files.push(compiled_file);
+    file_errors: &mut Vec<errs::CompilationError>,
+    warnings: &mut Vec<errs::CompilationWarning>,
+    fs: &dyn ProjectFs,
+) -> Result<Option<CompiledGlobalCss>, errs::CompilationError> {
+    // Bundle the global stylesheet along with the stylesheets it imports.
+    // Projects don't need one, unless the settings ask for a particular file
+
+    let entry_file = match &compilation_settings.global_stylesheet {
+        Some(path) => project_paths.root_dir.join(path),
+        None => project_paths
+            .global_css_dir
+            .join(global_css::GLOBAL_CSS_ENTRY_NAME),
+    };
+    if !fs.exists(&entry_file) {
+        return match compilation_settings.global_stylesheet {
+            Some(_) => Err(errs::CompilationError::Project(
+                errs::ProjectCompilationError::NoGlobalStylesheet { path: entry_file },
+            )),
+            None => Ok(None),
+        };
+    }
+
+    logging::log_brief("Compiling global CSS", compilation_settings.log_level);
+    let global_css = match global_css::build_global_css(
+        &entry_file,
+        project_paths,
+        css_partials,
+        compilation_settings.release,
+        fs,
+    ) {
+        Ok(global_css) => global_css,
+        Err(e @ errs::CompilationError::File { .. }) => {
+            file_errors.push(e);
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    warnings.extend(global_css.warnings);
+    match scoped_css::compiler::normalize_css(&global_css.plain_css, compilation_settings) {
+        Ok(content) => Ok(Some(CompiledGlobalCss {
+            source_files: global_css.source_files,
+            static_assets: global_css.static_assets,
+            content,
+        })),
+        Err(inner_error) => {
+            file_errors.push(errs::CompilationError::File {
+                file_name: entry_file.to_string_lossy().to_string(),
+                inner_error,
+            });
+            Ok(None)
+        }
+    }
+}
+
+fn compile_design_tokens(
+    project_paths: &ProjectPaths,
+    compilation_settings: &CompilationSettings,
+    file_errors: &mut Vec<errs::CompilationError>,
+    fs: &dyn ProjectFs,
+) -> Result<Option<CompiledDesignTokens>, errs::CompilationError> {
+    // Read the project's design tokens, if it has any, and write them out as custom properties
+
+    let tokens_file = &project_paths.tokens_file;
+    if !fs.exists(tokens_file) {
+        return Ok(None);
+    }
+    let result = design_tokens::parse_design_tokens(&read_file(tokens_file, fs)?)
+        .map_err(errs::FileCompilationError::DesignTokensError)
+        .and_then(|tokens| {
+            let css = scoped_css::compiler::normalize_css(&tokens.css(), compilation_settings)?;
+            Ok(CompiledDesignTokens { tokens, css })
+        });
+    match result {
+        Ok(design_tokens) => Ok(Some(design_tokens)),
+        Err(inner_error) => {
+            file_errors.push(errs::CompilationError::File {
+                file_name: tokens_file.to_string_lossy().to_string(),
+                inner_error,
+            });
+            Ok(None)
         }
-        Ok(compiled_files)
-    } else {
-        logging::log_brief(
-            "Scoped css directory is not present.",
-            compilation_settings.log_level,
-        );
-        Ok(vec![])
     }
 }
 
-fn bundle_scoped_css_files(scoped_css_files: &Vec<String>) -> String {
-    // Description is in the function name
+fn find_unknown_css_variables(
+    project_paths: &ProjectPaths,
+    design_tokens: &design_tokens::DesignTokens,
+    global_css: Option<&CompiledGlobalCss>,
+    scoped_css_files: &[CompiledScopedCssFile],
+    compiled_elements: &[CompiledElementFile],
+) -> Vec<errs::CompilationWarning> {
+    // Warn about var()s in scoped CSS that aren't a design token or set anywhere in the project's CSS.
+    // Custom properties are inherited, so one set by any element's styles can be used by every other element.
+    // var()s with a fallback value are left alone, as they are expected to be missing sometimes
+
+    let scoped_css: Vec<(&Path, &str)> = scoped_css_files
+        .iter()
+        .map(|f| (f.source_file.as_path(), f.content.as_str()))
+        .chain(compiled_elements.iter().flat_map(|f| {
+            f.element
+                .styles
+                .iter()
+                .map(|s| (f.source_file.as_path(), s.as_str()))
+        }))
+        .collect();
+
+    let mut known_names: Vec<String> = design_tokens
+        .properties
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect();
+    for css in global_css
+        .map(|g| g.content.as_str())
+        .into_iter()
+        .chain(scoped_css.iter().map(|(_, css)| *css))
+    {
+        known_names.extend(scoped_css::compiler::find_custom_properties(css).0);
+    }
 
-    scoped_css_files.join("\n\n")
+    let mut warnings = vec![];
+    let mut warned: Vec<(&Path, String)> = vec![];
+    for (path, css) in scoped_css {
+        for name in scoped_css::compiler::find_custom_properties(css).1 {
+            if known_names.contains(&name) || warned.contains(&(path, name.clone())) {
+                continue;
+            }
+            warnings.push(errs::CompilationWarning::UnknownCssVariable {
+                path: path
+                    .strip_prefix(&project_paths.root_dir)
+                    .unwrap_or(path)
+                    .to_path_buf(),
+                name: name.clone(),
+            });
+            warned.push((path, name));
+        }
+    }
+    warnings
 }
 
-fn save_scoped_css_bundle(project_paths: &ProjectPaths, bundled_scoped_css: String) {
-    // Save bundled and compiled scoped CSS to build dir
+fn save_static_assets(
+    project_paths: &ProjectPaths,
+    global_css: Option<&CompiledGlobalCss>,
+    fs: &dyn ProjectFs,
+) -> Result<(), errs::CompilationError> {
+    // Save the copies of static files with hashed names that the global CSS points at
+
+    for asset in global_css.iter().flat_map(|g| &g.static_assets) {
+        let content = fs
+            .read(&asset.source_file)
+            .map_err(errs::io_error("reading", &asset.source_file))?;
+        let target = project_paths.build_dir.join(&asset.name);
+        fs.write(&target, &content)
+            .map_err(errs::io_error("copying", &asset.source_file))?;
+    }
+    Ok(())
+}
 
-    fs::write(
-        project_paths.build_static_dir.join("bundle.css"),
-        bundled_scoped_css,
-    )
-    .expect("Failed writing scoped css bundle");
+fn bundle_css_files(
+    design_tokens: Option<&CompiledDesignTokens>,
+    global_css: Option<&CompiledGlobalCss>,
+    scoped_css_files: &[CompiledScopedCssFile],
+    compiled_elements: &[CompiledElementFile],
+    minify: bool,
+) -> String {
+    // Bundle the design tokens, the global CSS, then the scoped CSS files, then the <style> blocks of the elements.
+    // Global CSS goes first so that element styles win over it when they're equally specific.
+    // Minified files are already as small as they get, so they're just joined without any space between them
+
+    let scoped_css = scoped_css_files
+        .iter()
+        .map(|f| f.content.as_str())
+        .chain(
+            compiled_elements
+                .iter()
+                .flat_map(|f| f.element.styles.iter().map(|s| s.as_str())),
+        )
+        .map(scoped_css::compiler::point_urls_at_static_dir);
+    let parts: Vec<String> = design_tokens
+        .map(|t| t.css.clone())
+        .into_iter()
+        .chain(global_css.map(|g| g.content.clone()))
+        .chain(scoped_css)
+        .map(|css| css.trim_end().to_string())
+        .filter(|css| !css.is_empty())
+        .collect();
+    match (minify, parts.is_empty()) {
+        (true, _) | (false, true) => parts.join(""),
+        (false, false) => parts.join("\n\n") + "\n",
+    }
+}
+
+fn save_output_asset(
+    project_paths: &ProjectPaths,
+    asset_name: &str,
+    content: &str,
+    fs: &dyn ProjectFs,
+) -> Result<(), errs::CompilationError> {
+    // Save a compiled file (eg the bundle or the framework runtime) to the build dir
+
+    let path = project_paths.build_dir.join(asset_name);
+    if let Some(parent) = path.parent() {
+        fs.create_dir_all(parent)
+            .map_err(errs::io_error("creating", parent))?;
+    }
+    fs.write(&path, content.as_bytes())
+        .map_err(errs::io_error("writing", &path))
+}
+
+fn build_report(
+    project_paths: &ProjectPaths,
+    compiled_elements: &[CompiledElementFile],
+    scoped_css_files: &[CompiledScopedCssFile],
+    asset_names: &OutputAssetNames,
+    global_css: Option<&CompiledGlobalCss>,
+    runtime_files: &[&str],
+    output: &OutputContents,
+) -> report::CompilationReport {
+    // Gather up information about everything that was produced
+
+    let relative_path = |path: &Path| {
+        path.strip_prefix(&project_paths.root_dir)
+            .unwrap_or(path)
+            .to_path_buf()
+    };
+    let element_reports = |element_type: element_compiler::ElementType| {
+        compiled_elements
+            .iter()
+            .filter(|f| f.element.element_type == element_type)
+            .map(|f| report::ElementReport {
+                name: f.element.element_name.clone(),
+                compiled_name: f.element.compiled_element_name.clone(),
+                source_file: relative_path(&f.source_file),
+                routes: f.element.page_routes.clone(),
+                output_size: f.element.content.len(),
+            })
+            .collect()
+    };
+    let asset_report = |path: &str, content: &str| report::AssetReport {
+        path: path.to_string(),
+        size: content.len(),
+    };
+
+    report::CompilationReport {
+        runtime_version: RUNTIME_VERSION.to_string(),
+        runtime_files: runtime_files.iter().map(|f| f.to_string()).collect(),
+        elements: element_reports(element_compiler::ElementType::Basic),
+        pages: element_reports(element_compiler::ElementType::Page),
+        scoped_css_files: scoped_css_files
+            .iter()
+            .map(|f| report::ScopedCssReport {
+                scope_name: f.scope_name.clone(),
+                source_file: relative_path(&f.source_file),
+                output_size: f.content.len(),
+            })
+            .collect(),
+        assets: report::OutputAssetsReport {
+            framework_script: asset_report(&asset_names.framework_script, output.runtime),
+            bundle_script: asset_report(&asset_names.bundle_script, output.bundle),
+            scoped_css_bundle: asset_report(
+                &asset_names.scoped_css_bundle,
+                output.scoped_css_bundle,
+            ),
+            modules: output
+                .module_files
+                .iter()
+                .map(|m| asset_report(&m.name, &m.content))
+                .collect(),
+            chunks: output
+                .chunk_files
+                .iter()
+                .map(|c| asset_report(&c.name, &c.content))
+                .collect(),
+        },
+        global_css_files: global_css
+            .map(|g| {
+                g.source_files
+                    .iter()
+                    .map(|path| relative_path(path))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        warnings: vec![],
+    }
 }
@@ -0,0 +1,283 @@
+// Filesystem abstraction used by the project compiler, so that projects can be compiled from/to memory as well as disk.
+// Paths given to a ProjectFs are always the full paths that the compiler builds from the project dir.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Where the compiler reads projects from and writes builds to.
+///
+/// [`DiskFs`] is the real filesystem, [`MemoryFs`] keeps everything in memory.
+pub trait ProjectFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()>;
+    /// Paths of the entries in a directory, sorted so that output doesn't depend on filesystem order
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+    fn exists(&self, path: &Path) -> bool;
+    fn is_dir(&self, path: &Path) -> bool;
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    /// Remove a file, or a directory and everything in it
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    /// Turn a (possibly relative) project path into the absolute path used for the rest of compilation
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Recursively copy the contents of a directory into another directory, creating it if needed
+    fn copy_dir(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.create_dir_all(to)?;
+        for entry in self.read_dir(from)? {
+            let target = to.join(entry.file_name().unwrap_or_default());
+            if self.is_dir(&entry) {
+                self.copy_dir(&entry, &target)?;
+            } else {
+                self.write(&target, &self.read(&entry)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The real filesystem
+pub struct DiskFs;
+
+impl ProjectFs for DiskFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        fs::write(path, content)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for entry in fs::read_dir(path)? {
+            paths.push(entry?.path());
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        if path.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        path.canonicalize()
+    }
+}
+
+enum MemoryEntry {
+    File(Vec<u8>),
+    Dir,
+}
+
+/// A filesystem that only exists in memory, eg for tests or for compiling unsaved editor buffers.
+///
+/// Paths are used exactly as given, so use absolute paths (eg `/project/elements/Root.spall`).
+#[derive(Default)]
+pub struct MemoryFs {
+    entries: Mutex<BTreeMap<PathBuf, MemoryEntry>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> MemoryFs {
+        MemoryFs::default()
+    }
+
+    /// Add a file, creating its parent directories if they don't exist
+    pub fn add_file(&self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)
+                .expect("Could not create parent dir");
+        }
+        self.write(path, &content.into())
+            .expect("Could not add file");
+    }
+
+    /// Make a filesystem holding `files`, whose paths are relative to `root`
+    pub fn with_files(root: impl AsRef<Path>, files: &[(&str, &str)]) -> MemoryFs {
+        let fs = MemoryFs::new();
+        for (path, content) in files {
+            fs.add_file(root.as_ref().join(path), *content);
+        }
+        fs
+    }
+
+    /// Paths of all the files (not directories) that are stored
+    pub fn file_paths(&self) -> Vec<PathBuf> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| matches!(entry, MemoryEntry::File(_)))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} does not exist", path.to_string_lossy()),
+    )
+}
+
+impl ProjectFs for MemoryFs {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.entries.lock().unwrap().get(path) {
+            Some(MemoryEntry::File(content)) => Ok(content.clone()),
+            Some(MemoryEntry::Dir) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot read a directory",
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        // Like on a real filesystem, the parent directory has to exist first
+        let parent = path.parent().unwrap_or(Path::new(""));
+        if !parent.as_os_str().is_empty() && !self.is_dir(parent) {
+            return Err(not_found(parent));
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(MemoryEntry::Dir) = entries.get(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Cannot write to a directory",
+            ));
+        }
+        entries.insert(path.to_path_buf(), MemoryEntry::File(content.to_vec()));
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        if !self.is_dir(path) {
+            return Err(not_found(path));
+        }
+        // BTreeMap is already sorted
+        Ok(self
+            .entries
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|p| p.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.entries.lock().unwrap().contains_key(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(
+            self.entries.lock().unwrap().get(path),
+            Some(MemoryEntry::Dir)
+        )
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        for ancestor in path.ancestors().filter(|p| !p.as_os_str().is_empty()) {
+            match entries.get(ancestor) {
+                Some(MemoryEntry::File(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{} is a file", ancestor.to_string_lossy()),
+                    ))
+                }
+                Some(MemoryEntry::Dir) => (),
+                None => {
+                    entries.insert(ancestor.to_path_buf(), MemoryEntry::Dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(path) {
+            return Err(not_found(path));
+        }
+        entries.retain(|p, _| !p.starts_with(path));
+        Ok(())
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        if self.exists(path) {
+            Ok(path.to_path_buf())
+        } else {
+            Err(not_found(path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_fs() {
+        let fs = MemoryFs::with_files(
+            "/project",
+            &[
+                ("elements/Root.spall", "<p>Hi</p>"),
+                ("elements/A.spall", ""),
+            ],
+        );
+
+        assert!(fs.is_dir(Path::new("/project/elements")));
+        assert!(!fs.is_dir(Path::new("/project/elements/A.spall")));
+        assert_eq!(
+            fs.read_to_string(Path::new("/project/elements/Root.spall"))
+                .unwrap(),
+            "<p>Hi</p>"
+        );
+        assert_eq!(
+            fs.read_dir(Path::new("/project/elements")).unwrap(),
+            vec![
+                PathBuf::from("/project/elements/A.spall"),
+                PathBuf::from("/project/elements/Root.spall")
+            ]
+        );
+        assert!(fs
+            .write(Path::new("/project/build/index.html"), b"")
+            .is_err());
+
+        fs.copy_dir(
+            Path::new("/project/elements"),
+            Path::new("/project/build/copy"),
+        )
+        .unwrap();
+        assert!(fs.exists(Path::new("/project/build/copy/Root.spall")));
+
+        fs.remove(Path::new("/project/build")).unwrap();
+        assert_eq!(fs.file_paths().len(), 2);
+    }
+}
@@ -0,0 +1,256 @@
+// Finds the files in a project that should be compiled.
+// Each project dir only takes files with a certain extension - anything else gets a warning.
+// Files matching a pattern in the project's .spallignore (gitignore syntax) are skipped silently.
+
+use std::path::{Path, PathBuf};
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use crate::errs::{self, CompilationError, CompilationWarning, ProjectCompilationError};
+use crate::project_fs::ProjectFs;
+use crate::project_paths::ProjectPaths;
+
+pub const IGNORE_FILE_NAME: &str = ".spallignore";
+
+// Full paths of all the project files that go into a build, sorted within each category
+pub struct ProjectIndex {
+    pub elements: Vec<PathBuf>,
+    pub pages: Vec<PathBuf>,
+    pub common_files: Vec<PathBuf>,
+    pub scoped_css_files: Vec<PathBuf>,
+    pub css_partials: Vec<PathBuf>,
+    pub static_files: Vec<PathBuf>,
+}
+
+pub fn index_project(
+    project_paths: &ProjectPaths,
+    fs: &dyn ProjectFs,
+    warnings: &mut Vec<CompilationWarning>,
+) -> Result<ProjectIndex, CompilationError> {
+    let ignore_rules = read_ignore_file(project_paths, fs)?;
+    let mut find_files = |dir: &Path, extension: &'static str| {
+        find_files_with_extension(dir, extension, project_paths, &ignore_rules, fs, warnings)
+    };
+
+    Ok(ProjectIndex {
+        elements: find_files(&project_paths.elements_dir, "spall")?,
+        pages: find_files(&project_paths.pages_dir, "spall")?,
+        common_files: find_files(&project_paths.common_dir, "js")?,
+        scoped_css_files: find_files(&project_paths.scoped_css_dir, "css")?,
+        css_partials: find_files(&project_paths.css_partials_dir, "css")?,
+        static_files: find_static_files(&project_paths.static_dir, &ignore_rules, fs)?,
+    })
+}
+
+fn read_ignore_file(
+    project_paths: &ProjectPaths,
+    fs: &dyn ProjectFs,
+) -> Result<Gitignore, CompilationError> {
+    // Parse .spallignore, or give an empty set of rules if there isn't one.
+    // Patterns are relative to the project dir, like a .gitignore at the root of a repo
+
+    let mut builder = GitignoreBuilder::new(&project_paths.root_dir);
+    let ignore_file_path = project_paths.root_dir.join(IGNORE_FILE_NAME);
+    if fs.exists(&ignore_file_path) {
+        let content = fs
+            .read_to_string(&ignore_file_path)
+            .map_err(errs::io_error("reading", &ignore_file_path))?;
+        for (index, line) in content.lines().enumerate() {
+            builder.add_line(None, line).map_err(|e| {
+                CompilationError::Project(ProjectCompilationError::InvalidIgnoreFile {
+                    line_number: index + 1,
+                    line: line.to_string(),
+                    reason: e.to_string(),
+                })
+            })?;
+        }
+    }
+    builder.build().map_err(|e| {
+        CompilationError::Project(ProjectCompilationError::InvalidIgnoreFile {
+            line_number: 0,
+            line: String::new(),
+            reason: e.to_string(),
+        })
+    })
+}
+
+fn is_ignored(path: &Path, is_dir: bool, ignore_rules: &Gitignore) -> bool {
+    ignore_rules
+        .matched_path_or_any_parents(path, is_dir)
+        .is_ignore()
+}
+
+fn find_files_with_extension(
+    dir: &Path,
+    extension: &'static str,
+    project_paths: &ProjectPaths,
+    ignore_rules: &Gitignore,
+    fs: &dyn ProjectFs,
+    warnings: &mut Vec<CompilationWarning>,
+) -> Result<Vec<PathBuf>, CompilationError> {
+    // Find the files directly in dir that have the extension.
+    // Subdirectories aren't compiled, so they get a warning like any other unexpected file
+    // (apart from styles/_partials, which is indexed by itself, and styles/global, whose files are found through @import)
+
+    if !fs.exists(dir) {
+        return Ok(vec![]);
+    }
+
+    let mut files = vec![];
+    for path in fs
+        .read_dir(dir)
+        .map_err(errs::io_error("reading directory", dir))?
+    {
+        let is_dir = fs.is_dir(&path);
+        if is_ignored(&path, is_dir, ignore_rules)
+            || path == project_paths.css_partials_dir
+            || path == project_paths.global_css_dir
+        {
+            continue;
+        }
+        if !is_dir && path.extension().is_some_and(|e| e == extension) {
+            files.push(path);
+        } else {
+            warnings.push(CompilationWarning::UnexpectedFile {
+                path: path
+                    .strip_prefix(&project_paths.root_dir)
+                    .unwrap_or(&path)
+                    .to_path_buf(),
+                expected_extension: extension,
+            });
+        }
+    }
+    Ok(files)
+}
+
+fn find_static_files(
+    dir: &Path,
+    ignore_rules: &Gitignore,
+    fs: &dyn ProjectFs,
+) -> Result<Vec<PathBuf>, CompilationError> {
+    // Recursively find every file in the static dir, as anything can be served from there
+
+    if !fs.exists(dir) {
+        return Ok(vec![]);
+    }
+
+    let mut files = vec![];
+    for path in fs
+        .read_dir(dir)
+        .map_err(errs::io_error("reading directory", dir))?
+    {
+        let is_dir = fs.is_dir(&path);
+        if is_ignored(&path, is_dir, ignore_rules) {
+            continue;
+        }
+        if is_dir {
+            files.extend(find_static_files(&path, ignore_rules, fs)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_fs::MemoryFs;
+
+    fn index_files(files: &[&str]) -> (ProjectIndex, Vec<CompilationWarning>) {
+        let fs = MemoryFs::new();
+        for file in files {
+            fs.add_file(Path::new("/project").join(file), "");
+        }
+        let mut warnings = vec![];
+        let index = index_project(
+            &ProjectPaths::new(Path::new("/project")),
+            &fs,
+            &mut warnings,
+        )
+        .unwrap();
+        (index, warnings)
+    }
+
+    #[test]
+    fn test_filters_by_extension() {
+        let (index, warnings) = index_files(&[
+            "elements/Root.spall",
+            "elements/notes.txt",
+            "elements/old/Thing.spall",
+            "common/api.js",
+            "styles/Root.css",
+            "styles/Root.css.bak",
+            "styles/_partials/theme.css",
+            "styles/global/index.css",
+        ]);
+        assert_eq!(
+            index.elements,
+            vec![PathBuf::from("/project/elements/Root.spall")]
+        );
+        assert_eq!(index.common_files.len(), 1);
+        assert_eq!(index.scoped_css_files.len(), 1);
+        assert_eq!(
+            index.css_partials,
+            vec![PathBuf::from("/project/styles/_partials/theme.css")]
+        );
+        assert_eq!(
+            warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>(),
+            vec![
+                "Skipping elements/notes.txt (expected only .spall files, add it to .spallignore to hide this warning)",
+                "Skipping elements/old (expected only .spall files, add it to .spallignore to hide this warning)",
+                "Skipping styles/Root.css.bak (expected only .css files, add it to .spallignore to hide this warning)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_spallignore() {
+        let fs = MemoryFs::new();
+        fs.add_file(
+            "/project/.spallignore",
+            "*.txt\n# comment\n/pages/Draft.spall\nstatic/drafts/",
+        );
+        for file in [
+            "elements/Root.spall",
+            "elements/notes.txt",
+            "pages/Draft.spall",
+            "pages/Index.spall",
+            "static/logo.png",
+            "static/readme.txt",
+            "static/drafts/a.png",
+            "static/images/b.png",
+        ] {
+            fs.add_file(Path::new("/project").join(file), "");
+        }
+        let mut warnings = vec![];
+        let index = index_project(
+            &ProjectPaths::new(Path::new("/project")),
+            &fs,
+            &mut warnings,
+        )
+        .unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(index.elements.len(), 1);
+        assert_eq!(
+            index.pages,
+            vec![PathBuf::from("/project/pages/Index.spall")]
+        );
+        assert_eq!(
+            index.static_files,
+            vec![
+                PathBuf::from("/project/static/images/b.png"),
+                PathBuf::from("/project/static/logo.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_dirs_are_empty() {
+        let (index, warnings) = index_files(&["elements/Root.spall"]);
+        assert!(index.pages.is_empty());
+        assert!(index.static_files.is_empty());
+        assert!(warnings.is_empty());
+    }
+}
@@ -0,0 +1,42 @@
+// Locations of the different parts of a project
+
+use std::path::{Path, PathBuf};
+
+#[allow(dead_code)]
+pub struct ProjectPaths {
+    pub root_dir: PathBuf,
+    pub build_dir: PathBuf,
+    pub build_scripts_dir: PathBuf,
+    pub build_css_dir: PathBuf,
+    pub meta_dir: PathBuf,
+    pub elements_dir: PathBuf,
+    pub pages_dir: PathBuf,
+    pub common_dir: PathBuf,
+    pub static_dir: PathBuf,
+    pub build_static_dir: PathBuf,
+    pub scoped_css_dir: PathBuf,
+    pub css_partials_dir: PathBuf,
+    pub global_css_dir: PathBuf,
+    pub tokens_file: PathBuf,
+}
+
+impl ProjectPaths {
+    pub fn new(project_dir: &Path) -> ProjectPaths {
+        ProjectPaths {
+            root_dir: project_dir.to_path_buf(),
+            build_dir: project_dir.join("build"),
+            build_scripts_dir: project_dir.join("build/scripts"),
+            build_css_dir: project_dir.join("build/css"),
+            meta_dir: project_dir.join("meta"),
+            elements_dir: project_dir.join("elements"),
+            pages_dir: project_dir.join("pages"),
+            common_dir: project_dir.join("common"),
+            static_dir: project_dir.join("static"),
+            build_static_dir: project_dir.join("build/static"),
+            scoped_css_dir: project_dir.join("styles"),
+            css_partials_dir: project_dir.join("styles/_partials"),
+            global_css_dir: project_dir.join("styles/global"),
+            tokens_file: project_dir.join("tokens.json"),
+        }
+    }
+}
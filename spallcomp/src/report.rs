@@ -0,0 +1,61 @@
+// Summary of what a compilation produced.
+// Returned to library callers and also saved into the build dir as spall-manifest.json so that CI/deploy scripts can use it.
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::errs::CompilationWarning;
+
+pub const MANIFEST_FILE_NAME: &str = "spall-manifest.json";
+
+#[derive(Serialize)]
+pub struct CompilationReport {
+    pub runtime_version: String,
+    pub runtime_files: Vec<String>, // the files from the runtime that the app uses and were put into the framework script
+    pub elements: Vec<ElementReport>,
+    pub pages: Vec<ElementReport>,
+    pub scoped_css_files: Vec<ScopedCssReport>,
+    pub global_css_files: Vec<PathBuf>, // relative to project dir, in the order they go into the CSS bundle
+    pub assets: OutputAssetsReport,
+    pub warnings: Vec<CompilationWarning>,
+}
+
+impl CompilationReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Failed serializing compilation report")
+    }
+}
+
+// A compiled element or page
+#[derive(Serialize)]
+pub struct ElementReport {
+    pub name: String,
+    pub compiled_name: String,
+    pub source_file: PathBuf, // relative to project dir
+    pub routes: Vec<String>,  // always empty for elements
+    pub output_size: usize,   // size in bytes of the compiled javascript, before minification
+}
+
+#[derive(Serialize)]
+pub struct ScopedCssReport {
+    pub scope_name: String,
+    pub source_file: PathBuf, // relative to project dir
+    pub output_size: usize,
+}
+
+// The files that index.html links to. Paths are relative to the build dir and include the hash in release builds
+#[derive(Serialize)]
+pub struct OutputAssetsReport {
+    pub framework_script: AssetReport,
+    pub bundle_script: AssetReport,
+    pub scoped_css_bundle: AssetReport,
+    pub modules: Vec<AssetReport>, // the element, page and common file modules, when outputting ES modules
+    pub chunks: Vec<AssetReport>,  // the page and shared chunks, when splitting pages
+}
+
+#[derive(Serialize)]
+pub struct AssetReport {
+    pub path: String,
+    pub size: usize,
+}
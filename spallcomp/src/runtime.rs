@@ -0,0 +1,174 @@
+// The Javascript runtime that gets shipped as framework.js.
+// The files in runtime/ are put in dependency order by build.rs, so this just has to pick the ones the app needs and join them.
+
+// A file from runtime/, with its requires() statement removed
+pub struct RuntimeFile {
+    pub name: &'static str,
+    pub requires: &'static [&'static str],
+    pub classes: &'static [&'static str], // top level classes, which are exported when building a module
+    pub content: &'static str,
+}
+
+include!(concat!(env!("OUT_DIR"), "/runtime_files.rs"));
+
+// Files that every app needs - everything is rendered from a SpallApp into the root element
+const CORE_FILES: [&str; 3] = ["SpallApp.js", "SpallElement.js", "SpallRootElement.js"];
+const PAGE_FILE: &str = "SpallPage.js";
+const ROUTED_APP_FILE: &str = "SpallRoutedApp.js";
+const DEBUG_RENDER_LOGGER_FILE: &str = "SpallDebugRenderLogger.js";
+const LAZY_PAGE_FILE: &str = "SpallLazyPage.js";
+
+// Which optional parts of the runtime an app uses
+pub struct RuntimeUsage {
+    pub pages: bool,
+    pub routed_app: bool,    // whether <RoutedApp> is used anywhere
+    pub debug_logging: bool, // whether SpallDebugRenderLogger is available to be passed to the renderer
+    pub lazy_pages: bool,    // whether pages are split into chunks that are loaded on first visit
+}
+
+/// Names of the runtime files needed for `usage`, in the order that they go into framework.js
+pub fn select_runtime_files(usage: &RuntimeUsage) -> Vec<&'static str> {
+    let mut needed: Vec<&str> = CORE_FILES.to_vec();
+    if usage.pages {
+        needed.push(PAGE_FILE);
+    }
+    if usage.routed_app {
+        needed.push(ROUTED_APP_FILE);
+    }
+    if usage.debug_logging {
+        needed.push(DEBUG_RENDER_LOGGER_FILE);
+    }
+    if usage.lazy_pages {
+        needed.push(LAZY_PAGE_FILE);
+    }
+
+    // Add everything that the needed files require.
+    // Requirements always come earlier in RUNTIME_FILES, so going backwards finds them all in one pass
+    for file in RUNTIME_FILES.iter().rev() {
+        if needed.contains(&file.name) {
+            for requirement in file.requires {
+                if !needed.contains(requirement) {
+                    needed.push(requirement);
+                }
+            }
+        }
+    }
+
+    RUNTIME_FILES
+        .iter()
+        .map(|f| f.name)
+        .filter(|name| needed.contains(name))
+        .collect()
+}
+
+pub fn build_framework_runtime(file_names: &[&str]) -> String {
+    RUNTIME_FILES
+        .iter()
+        .filter(|f| file_names.contains(&f.name))
+        .map(|f| f.content)
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// Names of the classes declared by the runtime files, which are what framework.js exports as a module
+pub fn runtime_classes(file_names: &[&str]) -> Vec<&'static str> {
+    RUNTIME_FILES
+        .iter()
+        .filter(|f| file_names.contains(&f.name))
+        .flat_map(|f| f.classes.iter().copied())
+        .collect()
+}
+
+pub fn build_framework_module(file_names: &[&str]) -> String {
+    format!(
+        "{}\nexport {{ {} }};\n",
+        build_framework_runtime(file_names),
+        runtime_classes(file_names).join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(pages: bool, routed_app: bool, debug_logging: bool) -> RuntimeUsage {
+        RuntimeUsage {
+            pages,
+            routed_app,
+            debug_logging,
+            lazy_pages: false,
+        }
+    }
+
+    #[test]
+    fn test_runtime_files_are_in_dependency_order() {
+        for (index, file) in RUNTIME_FILES.iter().enumerate() {
+            for requirement in file.requires {
+                let requirement_index = RUNTIME_FILES
+                    .iter()
+                    .position(|f| f.name == *requirement)
+                    .unwrap();
+                assert!(
+                    requirement_index < index,
+                    "{} comes before {requirement}, which it requires",
+                    file.name
+                );
+            }
+        }
+        let all_files = RUNTIME_FILES.iter().map(|f| f.name).collect::<Vec<_>>();
+        assert!(!build_framework_runtime(&all_files).contains("requires("));
+    }
+
+    #[test]
+    fn test_framework_module_exports_classes() {
+        let files = select_runtime_files(&usage(true, false, false));
+        let classes = runtime_classes(&files);
+        assert!(classes.contains(&"SpallApp"));
+        assert!(classes.contains(&"SpallStringRouteSection"));
+        assert!(build_framework_module(&files).contains("export { "));
+    }
+
+    #[test]
+    fn test_routing_is_left_out_without_pages() {
+        let files = select_runtime_files(&usage(false, false, true));
+        assert!(files.contains(&"SpallApp.js"));
+        assert!(files.contains(&"SpallRenderer.js"));
+        assert!(files.contains(&"SpallDebugRenderLogger.js"));
+        assert!(!files.contains(&"SpallRouter.js"));
+        assert!(!files.contains(&"SpallPage.js"));
+        assert!(!files.contains(&"SpallRoutedApp.js"));
+    }
+
+    #[test]
+    fn test_routing_is_included_when_used() {
+        let files = select_runtime_files(&usage(true, false, false));
+        assert!(files.contains(&"SpallPage.js"));
+        assert!(files.contains(&"SpallRouter.js"));
+        assert!(!files.contains(&"SpallRoutedApp.js"));
+
+        let files = select_runtime_files(&usage(false, true, false));
+        assert!(files.contains(&"SpallRoutedApp.js"));
+        assert!(files.contains(&"SpallRouter.js"));
+        assert!(!files.contains(&"SpallPage.js"));
+    }
+
+    #[test]
+    fn test_debug_logger_is_left_out_of_release_builds() {
+        let files = select_runtime_files(&usage(true, true, false));
+        assert!(!files.contains(&"SpallDebugRenderLogger.js"));
+        assert!(files.contains(&"SpallRenderLogger.js"));
+    }
+
+    #[test]
+    fn test_lazy_pages_are_only_included_when_splitting() {
+        let files = select_runtime_files(&usage(true, true, false));
+        assert!(!files.contains(&"SpallLazyPage.js"));
+
+        let files = select_runtime_files(&RuntimeUsage {
+            lazy_pages: true,
+            ..usage(true, true, false)
+        });
+        assert!(files.contains(&"SpallLazyPage.js"));
+        assert!(runtime_classes(&files).contains(&"SpallLazyPage"));
+    }
+}
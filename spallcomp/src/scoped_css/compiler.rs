@@ -1,62 +1,500 @@
-use std::fs;
 use std::path::Path;
 
 use crate::compilation_settings::*;
+use crate::element_compiler::MarkupNames;
 use crate::errs;
+use crate::global_css;
 use crate::logging;
+use crate::scoped_css::emitter;
+use crate::scoped_css::preprocessor;
+use crate::scoped_css::preprocessor::CssPartials;
+use crate::scoped_css::selector;
 use crate::scoped_css::tokeniser;
 use crate::scoped_css::tokeniser::CssToken;
 
-pub fn compile_scoped_css_file(
-    file_path: &Path,
-    compilation_settings: &CompilationSettings,
-) -> Result<String, errs::FileCompilationError> {
-    // Compile scoped css directly from a file, determining element name to target based on the file name
-
-    let file_content = fs::read_to_string(file_path).expect(&format!(
-        "Failed reading scoped css file: {}",
-        file_path.to_string_lossy()
-    ));
-    let element_name = file_path.file_stem().unwrap().to_str().unwrap();
-    compile_scoped_css(&file_content, &element_name, compilation_settings)
+// What the block that we're currently in contains, which decides what happens to selectors in it
+#[derive(Clone, Copy, PartialEq)]
+enum BlockContext {
+    Rules,     // top level, or inside an at-rule like @media. Selectors get scoped
+    Keyframes, // selectors are keyframe selectors like 50%, which are left alone
+    Properties,
 }
 
 pub fn compile_scoped_css(
     file_content: &str,
     element_name: &str,
+    partials: &CssPartials,
     compilation_settings: &CompilationSettings,
 ) -> Result<String, errs::FileCompilationError> {
     // Compile scoped css from a string
     // Requires explicit setting of the element name
+    // Nesting, $variables and @use of partials are compiled to plain css first (see preprocessor)
     // In addition to tweaking the styles, also has the effect of normalizing the style
-    // Produced css is not optimal (it contains much whitespace), it may be desirable to run it through a minifier
+    // Produced css is minified if compilation_settings.minify_files is set, otherwise it is indented for reading
 
     logging::log_brief(
         format!("Compiling scoped CSS for element {element_name}").as_str(),
         compilation_settings.log_level,
     );
 
-    // Tokenise
-    let tokens = tokeniser::tokenise_css(file_content)
+    // Preprocess, then tokenise
+    let plain_css = preprocessor::preprocess(file_content, partials)
+        .map_err(errs::FileCompilationError::CssSourceError)?;
+    let tokens = tokeniser::tokenise_css(&plain_css)
         .or_else(|e| Err(errs::FileCompilationError::CssSyntaxError(e)))?;
 
-    // Write tokens back to a string, making required modifications as we go
-    let mut result = "".to_string();
+    // Keyframes are global, so give them per-element names so that elements can't break each other's animations
+    let keyframe_names = find_keyframe_names(&tokens);
+
+    // Make the required modifications to the tokens, then write them back to a string
+    let mut scoped_tokens = Vec::with_capacity(tokens.len());
+    let mut block_contexts = vec![BlockContext::Rules];
+    let mut next_block_context = BlockContext::Properties;
+    let mut crnt_property_name = "".to_string();
     for token in tokens {
-        result += match token {
-            CssToken::BlockEnd => "}\n\n".to_string(),
-            CssToken::BlockStart => " {\n".to_string(),
-            CssToken::Colon => ": ".to_string(),
-            CssToken::Comma => ", ".to_string(),
-            CssToken::Comment(value) => format!("/* {value} */"),
-            CssToken::PropertyName(name) => format!("    {name}"),
-            CssToken::PropertyValue(value) => value,
-            CssToken::Semicolon => ";\n".to_string(),
+        let crnt_context = *block_contexts.last().unwrap_or(&BlockContext::Rules);
+        scoped_tokens.push(match token {
+            CssToken::AtRule { name, prelude } => {
+                next_block_context = if tokeniser::is_keyframes_at_rule(&name) {
+                    BlockContext::Keyframes
+                } else if crnt_context == BlockContext::Properties {
+                    BlockContext::Properties
+                } else {
+                    BlockContext::Rules
+                };
+                let prelude = if tokeniser::is_keyframes_at_rule(&name) {
+                    scoped_keyframe_name(&prelude, element_name)
+                } else {
+                    prelude
+                };
+                CssToken::AtRule { name, prelude }
+            }
+            CssToken::BlockEnd => {
+                block_contexts.pop();
+                CssToken::BlockEnd
+            }
+            CssToken::BlockStart => {
+                block_contexts.push(next_block_context);
+                next_block_context = BlockContext::Properties;
+                CssToken::BlockStart
+            }
+            CssToken::PropertyName(name) => {
+                crnt_property_name = name.clone();
+                CssToken::PropertyName(name)
+            }
+            CssToken::PropertyValue(value) => {
+                if crnt_property_name == "animation" || crnt_property_name == "animation-name" {
+                    CssToken::PropertyValue(rename_keyframes_in_value(
+                        &value,
+                        &keyframe_names,
+                        element_name,
+                    ))
+                } else {
+                    CssToken::PropertyValue(value)
+                }
+            }
 
             // This is the special one where we mess with the class names
-            CssToken::Selector(value) => format!("._sp{element_name} {value}"),
+            CssToken::Selector(value) if crnt_context != BlockContext::Keyframes => {
+                CssToken::Selector(
+                    selector::scope_selector(&value, &format!("._sp{element_name}"))
+                        .map_err(errs::FileCompilationError::CssSyntaxError)?,
+                )
+            }
+            other => other,
+        });
+    }
+    Ok(emitter::emit_css(
+        &scoped_tokens,
+        compilation_settings.minify_files,
+    ))
+}
+
+pub fn compile_global_css(
+    file_content: &str,
+    partials: &CssPartials,
+    compilation_settings: &CompilationSettings,
+) -> Result<String, errs::FileCompilationError> {
+    // Compile css that applies to the whole document, eg from a <style global> block.
+    // Nothing is scoped or renamed, it is only preprocessed and normalized (or minified) in the same way as scoped css
+
+    let plain_css = preprocessor::preprocess(file_content, partials)
+        .map_err(errs::FileCompilationError::CssSourceError)?;
+    normalize_css(&plain_css, compilation_settings)
+}
+
+pub fn point_urls_at_static_dir(css: &str) -> String {
+    // Relative url()s in scoped CSS were relative to static/, where the CSS bundle used to be written before it moved to
+    // css/. Make them absolute so that they keep pointing at the same files
+
+    global_css::rewrite_urls(css, &mut |url| {
+        if url.is_empty() || url.starts_with(['#', '/']) || global_css::is_external_url(url) {
+            return None;
+        }
+        let path = global_css::normalize_path(&Path::new("/static").join(url));
+        Some(path.to_string_lossy().replace('\\', "/"))
+    })
+}
+
+pub fn normalize_css(
+    plain_css: &str,
+    compilation_settings: &CompilationSettings,
+) -> Result<String, errs::FileCompilationError> {
+    // Write out css that has already been preprocessed in the same format as the rest of the css bundle
+
+    let tokens =
+        tokeniser::tokenise_css(plain_css).map_err(errs::FileCompilationError::CssSyntaxError)?;
+    Ok(emitter::emit_css(
+        &tokens,
+        compilation_settings.minify_files,
+    ))
+}
+
+/// Selectors in `file_content` that need a tag or class that isn't in the element's markup, so can never match anything.
+/// Files that don't compile are left to compile_scoped_css to report
+pub fn find_unmatched_selectors(
+    file_content: &str,
+    partials: &CssPartials,
+    markup_names: &MarkupNames,
+) -> Vec<String> {
+    let Ok(plain_css) = preprocessor::preprocess(file_content, partials) else {
+        return vec![];
+    };
+    let Ok(tokens) = tokeniser::tokenise_css(&plain_css) else {
+        return vec![];
+    };
+
+    let mut unmatched = vec![];
+    // Whether each open block is @keyframes, whose selectors (like 50%) aren't matched against markup
+    let mut in_keyframes = vec![false];
+    let mut next_block_is_keyframes = false;
+    for token in tokens {
+        match token {
+            CssToken::AtRule { name, .. } => {
+                next_block_is_keyframes = tokeniser::is_keyframes_at_rule(&name)
+            }
+            CssToken::BlockStart => {
+                in_keyframes.push(next_block_is_keyframes);
+                next_block_is_keyframes = false;
+            }
+            CssToken::BlockEnd => {
+                in_keyframes.pop();
+            }
+            CssToken::Selector(value) if in_keyframes.last() == Some(&false) => {
+                let Ok((tags, classes)) =
+                    selector::names_needed_in_markup(&value, markup_names.has_child_elements)
+                else {
+                    continue;
+                };
+                let missing_tag = tags.iter().any(|tag| !markup_names.tags.contains(tag));
+                let missing_class = !markup_names.has_dynamic_classes
+                    && classes
+                        .iter()
+                        .any(|class| !markup_names.classes.contains(class));
+                if missing_tag || missing_class {
+                    unmatched.push(value.trim().to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+    unmatched
+}
+
+/// Custom properties that `css` sets, and the ones that it uses with var() and no fallback value.
+/// Only used with css that has already compiled, so it can't fail
+pub fn find_custom_properties(css: &str) -> (Vec<String>, Vec<String>) {
+    let Ok(tokens) = tokeniser::tokenise_css(css) else {
+        return (vec![], vec![]);
+    };
+
+    let mut set = vec![];
+    let mut used = vec![];
+    for token in tokens {
+        match token {
+            CssToken::PropertyName(name) if name.starts_with("--") && !set.contains(&name) => {
+                set.push(name)
+            }
+            CssToken::PropertyValue(value) => {
+                for name in var_references_without_fallback(&value) {
+                    if !used.contains(&name) {
+                        used.push(name);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+    (set, used)
+}
+
+fn var_references_without_fallback(value: &str) -> Vec<String> {
+    // Names of the custom properties in the var()s of a property value, apart from those with a fallback
+
+    let mut names = vec![];
+    let mut rest = value;
+    while let Some(idx) = rest.find("var(") {
+        // Don't match the end of a longer function name
+        let is_var_function = !rest[..idx]
+            .chars()
+            .last()
+            .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        rest = &rest[idx + "var(".len()..];
+        let name_end = rest.find([',', ')']).unwrap_or(rest.len());
+        let name = rest[..name_end].trim();
+        if is_var_function && name.starts_with("--") && rest[name_end..].starts_with(')') {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+fn find_keyframe_names(tokens: &[CssToken]) -> Vec<String> {
+    tokens
+        .iter()
+        .filter_map(|token| match token {
+            CssToken::AtRule { name, prelude } if tokeniser::is_keyframes_at_rule(name) => {
+                Some(prelude.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn scoped_keyframe_name(keyframe_name: &str, element_name: &str) -> String {
+    format!("_sp{element_name}-{keyframe_name}")
+}
+
+fn rename_keyframes_in_value(value: &str, keyframe_names: &[String], element_name: &str) -> String {
+    // Point an animation property at the scoped names of the keyframes declared in this file.
+    // Names of keyframes that aren't declared here are left alone, as they could be global ones.
+    // Words are separated by whitespace or commas, which are kept as they are
+
+    let mut result = String::with_capacity(value.len());
+    for word in value.split_inclusive(|c: char| c.is_whitespace() || c == ',') {
+        let separator_start = word
+            .char_indices()
+            .next_back()
+            .filter(|(_, c)| c.is_whitespace() || *c == ',')
+            .map_or(word.len(), |(idx, _)| idx);
+        let (word, separator) = word.split_at(separator_start);
+        if keyframe_names.iter().any(|name| name == word) {
+            result += &scoped_keyframe_name(word, element_name);
+        } else {
+            result += word;
         }
-        .as_str();
+        result += separator;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(css: &str) -> String {
+        let settings = CompilationSettings {
+            log_level: CompilationLogLevel::Silent,
+            minify_files: false,
+            ..Default::default()
+        };
+        compile_scoped_css(css, "Button", &CssPartials::new(), &settings).unwrap()
+    }
+
+    #[test]
+    fn test_golden_files() {
+        // Every testdata/scoped_css/x.css is compiled and compared against x.expected.css and x.expected.min.css,
+        // or x.error.txt if it should fail. Files in testdata/scoped_css/_partials can be used with @use.
+        // Run with SPALL_UPDATE_GOLDEN=1 to rewrite the expected files after an intentional change
+        let golden_dir =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/scoped_css");
+        let update = std::env::var("SPALL_UPDATE_GOLDEN").is_ok();
+
+        let mut inputs: Vec<_> = std::fs::read_dir(&golden_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let path = path.to_string_lossy();
+                path.ends_with(".css")
+                    && !path.ends_with(".expected.css")
+                    && !path.ends_with(".expected.min.css")
+            })
+            .collect();
+        inputs.sort();
+        assert!(!inputs.is_empty());
+        let partials: CssPartials = std::fs::read_dir(golden_dir.join("_partials"))
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                (
+                    path.file_stem().unwrap().to_string_lossy().to_string(),
+                    std::fs::read_to_string(&path).unwrap(),
+                )
+            })
+            .collect();
+
+        for input in inputs {
+            let stem = input.file_stem().unwrap().to_string_lossy().to_string();
+            for minify_files in [false, true] {
+                let settings = CompilationSettings {
+                    log_level: CompilationLogLevel::Silent,
+                    minify_files,
+                    ..Default::default()
+                };
+                let extension = if minify_files { "min.css" } else { "css" };
+                let (expected_path, actual) = match compile_scoped_css(
+                    &std::fs::read_to_string(&input).unwrap(),
+                    "Golden",
+                    &partials,
+                    &settings,
+                ) {
+                    Ok(css) => (golden_dir.join(format!("{stem}.expected.{extension}")), css),
+                    Err(e) => (
+                        golden_dir.join(format!("{stem}.error.txt")),
+                        format!("{e}\n"),
+                    ),
+                };
+                if update {
+                    std::fs::write(&expected_path, &actual).unwrap();
+                }
+                let expected = std::fs::read_to_string(&expected_path).unwrap_or_else(|_| {
+                    panic!(
+                        "{} is missing (output was:\n{actual})",
+                        expected_path.display()
+                    )
+                });
+                assert_eq!(
+                    actual,
+                    expected,
+                    "{} doesn't match",
+                    expected_path.display()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_scoped_css() {
+        assert_eq!(
+            compile("p, .big { color: red; }"),
+            "._spButton p, ._spButton .big {\n    color: red;\n}\n\n"
+        );
+    }
+
+    #[test]
+    fn test_media_queries_are_scoped() {
+        assert_eq!(
+            compile("@media (max-width: 600px) { p { color: red; } @supports (display: grid) { div { display: grid; } } }"),
+            "@media (max-width: 600px) {\n    ._spButton p {\n        color: red;\n    }\n    @supports (display: grid) {\n        ._spButton div {\n            display: grid;\n        }\n    }\n}\n\n"
+        );
+    }
+
+    #[test]
+    fn test_keyframes_are_scoped_per_element() {
+        let css = compile(
+            "@keyframes spin { from { rotate: 0deg; } 50%, to { rotate: 360deg; } } p { animation: spin 1s linear, fade 2s; } div { animation-name: spin; }",
+        );
+        assert!(css.starts_with("@keyframes _spButton-spin {\n    from {\n"));
+        assert!(css.contains("    50%, to {\n        rotate: 360deg;\n    }\n"));
+        assert!(css.contains("animation: _spButton-spin 1s linear, fade 2s;"));
+        assert!(css.contains("animation-name: _spButton-spin;"));
+    }
+
+    #[test]
+    fn test_rename_keyframes_in_value() {
+        let names = vec!["spin".to_string(), "fade".to_string()];
+        assert_eq!(
+            rename_keyframes_in_value("spin\t1s  linear,fade 2s,\n  spinner 1s", &names, "Button"),
+            "_spButton-spin\t1s  linear,_spButton-fade 2s,\n  spinner 1s"
+        );
+    }
+
+    #[test]
+    fn test_compile_global_css() {
+        let settings = CompilationSettings {
+            log_level: CompilationLogLevel::Silent,
+            ..Default::default()
+        };
+        assert_eq!(
+            compile_global_css(
+                "@keyframes spin {} body  >  p { color: red; }",
+                &CssPartials::new(),
+                &settings
+            )
+            .unwrap(),
+            "@keyframes spin{}body>p{color:red}"
+        );
+    }
+
+    #[test]
+    fn test_find_unmatched_selectors() {
+        let markup_names = MarkupNames {
+            tags: vec!["div".to_string(), "p".to_string()],
+            classes: vec!["card".to_string()],
+            has_dynamic_classes: false,
+            has_child_elements: false,
+        };
+        assert_eq!(
+            find_unmatched_selectors(
+                ":host, .card > p, div.missing, a, :global(.dark) p {} @keyframes x { from {} } @media print { span {} }",
+                &CssPartials::new(),
+                &markup_names
+            ),
+            vec!["div.missing", "a", "span"]
+        );
+
+        let markup_names = MarkupNames {
+            has_dynamic_classes: true,
+            ..markup_names
+        };
+        assert_eq!(
+            find_unmatched_selectors(".missing, a {}", &CssPartials::new(), &markup_names),
+            vec!["a"]
+        );
+
+        let markup_names = MarkupNames {
+            has_child_elements: true,
+            ..markup_names
+        };
+        assert_eq!(
+            find_unmatched_selectors("a, :host > a {}", &CssPartials::new(), &markup_names),
+            vec![":host > a"]
+        );
+    }
+
+    #[test]
+    fn test_urls_point_at_static_dir() {
+        assert_eq!(
+            point_urls_at_static_dir(
+                "a{background:url(img/a.png),url('../b.png'),url(/c.png),url(data:x),url(#m)}"
+            ),
+            "a{background:url(/static/img/a.png),url(\"/b.png\"),url(/c.png),url(data:x),url(#m)}"
+        );
+    }
+
+    #[test]
+    fn test_find_custom_properties() {
+        assert_eq!(
+            find_custom_properties(
+                ".a { --gap: 4px; margin: var(--gap) var( --edge ); color: var(--text, black); } \
+                 .b { --gap: 2px; border-color: var(--text); --inner: calc(2 * var(--gap)); background: myvar(--x); }"
+            ),
+            (
+                vec!["--gap".to_string(), "--inner".to_string()],
+                vec![
+                    "--gap".to_string(),
+                    "--edge".to_string(),
+                    "--text".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_at_rules_without_rules() {
+        assert_eq!(
+            compile("@import url(base.css); @font-face { font-family: Mine; }"),
+            "@import url(base.css);\n@font-face {\n    font-family: Mine;\n}\n\n"
+        );
     }
-    Ok(result)
 }
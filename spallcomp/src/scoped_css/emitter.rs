@@ -0,0 +1,224 @@
+// Writes CSS tokens back out as text, either readable (indented, one property per line) or minified.
+// Minifying drops comments, collapses whitespace and merges rules with the same selectors.
+// Only rules that are next to each other are merged - merging ones further apart could change which rule wins
+// when something in between matches the same markup.
+
+use crate::common::tokeniser_utils;
+use crate::scoped_css::tokeniser::CssToken;
+
+const INDENT: &str = "    ";
+
+pub fn emit_css(tokens: &[CssToken], minify: bool) -> String {
+    if minify {
+        emit_minified(tokens)
+    } else {
+        emit_readable(tokens)
+    }
+}
+
+fn emit_readable(tokens: &[CssToken]) -> String {
+    let mut result = "".to_string();
+    let mut depth = 0;
+    for token in tokens {
+        result += match token {
+            CssToken::AtRule { name, prelude } => {
+                if prelude.is_empty() {
+                    format!("{}@{name}", INDENT.repeat(depth))
+                } else {
+                    format!("{}@{name} {prelude}", INDENT.repeat(depth))
+                }
+            }
+            CssToken::BlockEnd => {
+                depth -= 1;
+                if depth == 0 {
+                    "}\n\n".to_string()
+                } else {
+                    format!("{}}}\n", INDENT.repeat(depth))
+                }
+            }
+            CssToken::BlockStart => {
+                depth += 1;
+                " {\n".to_string()
+            }
+            CssToken::Colon => ": ".to_string(),
+            CssToken::Comma => ", ".to_string(),
+            CssToken::Comment(value) => format!("{}/* {value} */\n", INDENT.repeat(depth)),
+            CssToken::Important => " !important".to_string(),
+            CssToken::PropertyName(name) => format!("{}{name}", INDENT.repeat(depth)),
+            CssToken::PropertyValue(value) => value.clone(),
+            CssToken::Semicolon => ";\n".to_string(),
+            CssToken::Selector(value) => {
+                // Only the first selector of a rule needs indenting
+                if result.ends_with('\n') || result.is_empty() {
+                    format!("{}{value}", INDENT.repeat(depth))
+                } else {
+                    value.clone()
+                }
+            }
+        }
+        .as_str();
+    }
+    result
+}
+
+fn emit_minified(tokens: &[CssToken]) -> String {
+    let mut result = "".to_string();
+    // Selectors of the rule being read, and of the rule that was just closed along with where its } is in result
+    let mut crnt_selectors = "".to_string();
+    let mut last_closed_rule: Option<(String, usize)> = None;
+    // The selectors of each open block, or None for at-rule blocks
+    let mut open_blocks: Vec<Option<String>> = vec![];
+    let mut crnt_property_name = "";
+
+    for token in tokens {
+        match token {
+            CssToken::Comment(_) => continue,
+            CssToken::Selector(value) => {
+                crnt_selectors += &compact(value, &['>', '+', '~', ',']);
+                continue;
+            }
+            CssToken::Comma => {
+                crnt_selectors.push(',');
+                continue;
+            }
+            CssToken::BlockStart if !crnt_selectors.is_empty() => {
+                let selectors = std::mem::take(&mut crnt_selectors);
+                match last_closed_rule.take() {
+                    // Same selectors as the rule just before, so carry on inside that one
+                    Some((last_selectors, close_idx)) if last_selectors == selectors => {
+                        result.truncate(close_idx);
+                        if !result.ends_with('{') {
+                            result.push(';');
+                        }
+                    }
+                    _ => {
+                        result += &selectors;
+                        result.push('{');
+                    }
+                }
+                open_blocks.push(Some(selectors));
+                continue;
+            }
+            _ => (),
+        }
+        last_closed_rule = None;
+
+        match token {
+            CssToken::AtRule { name, prelude } => {
+                result += &format!("@{name}");
+                if !prelude.is_empty() {
+                    result += &format!(" {}", compact(prelude, &[',']));
+                }
+            }
+            CssToken::BlockStart => {
+                open_blocks.push(None);
+                result.push('{');
+            }
+            CssToken::BlockEnd => {
+                // The last property in a block doesn't need its semicolon
+                if result.ends_with(';') {
+                    result.pop();
+                }
+                if let Some(Some(selectors)) = open_blocks.pop() {
+                    last_closed_rule = Some((selectors, result.len()));
+                }
+                result.push('}');
+            }
+            CssToken::Colon => result.push(':'),
+            CssToken::Important => result += "!important",
+            CssToken::PropertyName(name) => {
+                crnt_property_name = name;
+                result += name;
+            }
+            // Custom properties can hold anything, so their values are kept as they are
+            CssToken::PropertyValue(value) if crnt_property_name.starts_with("--") => {
+                result += if value.is_empty() { " " } else { value };
+            }
+            CssToken::PropertyValue(value) => result += &compact(value, &[',']),
+            CssToken::Semicolon => result.push(';'),
+            CssToken::Comment(_) | CssToken::Selector(_) | CssToken::Comma => (),
+        }
+    }
+    result
+}
+
+fn compact(text: &str, tight_chars: &[char]) -> String {
+    // Collapse runs of whitespace into a single space, and remove it completely around tight_chars.
+    // Whitespace inside strings and escapes (eg the space that ends \31 ) is left alone
+
+    let mut result = String::new();
+    let mut pending_space = false;
+    let mut quote_char = None;
+    let mut escape_end = 0;
+    for (idx, c) in text.char_indices() {
+        if idx < escape_end || quote_char.is_some() {
+            result.push(c);
+            if quote_char == Some(c) && idx >= escape_end {
+                quote_char = None;
+            } else if c == '\\' && idx >= escape_end {
+                escape_end = idx + tokeniser_utils::escape_length(&text[idx..]);
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+        if pending_space
+            && !result.is_empty()
+            && !tight_chars.contains(&c)
+            && !result.ends_with(tight_chars)
+        {
+            result.push(' ');
+        }
+        pending_space = false;
+        match c {
+            '"' | '\'' => quote_char = Some(c),
+            '\\' => escape_end = idx + tokeniser_utils::escape_length(&text[idx..]),
+            _ => (),
+        }
+        result.push(c);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scoped_css::tokeniser::tokenise_css;
+
+    fn minify(css: &str) -> String {
+        emit_css(&tokenise_css(css).unwrap(), true)
+    }
+
+    #[test]
+    fn test_minify() {
+        assert_eq!(
+            minify(
+                "/* c */ div  >  p,\n a + b { color : red ;  margin: 0 ,  1px  2px !important; }"
+            ),
+            "div>p,a+b{color:red;margin:0,1px 2px!important}"
+        );
+        assert_eq!(
+            minify("@media (max-width: 600px) { p { color: red } } @import url(a.css);"),
+            "@media (max-width: 600px){p{color:red}}@import url(a.css);"
+        );
+        assert_eq!(
+            minify("p { content: \"a  ,  b\"; font-family: \"\\\\\"  x; } .icon-\\31 0 {}"),
+            "p{content:\"a  ,  b\";font-family:\"\\\\\" x}.icon-\\31 0{}"
+        );
+    }
+
+    #[test]
+    fn test_merge_adjacent_duplicate_rules() {
+        assert_eq!(
+            minify("p, a { color: red; } p,a { margin: 0; } a { top: 0; } p, a { left: 0; }"),
+            "p,a{color:red;margin:0}a{top:0}p,a{left:0}"
+        );
+        assert_eq!(minify("p {} p { color: red; }"), "p{color:red}");
+        assert_eq!(
+            minify("@media print { p { color: red; } } @media print { p { top: 0; } }"),
+            "@media print{p{color:red}}@media print{p{top:0}}"
+        );
+    }
+}
@@ -1,2 +1,5 @@
 pub mod compiler;
+pub mod emitter;
+pub mod preprocessor;
+pub mod selector;
 pub mod tokeniser;
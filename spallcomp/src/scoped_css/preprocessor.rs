@@ -0,0 +1,684 @@
+// A small superset of CSS that is compiled to plain CSS before scoping, so that styles can be shared without a
+// separate preprocessor. It adds:
+// - Nested rules. & stands for the selectors of the rule it is nested in (eg &:hover, .dark &, &__title), and
+//   nested selectors without an & are descendants of it. @media and other grouping at-rules can be nested too
+// - $variables, defined with `$accent: #0af;` and used in property values and at-rule preludes.
+//   A variable defined inside a block can only be used inside that block
+// - `@use "name";`, which includes styles/_partials/name.css at that point, along with any variables it defines.
+//   Each partial is only included once per file, however many times it is used
+//
+// The source is parsed into statements that remember the line they start on, so that errors (including plain CSS
+// syntax errors, which are found here first) can say where they are. Plain CSS comes out the same as it went in.
+
+use std::collections::HashMap;
+
+use crate::common::tokeniser_utils;
+use crate::errs;
+use crate::scoped_css::selector;
+use crate::scoped_css::tokeniser;
+
+/// The contents of each partial in styles/_partials, by file name without the .css
+pub type CssPartials = HashMap<String, String>;
+
+const USE_AT_RULE: &str = "use";
+const PARTIAL_EXTENSION: &str = ".css";
+
+enum Statement {
+    Comment(String),
+    Variable {
+        name: String,
+        value: String,
+        line: usize,
+    },
+    Use {
+        partial_name: String,
+        line: usize,
+    },
+    Declaration {
+        text: String, // eg color: red, without the semicolon
+        line: usize,
+    },
+    AtRule {
+        name: String,
+        prelude: String,
+        block: Option<Vec<Statement>>,
+        line: usize,
+    },
+    Rule {
+        selectors: String,
+        block: Vec<Statement>,
+        line: usize,
+    },
+}
+
+// What the block that is being written contains, which decides what its statements turn into
+#[derive(Clone, Copy)]
+enum BlockContext<'a> {
+    Rules, // top level, or inside an at-rule like @media that isn't nested in a rule
+    RuleBody(&'a [String]), // inside a rule, whose resolved selectors are given
+    Keyframes,
+    Properties, // eg @font-face
+}
+
+type ParseResult<T> = Result<T, (usize, errs::CssSyntaxError)>; // errors come with their line
+
+pub fn preprocess(css: &str, partials: &CssPartials) -> Result<String, errs::CssSourceError> {
+    // Compile css that may use nesting, $variables and @use into plain css
+
+    let statements = parse_source(css, None)?;
+    let mut preprocessor = Preprocessor {
+        partials,
+        used_partials: vec![],
+        partial_stack: vec![],
+        variable_scopes: vec![HashMap::new()],
+        output: String::new(),
+    };
+    preprocessor.write_block(&statements, BlockContext::Rules)?;
+    Ok(preprocessor.output)
+}
+
+fn parse_source(
+    css: &str,
+    partial_name: Option<&str>,
+) -> Result<Vec<Statement>, errs::CssSourceError> {
+    parse_statements(css, 0, None)
+        .map(|(statements, _)| statements)
+        .map_err(|(line, error)| errs::CssSourceError {
+            line,
+            partial_name: partial_name.map(str::to_string),
+            error,
+        })
+}
+
+fn line_at(css: &str, idx: usize) -> usize {
+    css[..idx].matches('\n').count() + 1
+}
+
+fn parse_statements(
+    css: &str,
+    start: usize,
+    open_brace: Option<usize>,
+) -> ParseResult<(Vec<Statement>, usize)> {
+    // Parse statements from start until the end of the css, or until the } that closes open_brace if it is given.
+    // Returns the statements and the index after the last thing read
+
+    let mut statements = vec![];
+    let mut idx = start;
+    loop {
+        idx += css[idx..].len() - css[idx..].trim_start().len();
+        let rest = &css[idx..];
+        if let Some(comment) = rest.strip_prefix("/*") {
+            let comment_length = comment
+                .find("*/")
+                .ok_or((line_at(css, idx), errs::CssSyntaxError::UnexpectedEndOfFile))?;
+            statements.push(Statement::Comment(
+                comment[..comment_length].trim().to_string(),
+            ));
+            idx += comment_length + 4;
+            continue;
+        }
+        let line = line_at(css, idx);
+        match rest.chars().next() {
+            None => match open_brace {
+                Some(brace_idx) => Err((
+                    line_at(css, brace_idx),
+                    errs::CssSyntaxError::UnexpectedEndOfFile,
+                ))?,
+                None => break,
+            },
+            Some('}') => match open_brace {
+                Some(_) => {
+                    idx += 1;
+                    break;
+                }
+                None => Err((line, errs::CssSyntaxError::UnexpectedBlockEnd))?,
+            },
+            Some(';') => idx += 1,
+            Some('@') => {
+                let (statement, chars_read) = parse_at_rule(css, idx)?;
+                statements.push(statement);
+                idx += chars_read;
+            }
+            Some('$') => {
+                let (text, chars_read) = read_declaration(rest, line)?;
+                let (name, value) = text.split_once(':').unwrap_or_default();
+                statements.push(Statement::Variable {
+                    name: name[1..].trim().to_string(),
+                    value: value.trim().to_string(),
+                    line,
+                });
+                idx += chars_read;
+            }
+            Some(_) if rest.starts_with("--") => {
+                // Custom properties can hold blocks, so are always declarations
+                let (text, chars_read) = read_declaration(rest, line)?;
+                statements.push(Statement::Declaration { text, line });
+                idx += chars_read;
+            }
+            Some(_) => {
+                let (text, stop_char, chars_read) =
+                    tokeniser::read_until_top_level(rest, &[';', '{', '}']);
+                if stop_char == Some('{') {
+                    let selectors = text.trim().to_string();
+                    if selectors.is_empty() {
+                        Err((
+                            line,
+                            errs::CssSyntaxError::InvalidSelector {
+                                selector: selectors.clone(),
+                                reason: "it is empty",
+                            },
+                        ))?
+                    }
+                    let brace_idx = idx + chars_read;
+                    let (block, block_end) = parse_statements(css, brace_idx + 1, Some(brace_idx))?;
+                    statements.push(Statement::Rule {
+                        selectors,
+                        block,
+                        line,
+                    });
+                    idx = block_end;
+                } else {
+                    let (text, chars_read) = read_declaration(rest, line)?;
+                    statements.push(Statement::Declaration { text, line });
+                    idx += chars_read;
+                }
+            }
+        }
+    }
+    Ok((statements, idx))
+}
+
+fn read_declaration(css: &str, line: usize) -> ParseResult<(String, usize)> {
+    // Read a declaration (or variable definition) up to the ; or } that ends it.
+    // Returns it without the ; and the number of bytes read, including the ; but not a }
+
+    let (text, stop_char, chars_read) = tokeniser::read_until_top_level(css, &[';', '}']);
+    let text = text.trim().to_string();
+    match stop_char {
+        None => Err((line, errs::CssSyntaxError::UnexpectedEndOfFile)),
+        Some(_)
+            if !tokeniser_utils::top_level_chars(&text)
+                .iter()
+                .any(|(_, c)| *c == ':') =>
+        {
+            Err((
+                line,
+                errs::CssSyntaxError::MissingColon {
+                    property_name: text,
+                },
+            ))
+        }
+        Some(';') => Ok((text, chars_read + 1)),
+        Some(_) => Ok((text, chars_read)),
+    }
+}
+
+fn parse_at_rule(css: &str, start: usize) -> ParseResult<(Statement, usize)> {
+    // Parse an at-rule, including its block if it has one.
+    // Presumes css[start..] starts with the @. Returns the statement and the number of bytes read
+
+    let line = line_at(css, start);
+    let rest = &css[start..];
+    let name: String = rest[1..]
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    let prelude_start = 1 + name.len();
+    let (prelude, stop_char, prelude_length) =
+        tokeniser::read_until_top_level(&rest[prelude_start..], &[';', '{', '}']);
+    let prelude = prelude.trim().to_string();
+    let prelude_end = prelude_start + prelude_length;
+
+    if name == USE_AT_RULE {
+        if stop_char != Some(';') && stop_char != Some('}') {
+            Err((line, errs::CssSyntaxError::UnexpectedEndOfFile))?
+        }
+        let partial_name = prelude.trim_matches(['"', '\'']);
+        let partial_name = partial_name
+            .strip_suffix(PARTIAL_EXTENSION)
+            .unwrap_or(partial_name)
+            .to_string();
+        let chars_read = prelude_end + usize::from(stop_char == Some(';'));
+        return Ok((Statement::Use { partial_name, line }, chars_read));
+    }
+
+    let (block, chars_read) = match stop_char {
+        None => Err((line, errs::CssSyntaxError::UnexpectedEndOfFile))?,
+        Some(';') => (None, prelude_end + 1),
+        Some('}') => (None, prelude_end),
+        Some(_) => {
+            let brace_idx = start + prelude_end;
+            let (block, block_end) = parse_statements(css, brace_idx + 1, Some(brace_idx))?;
+            (Some(block), block_end - start)
+        }
+    };
+    Ok((
+        Statement::AtRule {
+            name,
+            prelude,
+            block,
+            line,
+        },
+        chars_read,
+    ))
+}
+
+struct Preprocessor<'a> {
+    partials: &'a CssPartials,
+    used_partials: Vec<String>,
+    partial_stack: Vec<String>, // the partials currently being written, innermost last
+    variable_scopes: Vec<HashMap<String, String>>,
+    output: String,
+}
+
+impl Preprocessor<'_> {
+    fn error(&self, line: usize, error: errs::CssSyntaxError) -> errs::CssSourceError {
+        errs::CssSourceError {
+            line,
+            partial_name: self.partial_stack.last().cloned(),
+            error,
+        }
+    }
+
+    fn write_block(
+        &mut self,
+        statements: &[Statement],
+        context: BlockContext,
+    ) -> Result<(), errs::CssSourceError> {
+        // Write out the plain css for the statements in a block.
+        // Declarations in a rule body are written in a rule of their own, which is split wherever a nested rule
+        // comes between them so that they still apply in the same order
+
+        let mut pending_declarations: Vec<String> = vec![];
+        for statement in statements {
+            match statement {
+                Statement::Comment(text) => match context {
+                    BlockContext::RuleBody(_) => pending_declarations.push(format!("/* {text} */")),
+                    _ => self.output += &format!("/* {text} */\n"),
+                },
+                Statement::Variable { name, value, line } => {
+                    let value = self.substitute_variables(value, *line)?;
+                    if let Some(scope) = self.variable_scopes.last_mut() {
+                        scope.insert(name.clone(), value);
+                    }
+                }
+                Statement::Use { partial_name, line } => {
+                    self.write_partial(partial_name, *line, context)?
+                }
+                Statement::Declaration { text, line } => {
+                    let text = self.substitute_variables(text, *line)?;
+                    match context {
+                        BlockContext::RuleBody(_) => pending_declarations.push(format!("{text};")),
+                        BlockContext::Rules => Err(self.error(
+                            *line,
+                            errs::CssSyntaxError::DeclarationOutsideRule { declaration: text },
+                        ))?,
+                        _ => self.output += &format!("{text};\n"),
+                    }
+                }
+                Statement::AtRule { .. } | Statement::Rule { .. } => {
+                    if let BlockContext::RuleBody(parent_selectors) = context {
+                        self.write_declarations(parent_selectors, &mut pending_declarations);
+                    }
+                    self.write_nested(statement, context)?;
+                }
+            }
+        }
+
+        if let BlockContext::RuleBody(parent_selectors) = context {
+            // A rule with nothing nested in it is written as it is, even if it is empty
+            let has_nested = statements
+                .iter()
+                .any(|s| matches!(s, Statement::AtRule { .. } | Statement::Rule { .. }));
+            if !has_nested || !pending_declarations.is_empty() {
+                self.output += &format!("{} {{\n", parent_selectors.join(", "));
+                for declaration in pending_declarations {
+                    self.output += &format!("{declaration}\n");
+                }
+                self.output += "}\n";
+            }
+        }
+        Ok(())
+    }
+
+    fn write_declarations(&mut self, selectors: &[String], declarations: &mut Vec<String>) {
+        if declarations.is_empty() {
+            return;
+        }
+        self.output += &format!("{} {{\n", selectors.join(", "));
+        for declaration in declarations.drain(..) {
+            self.output += &format!("{declaration}\n");
+        }
+        self.output += "}\n";
+    }
+
+    fn write_nested(
+        &mut self,
+        statement: &Statement,
+        context: BlockContext,
+    ) -> Result<(), errs::CssSourceError> {
+        // Write a rule or at-rule, along with everything inside it
+
+        let parent_selectors = match context {
+            BlockContext::RuleBody(parent_selectors) => Some(parent_selectors),
+            _ => None,
+        };
+        self.variable_scopes.push(HashMap::new());
+        match statement {
+            Statement::Rule {
+                selectors,
+                block,
+                line,
+            } => match context {
+                BlockContext::Rules | BlockContext::RuleBody(_) => {
+                    let selectors = resolve_selectors(selectors, parent_selectors)
+                        .map_err(|e| self.error(*line, e))?;
+                    for selector in &selectors {
+                        selector::validate_selector(selector).map_err(|e| self.error(*line, e))?;
+                    }
+                    self.write_block(block, BlockContext::RuleBody(&selectors))?;
+                }
+                // Keyframe selectors like 50%, which are left alone
+                BlockContext::Keyframes | BlockContext::Properties => {
+                    self.output += &format!("{selectors} {{\n");
+                    self.write_block(block, BlockContext::Properties)?;
+                    self.output += "}\n";
+                }
+            },
+            Statement::AtRule {
+                name,
+                prelude,
+                block,
+                line,
+            } => {
+                let prelude = self.substitute_variables(prelude, *line)?;
+                self.output += &format!("@{name} {prelude}");
+                match block {
+                    None => self.output += ";\n",
+                    Some(block) => {
+                        let block_context = if tokeniser::is_keyframes_at_rule(name) {
+                            BlockContext::Keyframes
+                        } else if tokeniser::is_grouping_at_rule(name) {
+                            match parent_selectors {
+                                // Nested in a rule, so what's inside still belongs to that rule
+                                Some(parent_selectors) => BlockContext::RuleBody(parent_selectors),
+                                None => BlockContext::Rules,
+                            }
+                        } else {
+                            BlockContext::Properties
+                        };
+                        self.output += " {\n";
+                        self.write_block(block, block_context)?;
+                        self.output += "}\n";
+                    }
+                }
+            }
+            _ => (),
+        }
+        self.variable_scopes.pop();
+        Ok(())
+    }
+
+    fn write_partial(
+        &mut self,
+        partial_name: &str,
+        line: usize,
+        context: BlockContext,
+    ) -> Result<(), errs::CssSourceError> {
+        // Write a partial's css where it is used. Its variables are defined in the scope it is used in
+
+        if self.partial_stack.iter().any(|name| name == partial_name) {
+            Err(self.error(
+                line,
+                errs::CssSyntaxError::CircularUse {
+                    name: partial_name.to_string(),
+                },
+            ))?
+        }
+        if self.used_partials.iter().any(|name| name == partial_name) {
+            return Ok(());
+        }
+        let content = self.partials.get(partial_name).ok_or_else(|| {
+            self.error(
+                line,
+                errs::CssSyntaxError::UnknownPartial {
+                    name: partial_name.to_string(),
+                },
+            )
+        })?;
+        let statements = parse_source(content, Some(partial_name))?;
+
+        self.used_partials.push(partial_name.to_string());
+        self.partial_stack.push(partial_name.to_string());
+        self.write_block(&statements, context)?;
+        self.partial_stack.pop();
+        Ok(())
+    }
+
+    fn substitute_variables(
+        &self,
+        text: &str,
+        line: usize,
+    ) -> Result<String, errs::CssSourceError> {
+        // Replace each $variable in text with its value. Anything in strings or escaped is left alone
+
+        let mut result = String::new();
+        let mut quote_char = None;
+        let mut idx = 0;
+        while let Some(c) = text[idx..].chars().next() {
+            match (quote_char, c) {
+                (_, '\\') => {
+                    let escape_end = idx + tokeniser_utils::escape_length(&text[idx..]);
+                    result += &text[idx..escape_end];
+                    idx = escape_end;
+                    continue;
+                }
+                (Some(q), _) if c == q => quote_char = None,
+                (Some(_), _) => (),
+                (None, '"' | '\'') => quote_char = Some(c),
+                (None, '$') => {
+                    let name: String = text[idx + 1..]
+                        .chars()
+                        .take_while(|c| is_variable_name_char(*c))
+                        .collect();
+                    if !name.is_empty() {
+                        let value = self
+                            .variable_scopes
+                            .iter()
+                            .rev()
+                            .find_map(|scope| scope.get(&name))
+                            .ok_or_else(|| {
+                                self.error(
+                                    line,
+                                    errs::CssSyntaxError::UndefinedVariable { name: name.clone() },
+                                )
+                            })?;
+                        result += value;
+                        idx += 1 + name.len();
+                        continue;
+                    }
+                }
+                _ => (),
+            }
+            result.push(c);
+            idx += c.len_utf8();
+        }
+        Ok(result)
+    }
+}
+
+fn is_variable_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+fn resolve_selectors(
+    selectors: &str,
+    parent_selectors: Option<&[String]>,
+) -> Result<Vec<String>, errs::CssSyntaxError> {
+    // Combine the selectors of a nested rule with the selectors of the rule it is in.
+    // & is replaced by each parent selector, and selectors without one become descendants of it
+
+    let selectors = selector::split_selector_list(selectors);
+    let Some(parent_selectors) = parent_selectors else {
+        if let Some(selector) = selectors
+            .iter()
+            .find(|s| replace_parent_selector(s, "").is_some())
+        {
+            Err(errs::CssSyntaxError::ParentSelectorOutsideRule {
+                selector: selector.trim().to_string(),
+            })?
+        }
+        return Ok(selectors);
+    };
+
+    let mut resolved = vec![];
+    for parent_selector in parent_selectors {
+        for selector in &selectors {
+            let selector = selector.trim();
+            resolved.push(
+                replace_parent_selector(selector, parent_selector)
+                    .unwrap_or_else(|| format!("{parent_selector} {selector}")),
+            );
+        }
+    }
+    Ok(resolved)
+}
+
+fn replace_parent_selector(selector: &str, parent_selector: &str) -> Option<String> {
+    // Replace each & in selector (other than in strings or escapes) with parent_selector.
+    // Returns None if there isn't one
+
+    let mut result = String::new();
+    let mut found = false;
+    let mut quote_char = None;
+    let mut idx = 0;
+    while let Some(c) = selector[idx..].chars().next() {
+        match (quote_char, c) {
+            (_, '\\') => {
+                let escape_end = idx + tokeniser_utils::escape_length(&selector[idx..]);
+                result += &selector[idx..escape_end];
+                idx = escape_end;
+                continue;
+            }
+            (Some(q), _) if c == q => quote_char = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'') => quote_char = Some(c),
+            (None, '&') => {
+                found = true;
+                result += parent_selector;
+                idx += 1;
+                continue;
+            }
+            _ => (),
+        }
+        result.push(c);
+        idx += c.len_utf8();
+    }
+    found.then_some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preprocess_ok(css: &str) -> String {
+        let partials = CssPartials::from([
+            (
+                "theme".to_string(),
+                "$accent: blue;\n$gap: 4px;\n.themed { color: $accent; }".to_string(),
+            ),
+            ("loop".to_string(), "@use \"loop\";".to_string()),
+            ("broken".to_string(), "\n\np { color }".to_string()),
+        ]);
+        match preprocess(css, &partials) {
+            Ok(result) => tokeniser::tokenise_css(&result)
+                .map(|tokens| crate::scoped_css::emitter::emit_css(&tokens, true))
+                .unwrap(),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_plain_css_is_unchanged() {
+        assert_eq!(
+            preprocess_ok("/* c */ a, b:hover {} @media print { p { color: red; --x: { a: b }; } } @import url(a.css);"),
+            "a,b:hover{}@media print{p{color:red;--x:{ a: b }}}@import url(a.css);"
+        );
+    }
+
+    #[test]
+    fn test_nesting() {
+        assert_eq!(
+            preprocess_ok(".card, .panel { color: red; &:hover, .dark & { color: blue; } h2 { margin: 0; } top: 0; }"),
+            ".card,.panel{color:red}.card:hover,.dark .card,.panel:hover,.dark .panel{color:blue}.card h2,.panel h2{margin:0}.card,.panel{top:0}"
+        );
+        assert_eq!(
+            preprocess_ok(
+                ".card { &__title { a: b; } @media print { display: none; p { c: d; } } }"
+            ),
+            ".card__title{a:b}@media print{.card{display:none}.card p{c:d}}"
+        );
+        assert_eq!(
+            preprocess_ok(
+                "@keyframes spin { from { rotate: 0deg; } } @font-face { font-family: x; }"
+            ),
+            "@keyframes spin{from{rotate:0deg}}@font-face{font-family:x}"
+        );
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(
+            preprocess_ok("$size: 2px; $border: $size solid; p { $inner: red; border: $border $inner; content: \"$size\"; } @media (min-width: $size) { a { b: c; } }"),
+            "p{border:2px solid red;content:\"$size\"}@media (min-width: 2px){a{b:c}}"
+        );
+        assert_eq!(
+            preprocess_ok("p { $inner: red; }\na { color: $inner; }"),
+            "Line 2: Variable $inner is not defined"
+        );
+    }
+
+    #[test]
+    fn test_use_partials() {
+        assert_eq!(
+            preprocess_ok("@use \"theme\";\n@use 'theme.css';\np { gap: $gap; }"),
+            ".themed{color:blue}p{gap:4px}"
+        );
+        assert_eq!(
+            preprocess_ok("\n@use \"missing\";"),
+            "Line 2: There is no partial called \"missing\" (expected styles/_partials/missing.css)"
+        );
+        assert_eq!(
+            preprocess_ok("@use \"loop\";"),
+            "Line 1 of partial \"loop\": Partial \"loop\" ends up using itself through @use"
+        );
+        assert_eq!(
+            preprocess_ok("@use \"broken\";"),
+            "Line 3 of partial \"broken\": Expected a : after property \"color\""
+        );
+    }
+
+    #[test]
+    fn test_errors_have_lines() {
+        assert_eq!(
+            preprocess_ok("p {\n  color: red;\n}\n\n& a {}"),
+            "Line 5: Selector \"& a\" uses & but is not nested inside another rule"
+        );
+        assert_eq!(
+            preprocess_ok("a {}\ncolor: red;"),
+            "Line 2: Property \"color: red\" is not inside a rule"
+        );
+        assert_eq!(
+            preprocess_ok("a {}\n}"),
+            "Line 2: Unexpected } without a matching {"
+        );
+        assert_eq!(
+            preprocess_ok("a {\n\n b {"),
+            "Line 3: Unexpected end of file"
+        );
+        assert_eq!(
+            preprocess_ok("a {\n  b, > {}\n}"),
+            "Line 2: Selector \"a >\" is not valid because it ends with a combinator"
+        );
+    }
+}
@@ -0,0 +1,375 @@
+// Parses CSS selectors enough to scope them to an element.
+// A selector is split into compound selectors (eg p.big:hover::before) and the combinators between them.
+// Brackets, parentheses and strings are kept intact, so attribute selectors and :is()/:not() are never split up.
+//
+// Scoping puts the element's class in front of the first compound that belongs to the element:
+// - :host (or :host(.extra)) refers to the element's wrapper itself, so it is replaced by the scope class instead
+// - :global(...) is unwrapped and never gets the scope in front of it, eg :global(body.dark) .x -> body.dark ._spButton .x
+// - html, body and :root at the start of a selector can't be inside an element, so they're treated as global too
+
+use crate::common::tokeniser_utils::{self, top_level_chars};
+use crate::errs;
+
+const HOST_PSEUDO_CLASS: &str = ":host";
+const GLOBAL_PSEUDO_CLASS: &str = ":global(";
+// Compounds starting with these can only ever be outside of an element
+const DOCUMENT_LEVEL_SELECTORS: [&str; 3] = ["html", "body", ":root"];
+
+#[derive(Debug, PartialEq)]
+enum SelectorPart {
+    Compound(String),
+    Combinator(char), // ' ' for descendant, or one of > + ~
+}
+
+/// Split a selector list at the commas that separate selectors, eg `:is(a, b), c` -> [`:is(a, b)`, `c`]
+pub fn split_selector_list(selector_list: &str) -> Vec<String> {
+    let mut selectors = vec![];
+    let mut start = 0;
+    for (idx, c) in top_level_chars(selector_list) {
+        if c == ',' {
+            selectors.push(selector_list[start..idx].trim().to_string());
+            start = idx + 1;
+        }
+    }
+    selectors.push(selector_list[start..].trim().to_string());
+    selectors
+}
+
+/// Check that a single (not comma separated) selector can be parsed, without scoping it
+pub fn validate_selector(selector: &str) -> Result<(), errs::CssSyntaxError> {
+    parse_selector(selector).map(drop)
+}
+
+/// Scope a single (not comma separated) selector to the element with class `scope_class` (eg `._spButton`)
+pub fn scope_selector(selector: &str, scope_class: &str) -> Result<String, errs::CssSyntaxError> {
+    let mut parts = parse_selector(selector)?;
+
+    // If :host is used then the selector already says where the element is
+    let mut scope_placed = false;
+    for part in &mut parts {
+        if let SelectorPart::Compound(compound) = part {
+            if let Some(replaced) = replace_host(compound, scope_class) {
+                *compound = replaced;
+                scope_placed = true;
+            }
+        }
+    }
+
+    let mut result = String::new();
+    let mut at_start = true;
+    for part in parts {
+        match part {
+            SelectorPart::Combinator(' ') => result.push(' '),
+            SelectorPart::Combinator(c) => result += &format!(" {c} "),
+            SelectorPart::Compound(compound) => {
+                let is_global = compound.starts_with(GLOBAL_PSEUDO_CLASS)
+                    || (at_start && is_document_level(&compound));
+                if !is_global && !scope_placed {
+                    result += &format!("{scope_class} ");
+                    scope_placed = true;
+                }
+                at_start = at_start && is_global;
+                result += &unwrap_global(&compound);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Tag names (lowercase) and class names that the element's markup needs to contain for a single selector to match.
+/// Compounds that aren't inside the element (:host, :global(...) and document level ones) don't need anything.
+/// Classes written with escapes are left out as they can't be compared to the markup directly.
+/// With `skip_descendants`, so are compounds that are reached through a descendant combinator (including the one
+/// after the scope class), as they could be in the markup of a child element.
+pub fn names_needed_in_markup(
+    selector: &str,
+    skip_descendants: bool,
+) -> Result<(Vec<String>, Vec<String>), errs::CssSyntaxError> {
+    let mut tags = vec![];
+    let mut classes = vec![];
+    let mut at_start = true;
+    let mut is_descendant = true;
+    for part in parse_selector(selector)? {
+        let compound = match part {
+            SelectorPart::Combinator(combinator) => {
+                is_descendant = is_descendant || combinator == ' ';
+                continue;
+            }
+            SelectorPart::Compound(compound) => compound,
+        };
+        let is_global =
+            compound.starts_with(GLOBAL_PSEUDO_CLASS) || (at_start && is_document_level(&compound));
+        at_start = at_start && is_global;
+        if is_global {
+            continue;
+        }
+        if replace_host(&compound, "").is_some() {
+            is_descendant = false;
+            continue;
+        }
+        if skip_descendants && is_descendant {
+            continue;
+        }
+
+        let tag_end = compound
+            .find(['.', '#', '[', ':'])
+            .unwrap_or(compound.len());
+        let tag = &compound[..tag_end];
+        if !tag.is_empty() && tag != "*" && !tag.contains(['\\', '|']) {
+            tags.push(tag.to_lowercase());
+        }
+        for (idx, c) in top_level_chars(&compound) {
+            if c != '.' {
+                continue;
+            }
+            let class: String = compound[idx + 1..]
+                .chars()
+                .take_while(|c| {
+                    c.is_alphanumeric() || matches!(c, '-' | '_' | '\\') || !c.is_ascii()
+                })
+                .collect();
+            if !class.is_empty() && !class.contains('\\') {
+                classes.push(class);
+            }
+        }
+    }
+    Ok((tags, classes))
+}
+
+fn parse_selector(selector: &str) -> Result<Vec<SelectorPart>, errs::CssSyntaxError> {
+    // Split a selector into compounds and combinators. Whitespace around combinators is dropped
+
+    let selector = selector.trim();
+    let invalid = |reason: &'static str| errs::CssSyntaxError::InvalidSelector {
+        selector: selector.to_string(),
+        reason,
+    };
+    if selector.is_empty() {
+        return Err(invalid("it is empty"));
+    }
+    check_balanced(selector).map_err(invalid)?;
+
+    let mut parts = vec![];
+    let mut compound_start = 0;
+    let mut pending_combinator: Option<char> = None;
+    for (idx, c) in top_level_chars(selector) {
+        if !matches!(c, ' ' | '\t' | '\n' | '\r' | '>' | '+' | '~') {
+            continue;
+        }
+        if compound_start < idx {
+            if let Some(combinator) = pending_combinator.take() {
+                parts.push(SelectorPart::Combinator(combinator));
+            }
+            parts.push(SelectorPart::Compound(
+                selector[compound_start..idx].to_string(),
+            ));
+        }
+        compound_start = idx + c.len_utf8();
+        // A >, + or ~ wins over the whitespace around it
+        if !c.is_whitespace() {
+            if matches!(pending_combinator, Some('>' | '+' | '~')) {
+                return Err(invalid("it has two combinators in a row"));
+            }
+            pending_combinator = Some(c);
+        } else if pending_combinator.is_none() {
+            pending_combinator = Some(' ');
+        }
+    }
+    if compound_start >= selector.len() {
+        return Err(invalid("it ends with a combinator"));
+    }
+    if let Some(combinator) = pending_combinator {
+        if parts.is_empty() {
+            return Err(invalid("it starts with a combinator"));
+        }
+        parts.push(SelectorPart::Combinator(combinator));
+    }
+    parts.push(SelectorPart::Compound(
+        selector[compound_start..].to_string(),
+    ));
+    Ok(parts)
+}
+
+fn check_balanced(selector: &str) -> Result<(), &'static str> {
+    let mut scanner = tokeniser_utils::BracketScanner::new(selector);
+    scanner.by_ref().for_each(drop);
+    if scanner.has_mismatched_bracket {
+        Err("its brackets don't match")
+    } else if scanner.has_unclosed_string {
+        Err("it has an unclosed string")
+    } else if scanner.has_unclosed_bracket() {
+        Err("its brackets don't match")
+    } else {
+        Ok(())
+    }
+}
+
+fn find_top_level(compound: &str, pattern: &str) -> Option<usize> {
+    // Byte index of pattern in compound, ignoring anything in brackets or strings
+    top_level_chars(compound)
+        .into_iter()
+        .map(|(idx, _)| idx)
+        .find(|idx| compound[*idx..].starts_with(pattern))
+}
+
+fn matching_paren(text: &str, open_idx: usize) -> usize {
+    // Index of the ) that closes the ( at open_idx. Brackets are known to be balanced by now
+    let closes = top_level_chars(&text[open_idx + 1..]);
+    closes
+        .iter()
+        .find(|(_, c)| *c == ')')
+        .map(|(idx, _)| open_idx + 1 + idx)
+        .unwrap_or(text.len() - 1)
+}
+
+fn replace_host(compound: &str, scope_class: &str) -> Option<String> {
+    // Turn :host into the scope class, and :host(.active) into the scope class followed by .active.
+    // Anything before it stays in front, so a type selector like the p in p:host is still first
+
+    let start = find_top_level(compound, HOST_PSEUDO_CLASS)?;
+    let mut end = start + HOST_PSEUDO_CLASS.len();
+    let next_char = compound[end..].chars().next();
+    if next_char.is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        // Something else like :host-context
+        return None;
+    }
+    let mut host_arguments = "";
+    if next_char == Some('(') {
+        let close = matching_paren(compound, end);
+        host_arguments = compound[end + 1..close].trim();
+        end = close + 1;
+    }
+    Some(format!(
+        "{}{scope_class}{host_arguments}{}",
+        &compound[..start],
+        &compound[end..]
+    ))
+}
+
+fn unwrap_global(compound: &str) -> String {
+    // Replace every :global(x) in the compound with x
+    match find_top_level(compound, GLOBAL_PSEUDO_CLASS) {
+        Some(start) => {
+            let open = start + GLOBAL_PSEUDO_CLASS.len() - 1;
+            let close = matching_paren(compound, open);
+            format!(
+                "{}{}{}",
+                &compound[..start],
+                compound[open + 1..close].trim(),
+                unwrap_global(&compound[close + 1..])
+            )
+        }
+        None => compound.to_string(),
+    }
+}
+
+fn is_document_level(compound: &str) -> bool {
+    DOCUMENT_LEVEL_SELECTORS.iter().any(|selector| {
+        compound.starts_with(selector)
+            && !compound[selector.len()..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(selector: &str) -> String {
+        scope_selector(selector, "._spButton").unwrap()
+    }
+
+    #[test]
+    fn test_split_selector_list() {
+        assert_eq!(
+            split_selector_list(":is(.a, .b) p, [title=\"x, y\"], c"),
+            vec![":is(.a, .b) p", "[title=\"x, y\"]", "c"]
+        );
+    }
+
+    #[test]
+    fn test_scope_selector() {
+        assert_eq!(scope("p"), "._spButton p");
+        assert_eq!(scope("div>p  +  a ~b"), "._spButton div > p + a ~ b");
+        assert_eq!(
+            scope("a[href$=\".pdf\"] > span::before"),
+            "._spButton a[href$=\".pdf\"] > span::before"
+        );
+        assert_eq!(
+            scope("li:not(.done, :first-child) :is(b, i)"),
+            "._spButton li:not(.done, :first-child) :is(b, i)"
+        );
+        assert_eq!(scope("[data-x=\"a > b\"]"), "._spButton [data-x=\"a > b\"]");
+    }
+
+    #[test]
+    fn test_host_and_global() {
+        assert_eq!(scope(":host"), "._spButton");
+        assert_eq!(scope(":host(.active) > p"), "._spButton.active > p");
+        assert_eq!(scope(":host:hover p"), "._spButton:hover p");
+        assert_eq!(scope(":global(.dark) :host p"), ".dark ._spButton p");
+        assert_eq!(scope("div:host > p"), "div._spButton > p");
+        assert_eq!(scope("div.a:host(.active)"), "div.a._spButton.active");
+        assert_eq!(scope(":global(.dark) p"), ".dark ._spButton p");
+        assert_eq!(scope(".card :global(.icon)"), "._spButton .card .icon");
+        assert_eq!(scope(":global(h1)"), "h1");
+    }
+
+    #[test]
+    fn test_document_level_selectors() {
+        assert_eq!(scope(":root"), ":root");
+        assert_eq!(scope("body.dark .x"), "body.dark ._spButton .x");
+        assert_eq!(scope("html > body p"), "html > body ._spButton p");
+        assert_eq!(scope("bodyguard"), "._spButton bodyguard");
+        assert_eq!(scope("p html"), "._spButton p html");
+    }
+
+    #[test]
+    fn test_names_needed_in_markup() {
+        let names = |selector| names_needed_in_markup(selector, false).unwrap();
+        assert_eq!(
+            names("div.card > P.big.red:hover a[href] .x\\:y"),
+            (
+                vec!["div".to_string(), "p".to_string(), "a".to_string()],
+                vec!["card".to_string(), "big".to_string(), "red".to_string()]
+            )
+        );
+        assert_eq!(
+            names(":host(.active) :global(.dark) body li:not(.done)"),
+            (vec!["body".to_string(), "li".to_string()], vec![])
+        );
+        assert_eq!(names("html.dark *"), (vec![], vec![]));
+
+        let own_names = |selector| names_needed_in_markup(selector, true).unwrap();
+        assert_eq!(own_names(".card p"), (vec![], vec![]));
+        assert_eq!(
+            own_names(":host > p.a + a .b"),
+            (
+                vec!["p".to_string(), "a".to_string()],
+                vec!["a".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_selectors() {
+        for selector in [
+            "",
+            "> p",
+            "p >",
+            "p > > a",
+            "a[href",
+            ":is(a",
+            "a[title=\"x]",
+        ] {
+            assert!(
+                scope_selector(selector, "._spButton").is_err(),
+                "{selector} should be invalid"
+            );
+            assert!(validate_selector(selector).is_err());
+        }
+        assert!(validate_selector(":host > .a:is(b, c)").is_ok());
+    }
+}
@@ -1,74 +1,209 @@
-// Pretty basic tokeniser for CSS, to allow scoped css
-// Does not appreciate complex stuff like styles inside blocks - eg media queries
+// Tokeniser for CSS, to allow scoped css
+// At-rules are read as an AtRule token followed by either a semicolon (eg @import) or a block.
+// Depending on the at-rule, the block contains more rules (eg @media, @keyframes) or properties (eg @font-face)
+//
+// Everything is read with read_until_top_level, which skips over strings, url()s, escapes, brackets and comments,
+// so that something like content: "}" or a custom property holding a block doesn't end the rule early.
+// Comments between rules and properties become Comment tokens. Comments anywhere else (eg in a selector) are dropped
 
-// Current limitations:
-// - Does not read comments
-
-use crate::common::tokeniser_utils;
+use crate::common::tokeniser_utils::{BracketScanner, ScannedKind};
 use crate::errs;
+use crate::scoped_css::selector;
 
 #[derive(Debug)]
 pub enum CssToken {
     Comment(String),
+    AtRule { name: String, prelude: String }, // name doesn't include the @, eg media and (max-width: 600px)
     Selector(String),
     Comma,
     BlockStart,
     BlockEnd,
     PropertyName(String),
     Colon,
-    PropertyValue(String),
+    PropertyValue(String), // without any !important
+    Important,
     Semicolon,
 }
 
+// At-rules whose blocks contain rules rather than properties
+const GROUPING_AT_RULES: [&str; 5] = ["media", "supports", "container", "layer", "document"];
+const IMPORTANT: &str = "important";
+
 pub fn tokenise_css(css: &str) -> Result<Vec<CssToken>, errs::CssSyntaxError> {
     // Convert a css stream into a vec of tokens
 
+    let (tokens, chars_read) = read_rules(css, false)?;
+    if chars_read < css.len() {
+        // Only a nested read stops at a block end
+        Err(errs::CssSyntaxError::UnexpectedBlockEnd)?
+    }
+    Ok(tokens)
+}
+
+pub fn is_keyframes_at_rule(name: &str) -> bool {
+    // Also matches vendor-prefixed versions like -webkit-keyframes
+    name == "keyframes" || name.ends_with("-keyframes")
+}
+
+pub fn is_grouping_at_rule(name: &str) -> bool {
+    GROUPING_AT_RULES.contains(&name)
+}
+
+pub fn read_until_top_level(css: &str, stop_chars: &[char]) -> (String, Option<char>, usize) {
+    // Read until (but not including) one of stop_chars, ignoring any inside strings, url()s, brackets or comments.
+    // Returns the text read with comments left out, the char that was stopped at (None if went until end)
+    // and the number of bytes read
+
+    let mut result = String::new();
+    let mut after_comment = false;
+    for part in BracketScanner::new(css) {
+        match part.kind {
+            ScannedKind::Char(c) | ScannedKind::OpenBracket(c)
+                if part.depth == 0 && stop_chars.contains(&c) =>
+            {
+                return (result, Some(c), part.idx);
+            }
+            ScannedKind::Comment => {
+                // Don't leave a double space where the comment was
+                after_comment = result.ends_with(char::is_whitespace);
+                continue;
+            }
+            ScannedKind::Char(c) if after_comment && c.is_whitespace() => continue,
+            _ => (),
+        }
+        after_comment = false;
+        result += &css[part.idx..part.idx + part.len];
+    }
+    (result, None, css.len())
+}
+
+fn skip_whitespace_and_comments(
+    css: &str,
+    tokens: &mut Vec<CssToken>,
+) -> Result<usize, errs::CssSyntaxError> {
+    // Skip to the next thing that isn't whitespace, adding a Comment token for each comment on the way.
+    // Returns the number of bytes skipped
+
+    let mut idx = 0;
+    loop {
+        idx += css[idx..].len() - css[idx..].trim_start().len();
+        if !css[idx..].starts_with("/*") {
+            return Ok(idx);
+        }
+        let comment_length = css[idx + 2..]
+            .find("*/")
+            .ok_or(errs::CssSyntaxError::UnexpectedEndOfFile)?;
+        tokens.push(CssToken::Comment(
+            css[idx + 2..idx + 2 + comment_length].trim().to_string(),
+        ));
+        idx += comment_length + 4;
+    }
+}
+
+fn read_rules(css: &str, nested: bool) -> Result<(Vec<CssToken>, usize), errs::CssSyntaxError> {
+    // Read rules and at-rules until the end of the css, or until the end of the block if nested.
+    // When nested, returned tokens include the block close
+
     let mut tokens = vec![];
     let mut idx: usize = 0;
-    while idx < css.len() {
-        // Read selectors until we get to the start of the properties
-        let (selector_tokens, chars_read) = read_selectors(&css[idx..])?;
-        idx += chars_read;
-        tokens.extend(selector_tokens);
+    loop {
+        idx += skip_whitespace_and_comments(&css[idx..], &mut tokens)?;
+        match css[idx..].chars().next() {
+            None => {
+                if nested {
+                    Err(errs::CssSyntaxError::UnexpectedEndOfFile)?
+                }
+                break;
+            }
+            Some('}') => {
+                if nested {
+                    tokens.push(CssToken::BlockEnd);
+                    idx += 1;
+                }
+                break;
+            }
+            Some('@') => {
+                let (at_rule_tokens, chars_read) = read_at_rule(&css[idx..])?;
+                idx += chars_read;
+                tokens.extend(at_rule_tokens);
+            }
+            Some(_) => {
+                // Read selectors until we get to the start of the properties
+                let (selector_tokens, chars_read) = read_selectors(&css[idx..])?;
+                if selector_tokens.len() == 1 {
+                    Err(errs::CssSyntaxError::InvalidSelector {
+                        selector: "".to_string(),
+                        reason: "it is empty",
+                    })?
+                }
+                idx += chars_read;
+                tokens.extend(selector_tokens);
 
-        // Read properties until we get to the end of those
-        let (property_tokens, chars_read) = read_all_css_properties(&css[idx..])?;
-        idx += chars_read;
-        tokens.extend(property_tokens);
+                // Read properties until we get to the end of those
+                let (property_tokens, chars_read) = read_all_css_properties(&css[idx..])?;
+                idx += chars_read;
+                tokens.extend(property_tokens);
+            }
+        }
     }
+    Ok((tokens, idx))
+}
 
-    Ok(tokens)
+fn read_at_rule(css: &str) -> Result<(Vec<CssToken>, usize), errs::CssSyntaxError> {
+    // Read an at-rule, including its block if it has one.
+    // Presumes css starts with the @
+
+    let name: String = css[1..]
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .collect();
+    let mut idx = 1 + name.len();
+    let (prelude, found_char, chars_read) = read_until_top_level(&css[idx..], &[';', '{']);
+    let found_char = found_char.ok_or(errs::CssSyntaxError::UnexpectedEndOfFile)?;
+    idx += chars_read + 1;
+
+    let mut tokens = vec![CssToken::AtRule {
+        name: name.clone(),
+        prelude: prelude.trim().to_string(),
+    }];
+    if found_char == ';' {
+        tokens.push(CssToken::Semicolon);
+        return Ok((tokens, idx));
+    }
+
+    tokens.push(CssToken::BlockStart);
+    let (block_tokens, chars_read) = if is_grouping_at_rule(&name) || is_keyframes_at_rule(&name) {
+        read_rules(&css[idx..], true)?
+    } else {
+        read_all_css_properties(&css[idx..])?
+    };
+    tokens.extend(block_tokens);
+    Ok((tokens, idx + chars_read))
 }
 
 fn read_selectors(css: &str) -> Result<(Vec<CssToken>, usize), errs::CssSyntaxError> {
-    // Read selectors until block open. Returned tokens include the block open
+    // Read selectors until block open. Returned tokens include the block open.
+    // Commas and braces inside attribute selectors, :is() etc are part of the selector
+
+    let (selector_list, found_char, chars_read) = read_until_top_level(css, &['{']);
+    if found_char.is_none() {
+        Err(errs::CssSyntaxError::UnexpectedEndOfFile)?
+    }
 
     let mut result = vec![];
-    let mut idx: usize = 0;
-    while idx < css.len() {
-        // Read selector and process found char
-        let (crnt_selector, found_char_opt) =
-            tokeniser_utils::read_until_any_of(&css[idx..], &vec![',', '{']);
-        let found_char = found_char_opt.ok_or(errs::CssSyntaxError::UnexpectedEndOfFile)?;
-
-        // Add selector to tokens
-        let cleaned_selector = crnt_selector.trim();
-        if cleaned_selector.len() > 0 {
-            result.push(CssToken::Selector(cleaned_selector.to_string()));
-        }
-        idx += crnt_selector.len();
-
-        // if is comma: add token, +1 idx
-        if found_char == ',' {
-            result.push(CssToken::Comma);
-            idx += 1;
-        } else {
-            result.push(CssToken::BlockStart);
-            idx += 1;
-            break;
+    if !selector_list.trim().is_empty() {
+        for (index, selector) in selector::split_selector_list(&selector_list)
+            .into_iter()
+            .enumerate()
+        {
+            if index > 0 {
+                result.push(CssToken::Comma);
+            }
+            result.push(CssToken::Selector(selector));
         }
     }
-    Ok((result, idx))
+    result.push(CssToken::BlockStart);
+    Ok((result, chars_read + 1))
 }
 
 fn read_all_css_properties(css: &str) -> Result<(Vec<CssToken>, usize), errs::CssSyntaxError> {
@@ -77,50 +212,76 @@ fn read_all_css_properties(css: &str) -> Result<(Vec<CssToken>, usize), errs::Cs
 
     let mut result = vec![];
     let mut idx: usize = 0;
-    while idx < css.len() {
-        // Skip whitespace so we can see if we're at block close
-        idx += tokeniser_utils::read_whitespace(&css[idx..]).len();
-        if tokeniser_utils::get_char_unwrap(css, idx) == '}' {
-            result.push(CssToken::BlockEnd);
-            idx += 1;
-            break;
+    loop {
+        // Skip whitespace and stray semicolons so we can see if we're at block close
+        idx += skip_whitespace_and_comments(&css[idx..], &mut result)?;
+        match css[idx..].chars().next() {
+            None => Err(errs::CssSyntaxError::UnexpectedEndOfFile)?,
+            Some('}') => {
+                result.push(CssToken::BlockEnd);
+                idx += 1;
+                break;
+            }
+            Some(';') => idx += 1,
+            Some(_) => {
+                // If we're not then read a property.
+                let (new_tokens, chars_read) = read_css_property(&css[idx..])?;
+                idx += chars_read;
+                result.extend(new_tokens);
+            }
         }
-
-        // If we're not then read a property.
-        let (new_tokens, chars_read) = read_css_property(&css[idx..])?;
-        idx += chars_read;
-        result.extend(new_tokens);
     }
     Ok((result, idx))
 }
 
 fn read_css_property(css: &str) -> Result<(Vec<CssToken>, usize), errs::CssSyntaxError> {
-    let mut chars_read: usize = 0;
     let mut result = vec![];
 
     // Read up until the colon separating name from value
-    let (property_name, found_colon) = tokeniser_utils::read_until_char(css, ':');
-    if !found_colon {
-        Err(errs::CssSyntaxError::UnexpectedEndOfFile)?
+    let (property_name, found_char, mut chars_read) = read_until_top_level(css, &[':', ';', '}']);
+    match found_char {
+        Some(':') => (),
+        Some(_) => Err(errs::CssSyntaxError::MissingColon {
+            property_name: property_name.trim().to_string(),
+        })?,
+        None => Err(errs::CssSyntaxError::UnexpectedEndOfFile)?,
     }
-    chars_read += property_name.len() + 1; // +1 to account for the colon token that we add just below
+    chars_read += 1; // +1 to account for the colon token that we add just below
     result.push(CssToken::PropertyName(property_name.trim().to_string()));
     result.push(CssToken::Colon);
 
-    // Read up until the semicolon at end of line
-    let (property_value, found_semicolon) =
-        tokeniser_utils::read_until_char(&css[chars_read..], ';');
-    if !found_semicolon {
-        Err(errs::CssSyntaxError::UnexpectedEndOfFile)?
+    // Read up until the semicolon at end of line. The last property in a block doesn't need one
+    let (property_value, found_char, value_length) =
+        read_until_top_level(&css[chars_read..], &[';', '}']);
+    chars_read += value_length;
+    match found_char {
+        Some(';') => chars_read += 1, // +1 to account for semicolon token
+        Some(_) => (),                // leave the } for read_all_css_properties
+        None => Err(errs::CssSyntaxError::UnexpectedEndOfFile)?,
     }
 
-    chars_read += property_value.len() + 1; // +1 to account for semicolon token
-    result.push(CssToken::PropertyValue(property_value.trim().to_string()));
+    let (property_value, is_important) = split_important(property_value.trim());
+    result.push(CssToken::PropertyValue(property_value.to_string()));
+    if is_important {
+        result.push(CssToken::Important);
+    }
     result.push(CssToken::Semicolon);
 
     Ok((result, chars_read))
 }
 
+fn split_important(property_value: &str) -> (&str, bool) {
+    // Take the !important off the end of a value. There can be whitespace after the !
+
+    if let Some(bang_idx) = property_value.rfind('!') {
+        let after_bang = property_value[bang_idx + 1..].trim_start();
+        if after_bang.eq_ignore_ascii_case(IMPORTANT) {
+            return (property_value[..bang_idx].trim_end(), true);
+        }
+    }
+    (property_value, false)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -152,6 +313,42 @@ mod tests {
         assert_eq!(len, 31);
     }
 
+    #[test]
+    fn test_tokenise_at_rules() {
+        let tokens = tokenise_css(
+            "@import url(a.css); @media (max-width: 600px) { p { color: red; } } @font-face { font-family: x; }",
+        )
+        .unwrap();
+        let expected = "[AtRule { name: \"import\", prelude: \"url(a.css)\" }, Semicolon, \
+            AtRule { name: \"media\", prelude: \"(max-width: 600px)\" }, BlockStart, \
+            Selector(\"p\"), BlockStart, PropertyName(\"color\"), Colon, PropertyValue(\"red\"), Semicolon, BlockEnd, BlockEnd, \
+            AtRule { name: \"font-face\", prelude: \"\" }, BlockStart, PropertyName(\"font-family\"), Colon, PropertyValue(\"x\"), Semicolon, BlockEnd]";
+        assert_eq!(format!("{tokens:?}"), expected);
+
+        let tokens =
+            tokenise_css("@-webkit-keyframes spin { from { top: 0; } 50%, to { top: 1px; } }")
+                .unwrap();
+        assert_eq!(tokens.len(), 19);
+
+        assert!(matches!(
+            tokenise_css("@media print { p { color: red; }"),
+            Err(errs::CssSyntaxError::UnexpectedEndOfFile)
+        ));
+        assert!(matches!(
+            tokenise_css("p { color: red; } }"),
+            Err(errs::CssSyntaxError::UnexpectedBlockEnd)
+        ));
+    }
+
+    #[test]
+    fn test_comments_and_important() {
+        let tokens = tokenise_css("/* a { */ p { color: red ! important /* b; */ }").unwrap();
+        assert_eq!(
+            format!("{tokens:?}"),
+            "[Comment(\"a {\"), Selector(\"p\"), BlockStart, PropertyName(\"color\"), Colon, PropertyValue(\"red\"), Important, Semicolon, BlockEnd]"
+        );
+    }
+
     #[test]
     fn test_read_css_property() {
         let (tokens, len) = read_css_property("color: red; ").unwrap();
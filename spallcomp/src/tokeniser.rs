@@ -4,7 +4,6 @@
 // A lot of the functions in this file return a tuple of a Vec<Token> and usize -
 // the usize is how many characters were consumed by that function so we can update the counter in the parent function
 
-use std::collections::HashMap;
 use std::fmt;
 
 use derive_more::Display;
@@ -13,11 +12,38 @@ use crate::common::tokeniser_utils;
 use crate::tag_attribute::TagAttribute;
 use crate::tag_type::TagType;
 
+// HTML elements that can't have any content, so are never closed - <br> is the same as <br />
+pub const VOID_ELEMENTS: [&str; 13] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+// Elements whose content is read as it is until their end tag, rather than as markup
+pub const RAW_TEXT_ELEMENTS: [&str; 4] = ["script", "style", "textarea", "code"];
+const COMMENT_START: &str = "<!--";
+const COMMENT_END: &str = "-->";
+// Keywords that a regex literal can come after, where a / would otherwise be division
+const REGEX_PRECEDING_KEYWORDS: [&str; 13] = [
+    "return",
+    "typeof",
+    "instanceof",
+    "in",
+    "of",
+    "new",
+    "delete",
+    "void",
+    "throw",
+    "case",
+    "do",
+    "else",
+    "yield",
+];
+
 // Root token class
 pub enum Token {
     Tag(TagToken),
     Content(ContentToken),
     InlineJavascript(InlineJavascriptToken),
+    Comment(CommentToken),
 }
 
 // Represents a single html tag - <opening>, </closing> or <standalone />
@@ -50,6 +76,7 @@ impl fmt::Display for TagToken {
 #[display(fmt = "[Content: {value}]")]
 pub struct ContentToken {
     pub value: String,
+    pub offset: usize, // byte index of the content in the markup, for error line numbers
 }
 // Represents a chunk of javascript found in the markup
 #[derive(Display)]
@@ -58,44 +85,106 @@ pub struct InlineJavascriptToken {
     pub value: String,
 }
 
+// Represents a <!-- comment -->, value is the text between the <!-- and -->
+#[derive(Display)]
+#[display(fmt = "[Comment: {value}]")]
+pub struct CommentToken {
+    pub value: String,
+}
+
 pub fn read_element(markup: &str) -> Vec<Token> {
     // Entry point to tokenisation, reads a string into a vec of tokens
 
     let mut remaining = markup.to_string();
-    let mut inside_script_tag = false;
+    let mut raw_text_tag: Option<String> = None; // set when the last token opened a raw text element
+    let mut pre_depth = 0;
     let mut result = vec![];
     while remaining.len() > 0 {
+        // Read the content of a raw text element. This comes first since it can contain ~ and <
+        if let Some(tag_name) = raw_text_tag.take() {
+            if !remaining.starts_with(&format!("</{tag_name}")) {
+                let content = match tag_name.as_str() {
+                    "script" => read_javascript(&remaining),
+                    "style" => read_css(&remaining),
+                    _ => read_raw_text(&remaining, &tag_name),
+                };
+                let offset = markup.len() - remaining.len();
+                remaining.drain(..content.len());
+                result.push(Token::Content(ContentToken {
+                    value: content,
+                    offset,
+                }));
+            }
+        }
+        // Read comment. Everything in it is left alone, so commented out markup isn't read as tags
+        else if remaining.starts_with(COMMENT_START) {
+            let (comment, size) = read_comment(&remaining);
+            remaining.drain(..size);
+            result.push(Token::Comment(comment));
+        }
         // Read tag
-        if remaining.chars().next().unwrap() == '<' {
+        else if remaining.chars().next().unwrap() == '<' {
             let (tag, chars) = read_html_tag(&remaining);
             remaining.drain(..chars);
-            inside_script_tag = tag.name == "<script>" && tag.tag_type == TagType::Start;
+            if tag.tag_type == TagType::Start && RAW_TEXT_ELEMENTS.contains(&tag.name.as_str()) {
+                raw_text_tag = Some(tag.name.clone());
+            }
+            if tag.name == "pre" {
+                match tag.tag_type {
+                    TagType::Start => pre_depth += 1,
+                    TagType::End if pre_depth > 0 => pre_depth -= 1,
+                    _ => (),
+                }
+            }
             result.push(Token::Tag(tag));
         }
-        // Read inline javascript
-        else if remaining.chars().next().unwrap() == '~' {
+        // Read inline javascript. A ~~ is an escaped ~, so is text
+        else if remaining.starts_with('~') && !remaining.starts_with("~~") {
             let (inline_js, size) = read_inline_javascript(&remaining);
             remaining.drain(..size);
             result.push(Token::InlineJavascript(inline_js));
         }
-        // Read script tag content
-        else if inside_script_tag {
-            let js = read_javascript(&remaining);
-            remaining.drain(..js.len());
-            result.push(Token::Content(ContentToken { value: js }));
-        }
-        // Read normal tag content
+        // Read normal tag content. Outside of a <pre>, whitespace between tags is dropped if it goes over lines
+        // (so indentation doesn't become text), otherwise it is kept as one space like in <b>a</b> <i>b</i>
         else {
-            let content = read_tag_content(&remaining);
-            remaining.drain(..content.len());
-            if !content.trim().is_empty() {
-                result.push(Token::Content(ContentToken { value: content }));
+            let offset = markup.len() - remaining.len();
+            let (mut content, size) = read_tag_content(&remaining);
+            remaining.drain(..size);
+            if content.trim().is_empty() && pre_depth == 0 {
+                content = if content.contains('\n') {
+                    "".to_string()
+                } else {
+                    " ".to_string()
+                };
+            }
+            if !content.is_empty() {
+                result.push(Token::Content(ContentToken {
+                    value: content,
+                    offset,
+                }));
             }
         }
     }
     result
 }
 
+fn read_comment(markup: &str) -> (CommentToken, usize) {
+    // Read a comment, expects markup to begin with <!--.
+    // A comment that is never closed goes until the end of the file, like in HTML
+
+    let inner = &markup[COMMENT_START.len()..];
+    let (value, length) = match inner.find(COMMENT_END) {
+        Some(end) => (&inner[..end], COMMENT_START.len() + end + COMMENT_END.len()),
+        None => (inner, markup.len()),
+    };
+    (
+        CommentToken {
+            value: value.to_string(),
+        },
+        length,
+    )
+}
+
 fn read_html_tag(markup: &str) -> (TagToken, usize) {
     // Read an open/close/standalone tag. Second return value is tag length
 
@@ -108,7 +197,7 @@ fn read_html_tag(markup: &str) -> (TagToken, usize) {
     while idx < markup.len() {
         let char = tokeniser_utils::get_char_unwrap(markup, idx);
         match char {
-            ' ' => {
+            c if c.is_whitespace() => {
                 idx += 1;
                 break;
             }
@@ -132,6 +221,9 @@ fn read_html_tag(markup: &str) -> (TagToken, usize) {
     }
 
     tag_name = tag_name.trim().to_string();
+    if tag_type == TagType::Start && VOID_ELEMENTS.contains(&tag_name.as_str()) {
+        tag_type = TagType::Standalone;
+    }
 
     // Read tag attributes
     let mut tag_attributes = vec![];
@@ -175,7 +267,10 @@ fn read_tag_attributes(data: &str) -> (Vec<TagAttribute>, usize) {
         // skip forward if there are any spaces
         idx += tokeniser_utils::read_whitespace(&data[idx..]).len();
 
-        // check if we are at end of tag
+        // check if we are at end of tag (or of the file, if the tag was never finished)
+        if idx >= data.len() {
+            break;
+        }
         let char = tokeniser_utils::get_char_unwrap(data, idx);
         if char == '/' || char == '>' {
             break;
@@ -203,7 +298,7 @@ fn read_tag_attribute(data: &str) -> (TagAttribute, usize) {
     // Read all of attribute name
     while idx < data.len() {
         let char = tokeniser_utils::get_char_unwrap(&data, idx);
-        if char == ' ' || char == '=' {
+        if char.is_whitespace() || char == '=' || char == '>' || char == '/' {
             break;
         } else {
             attribute_name.push(char);
@@ -211,21 +306,41 @@ fn read_tag_attribute(data: &str) -> (TagAttribute, usize) {
         idx += 1;
     }
 
-    idx += tokeniser_utils::read_whitespace(&data[idx..]).len();
+    // Attributes without a value (like <style global> or <input disabled>) are left with an empty one
+    let whitespace_len = tokeniser_utils::read_whitespace(&data[idx..]).len();
+    if !data[idx + whitespace_len..].starts_with('=') {
+        return (
+            TagAttribute {
+                name: attribute_name,
+                value: "".to_string(),
+                is_dynamic,
+            },
+            idx,
+        );
+    }
+    idx += whitespace_len;
     // if idx > len: err(you messed up)
     idx += 1; // jump over equals sign
               // if idx > len: err(you messed up)
     idx += tokeniser_utils::read_whitespace(&data[idx..]).len();
 
-    // Read attribute value
-    let mut attribute_value = tokeniser_utils::read_string(
-        tokeniser_utils::get_char_unwrap(data, idx),
-        '\\',
-        &data[idx..],
-    );
-    idx += attribute_value.len();
-    attribute_value.pop();
-    attribute_value.remove(0);
+    // Read attribute value, which is either quoted or (like in HTML) goes until the next space or end of the tag
+    let attribute_value = match data[idx..].chars().next() {
+        Some(quote_char) if quote_char == '"' || quote_char == '\'' => {
+            let mut quoted_value = tokeniser_utils::read_string(quote_char, '\\', &data[idx..]);
+            idx += quoted_value.len();
+            if quoted_value.len() > 1 && quoted_value.ends_with(quote_char) {
+                quoted_value.pop();
+            }
+            quoted_value.remove(0);
+            quoted_value
+        }
+        _ => {
+            let value = read_unquoted_attribute_value(&data[idx..]);
+            idx += value.len();
+            value
+        }
+    };
 
     // Prepare data for returning
     (
@@ -238,11 +353,13 @@ fn read_tag_attribute(data: &str) -> (TagAttribute, usize) {
     )
 }
 
-fn read_tag_content(markup: &str) -> String {
-    // Read the content (inner text) of a tag.
+fn read_unquoted_attribute_value(data: &str) -> String {
+    // Read an unquoted attribute value like the 2 in colspan=2.
+    // HTML would keep a / at the end as part of the value, but here it is taken as the end of a standalone tag
+
     let mut result = "".to_string();
-    for char in markup.chars() {
-        if char == '<' || char == '~' {
+    for (idx, char) in data.char_indices() {
+        if char.is_whitespace() || char == '>' || data[idx..].starts_with("/>") {
             break;
         }
         result.push(char);
@@ -250,53 +367,132 @@ fn read_tag_content(markup: &str) -> String {
     result
 }
 
+fn read_tag_content(markup: &str) -> (String, usize) {
+    // Read the content (inner text) of a tag, turning each ~~ into a ~. Second return value is the length read
+
+    let mut result = "".to_string();
+    let mut idx = 0;
+    while let Some(char) = markup[idx..].chars().next() {
+        if markup[idx..].starts_with("~~") {
+            result.push('~');
+            idx += 2;
+            continue;
+        }
+        if char == '<' || char == '~' {
+            break;
+        }
+        result.push(char);
+        idx += char.len_utf8();
+    }
+    (result, idx)
+}
+
 fn read_javascript(data: &str) -> String {
     // Read some JavaScript until it is ended by a </script> tag.
+    // Strings, regexes and comments are skipped over, and a </script> inside brackets doesn't count.
     // Returned value does not include the ending script tag
 
-    // todo: when I have internet get a library to make this a const
-    let brackets: HashMap<char, bool> = [
-        ('(', true),
-        (')', false),
-        ('[', true),
-        (']', false),
-        ('{', true),
-        ('}', false),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-    let quotes = vec!['\'', '`', '"'];
+    let mut bracket_depth = 0;
+    let mut idx = 0;
+    while idx < data.len() {
+        if bracket_depth == 0 && data[idx..].starts_with("</script>") {
+            break;
+        }
+        let char = data[idx..].chars().next().unwrap();
+        match char {
+            '(' | '[' | '{' => bracket_depth += 1,
+            ')' | ']' | '}' => bracket_depth = (bracket_depth - 1).max(0),
+            _ => (),
+        }
+        if char == '"' || char == '\'' || char == '`' {
+            idx += tokeniser_utils::read_string(char, '\\', &data[idx..]).len();
+        } else if data[idx..].starts_with("//") {
+            idx += data[idx..].find('\n').unwrap_or(data.len() - idx);
+        } else if data[idx..].starts_with("/*") {
+            idx += data[idx..]
+                .find("*/")
+                .map(|end| end + 2)
+                .unwrap_or(data.len() - idx);
+        } else if char == '/' && is_regex_start(&data[..idx]) {
+            idx += regex_length(&data[idx..]);
+        } else {
+            idx += char.len_utf8();
+        }
+    }
+    data[..idx].to_string()
+}
+
+fn is_regex_start(javascript_before: &str) -> bool {
+    // Whether a / starts a regex literal rather than being division, going by what comes before it.
+    // A regex can only go where a value starts, eg after an operator or a keyword like return, but not after a value
+
+    let before = javascript_before.trim_end();
+    match before.chars().next_back() {
+        None => true,
+        Some(c) if c.is_alphanumeric() || c == '_' || c == '$' => {
+            REGEX_PRECEDING_KEYWORDS.iter().any(|keyword| {
+                before.strip_suffix(keyword).is_some_and(|rest| {
+                    !rest
+                        .chars()
+                        .next_back()
+                        .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '$' || c == '.')
+                })
+            })
+        }
+        Some(c) => "(,=:[!&|?{};+-*%<>~^".contains(c),
+    }
+}
+
+fn regex_length(data: &str) -> usize {
+    // Length of the regex literal that data starts with, not counting its flags (which are read like any other
+    // letters). A / inside a [...] class doesn't end it, and neither does an escaped one
 
-    let mut result = "".to_string();
-    let mut bracket_stack = vec![];
-    let mut char_idx = 0;
-    while char_idx < data.len() {
-        let char = data.chars().nth(char_idx).unwrap();
-        match brackets.get(&char) {
-            Some(is_opening) => {
-                if *is_opening {
-                    bracket_stack.push(char);
-                } else {
-                    bracket_stack.pop();
-                }
+    let mut in_class = false;
+    let mut chars = data.char_indices().skip(1);
+    while let Some((idx, char)) = chars.next() {
+        match char {
+            '\\' => {
+                chars.next();
             }
-            None => (),
+            '[' => in_class = true,
+            ']' => in_class = false,
+            '/' if !in_class => return idx + 1,
+            // A regex can't go over lines, so this wasn't one
+            '\n' => return idx,
+            _ => (),
         }
+    }
+    data.len()
+}
+
+fn read_raw_text(data: &str, tag_name: &str) -> String {
+    // Read the content of a raw text element like <code> until its end tag, without reading anything in it as markup.
+    // Returned value does not include the end tag
 
-        if quotes.contains(&char) {
-            let string = tokeniser_utils::read_string(char, '\\', &data[char_idx..]);
-            result += &string;
-            char_idx += string.len();
+    let end = data.find(&format!("</{tag_name}")).unwrap_or(data.len());
+    data[..end].to_string()
+}
+
+fn read_css(data: &str) -> String {
+    // Read the CSS in a style tag until it is ended by a </style> tag.
+    // Strings and comments are skipped over so that they can contain </style>.
+    // Returned value does not include the ending style tag
+
+    let mut idx = 0;
+    while idx < data.len() && !data[idx..].starts_with("</style") {
+        let char = data[idx..].chars().next().unwrap();
+        if char == '"' || char == '\'' {
+            idx += tokeniser_utils::read_string(char, '\\', &data[idx..]).len();
+        } else if data[idx..].starts_with("/*") {
+            idx += data[idx..]
+                .find("*/")
+                .map(|end| end + 2)
+                .unwrap_or(data.len() - idx);
         } else {
-            result.push(char);
-            char_idx += 1;
-        }
-        if bracket_stack.len() == 0 && data[char_idx..].starts_with("</script>") {
-            break;
+            idx += char.len_utf8();
         }
     }
-    result
+    data[..idx].to_string()
 }
 
 fn read_inline_javascript(markup: &str) -> (InlineJavascriptToken, usize) {
@@ -328,6 +524,18 @@ fn read_inline_javascript(markup: &str) -> (InlineJavascriptToken, usize) {
 mod tests {
     use super::*;
 
+    fn token_strings(tokens: &[Token]) -> Vec<String> {
+        tokens
+            .iter()
+            .map(|token| match token {
+                Token::Tag(tag) => tag.to_string(),
+                Token::Content(content) => content.to_string(),
+                Token::InlineJavascript(js) => js.to_string(),
+                Token::Comment(comment) => comment.to_string(),
+            })
+            .collect()
+    }
+
     #[test]
     fn test_read_html_tag() {
         let mut data = read_html_tag("<h1>");
@@ -374,9 +582,73 @@ mod tests {
         assert_eq!(data.1, 21);
     }
 
+    #[test]
+    fn test_void_elements() {
+        let (tag, length) = read_html_tag("<br>");
+        assert_eq!(tag.tag_type, TagType::Standalone);
+        assert_eq!(length, 4);
+
+        let (tag, _) = read_html_tag("<img src='a.png'>");
+        assert_eq!(tag.tag_type, TagType::Standalone);
+        assert_eq!(tag.attributes[0].value, "a.png");
+
+        let (tag, _) = read_html_tag("</br>");
+        assert_eq!(tag.tag_type, TagType::End);
+
+        // Only HTML elements can be void
+        let (tag, _) = read_html_tag("<Input>");
+        assert_eq!(tag.tag_type, TagType::Start);
+    }
+
+    #[test]
+    fn test_unquoted_attribute_values() {
+        let (tag, length) = read_html_tag("<td colspan=2 class=wide>");
+        assert_eq!(tag.attributes[0].name, "colspan");
+        assert_eq!(tag.attributes[0].value, "2");
+        assert_eq!(tag.attributes[1].value, "wide");
+        assert_eq!(length, 25);
+
+        let (tag, _) = read_html_tag("<Counter start=5/>");
+        assert_eq!(tag.tag_type, TagType::Standalone);
+        assert_eq!(tag.attributes[0].value, "5");
+
+        let (tag, _) = read_html_tag("<input\n    disabled\n    type = text\n>");
+        assert_eq!(tag.name, "input");
+        assert_eq!(tag.attributes[0].name, "disabled");
+        assert_eq!(tag.attributes[1].name, "type");
+        assert_eq!(tag.attributes[1].value, "text");
+
+        // Unfinished tags at the end of the file don't panic
+        let (tag, _) = read_html_tag("<input disabled ");
+        assert_eq!(tag.attributes[0].name, "disabled");
+        let (tag, _) = read_html_tag("<td colspan=");
+        assert_eq!(tag.attributes[0].value, "");
+    }
+
     #[test]
     fn test_read_tag_content() {
-        assert_eq!(read_tag_content("Hello world</h1>"), "Hello world");
+        assert_eq!(
+            read_tag_content("Hello world</h1>"),
+            ("Hello world".to_string(), 11)
+        );
+        assert_eq!(
+            read_tag_content("~~5 minutes ~~~x~"),
+            ("~5 minutes ~".to_string(), 14)
+        );
+    }
+
+    #[test]
+    fn test_escaped_tildes() {
+        let tokens = read_element("<p>~~5 minutes</p>~x~");
+        assert_eq!(
+            token_strings(&tokens),
+            vec![
+                "[Start p tag]",
+                "[Content: ~5 minutes]",
+                "[End p tag]",
+                "[Inline javascript: x]",
+            ]
+        );
     }
 
     #[test]
@@ -388,6 +660,115 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_read_javascript_skips_comments() {
+        assert_eq!(
+            read_javascript("// don't stop\nf() { return 1 < 2; } /* </script> */</script>"),
+            "// don't stop\nf() { return 1 < 2; } /* </script> */"
+        );
+        assert_eq!(
+            read_javascript("name = \"Zoë\";</script>"),
+            "name = \"Zoë\";"
+        );
+    }
+
+    #[test]
+    fn test_read_javascript_skips_regexes() {
+        assert_eq!(
+            read_javascript("f(x) { return /\"/.test(x); }</script>"),
+            "f(x) { return /\"/.test(x); }"
+        );
+        assert_eq!(
+            read_javascript("f(x) { x.replace(/[/']\\//g, ''); }</script>"),
+            "f(x) { x.replace(/[/']\\//g, ''); }"
+        );
+        // division isn't a regex, so the quote after it is a string
+        assert_eq!(
+            read_javascript("f(a, b) { return a / b + '/</script>'; }</script>"),
+            "f(a, b) { return a / b + '/</script>'; }"
+        );
+    }
+
+    #[test]
+    fn test_raw_text_elements() {
+        let tokens = read_element(
+            "<script>f() { return a<b; }</script><code>~x~ <b> &amp;</code><textarea>  a\n  b</textarea><pre>\n  <b>x</b>  <i>y</i></pre>",
+        );
+        assert_eq!(
+            token_strings(&tokens),
+            vec![
+                "[Start script tag]",
+                "[Content: f() { return a<b; }]",
+                "[End script tag]",
+                "[Start code tag]",
+                "[Content: ~x~ <b> &amp;]",
+                "[End code tag]",
+                "[Start textarea tag]",
+                "[Content:   a\n  b]",
+                "[End textarea tag]",
+                "[Start pre tag]",
+                "[Content: \n  ]",
+                "[Start b tag]",
+                "[Content: x]",
+                "[End b tag]",
+                "[Content:   ]",
+                "[Start i tag]",
+                "[Content: y]",
+                "[End i tag]",
+                "[End pre tag]",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_css() {
+        assert_eq!(
+            read_css("p ~ a > b { color: red; }</style>"),
+            "p ~ a > b { color: red; }"
+        );
+        assert_eq!(
+            read_css("p::before { content: \"</style>\"; } /* </style> */</style>"),
+            "p::before { content: \"</style>\"; } /* </style> */"
+        );
+    }
+
+    #[test]
+    fn test_attribute_without_value() {
+        let (tag, _) = read_html_tag("<style global>");
+        assert_eq!(tag.name, "style");
+        assert_eq!(tag.attributes[0].name, "global");
+        assert_eq!(tag.attributes[0].value, "");
+
+        let (tag, _) = read_html_tag("<input disabled type='text' />");
+        assert_eq!(tag.tag_type, TagType::Standalone);
+        assert_eq!(tag.attributes[0].name, "disabled");
+        assert_eq!(tag.attributes[1].value, "text");
+    }
+
+    #[test]
+    fn test_read_comment() {
+        let (comment, length) = read_comment("<!-- <p>~x~</p> -->after");
+        assert_eq!(comment.value, " <p>~x~</p> ");
+        assert_eq!(length, 19);
+
+        let (comment, length) = read_comment("<!-- never closed <p>");
+        assert_eq!(comment.value, " never closed <p>");
+        assert_eq!(length, 21);
+
+        let tokens = read_element("<div><!-- <span>old</span> --><p>Hi</p></div>");
+        assert_eq!(
+            token_strings(&tokens),
+            vec![
+                "[Start div tag]",
+                "[Comment:  <span>old</span> ]",
+                "[Start p tag]",
+                "[Content: Hi]",
+                "[End p tag]",
+                "[End div tag]"
+            ]
+        );
+    }
+
     #[test]
     fn test_read_inline_javascript() {
         let mut data = read_inline_javascript("~if (x == 5) {\n");
@@ -14,6 +14,7 @@ use crate::server_options::ServerOptions;
 const SPA_FILE_NAME: &'static str = "index.html";
 const STATIC_DIR_NAME: &'static str = "static";
 const SCRIPT_DIR_NAME: &'static str = "scripts";
+const CSS_DIR_NAME: &'static str = "css";
 
 enum RequestedItem<'a> {
     SPA,
@@ -52,7 +53,7 @@ fn determine_requested_item(request_path: &Path) -> RequestedItem {
                 return RequestedItem::Invalid;
             }
             Component::Normal(x) => {
-                if x == STATIC_DIR_NAME || x == SCRIPT_DIR_NAME {
+                if x == STATIC_DIR_NAME || x == SCRIPT_DIR_NAME || x == CSS_DIR_NAME {
                     RequestedItem::StaticFile(request_path)
                 } else {
                     RequestedItem::SPA
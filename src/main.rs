@@ -45,7 +45,9 @@ fn main() {
     args.insert(0, format!("spall {subcommand:?}"));
 
     match subcommand {
-        Command::build => spallcomp::compile_project(&args),
+        Command::build => {
+            spallcomp::compile_project(&args);
+        }
         Command::serve => spallserve::serve_project(&args),
         Command::run => spallrun::run_project(&args),
         Command::init => spallinit::initialize_project(),